@@ -0,0 +1,7 @@
+Reading past the end of the input leaves 0 in the cell
+So this prints only the newline and not the exclamation mark
+
+++++ ++++
+[>++++<-]>+
+<,[>.<[-]]
+++++++++++.
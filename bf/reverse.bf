@@ -0,0 +1,5 @@
+Prints its input backwards
+The first cell stays empty so that the printing loop knows where to stop
+
+>,[>,]
+<[.<]
@@ -0,0 +1,21 @@
+#![no_main]
+#[macro_use]
+extern crate libfuzzer_sys;
+extern crate bfcrs;
+
+use bfcrs::fuzz::fuzz_seed;
+
+// libFuzzer picks the seeds, the generator turns them into balanced programs.
+fuzz_target!(|data: &[u8]| {
+  let seed = data
+    .iter()
+    .take(8)
+    .fold(0u64, |seed, &byte| seed << 8 | byte as u64);
+
+  if let Some(failure) = fuzz_seed(seed) {
+    panic!(
+      "{}\nProgram: {}\nInput: {:?}",
+      failure.message, failure.source, failure.input
+    );
+  }
+});
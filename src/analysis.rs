@@ -0,0 +1,153 @@
+use types::ProgramToken;
+use types::ProgramToken::*;
+
+// The number of cells of the fixed tape, and of the tape every backend allocates.
+pub const TAPE_CELLS: usize = 65536;
+
+// A range of cells relative to where the pointer starts. None means there's no bound on that side.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct PointerRange {
+  pub min: Option<isize>,
+  pub max: Option<isize>,
+}
+
+impl PointerRange {
+  fn exact(offset: isize) -> PointerRange {
+    PointerRange {
+      min: Some(offset),
+      max: Some(offset),
+    }
+  }
+
+  fn unbounded() -> PointerRange {
+    PointerRange {
+      min: None,
+      max: None,
+    }
+  }
+
+  // Every sum of an offset in self and an offset in other.
+  fn add(self, other: PointerRange) -> PointerRange {
+    let add = |a: Option<isize>, b: Option<isize>| a.and_then(|a| b.and_then(|b| a.checked_add(b)));
+
+    PointerRange {
+      min: add(self.min, other.min),
+      max: add(self.max, other.max),
+    }
+  }
+
+  fn union(self, other: PointerRange) -> PointerRange {
+    let bound = |a: Option<isize>, b: Option<isize>, pick: fn(isize, isize) -> isize| {
+      a.and_then(|a| b.map(|b| pick(a, b)))
+    };
+
+    PointerRange {
+      min: bound(self.min, other.min, isize::min),
+      max: bound(self.max, other.max, isize::max),
+    }
+  }
+
+  // How many cells the range covers, if it's bounded.
+  pub fn cells(&self) -> Option<usize> {
+    match (self.min, self.max) {
+      (Some(min), Some(max)) => Some((max - min) as usize + 1),
+      _ => None,
+    }
+  }
+
+  // Whether the range provably fits on a tape of the given size which starts at cell 0.
+  pub fn stays_within(&self, cells: usize) -> bool {
+    match (self.min, self.max) {
+      (Some(min), Some(max)) => min >= 0 && (max as usize) < cells,
+      _ => false,
+    }
+  }
+
+  pub fn can_go_left(&self) -> bool {
+    self.min.map_or(true, |min| min < 0)
+  }
+}
+
+// What running a part of a program does: the cells it can touch, if any, and how far it can
+// move the pointer, both relative to where it starts.
+struct Summary {
+  touched: Option<PointerRange>,
+  movement: PointerRange,
+}
+
+impl Summary {
+  fn touch(&mut self, range: PointerRange) {
+    self.touched = Some(match self.touched {
+      Some(touched) => touched.union(range),
+      None => range,
+    });
+  }
+
+  // A loop which checks the cell at the offset from the pointer.
+  fn add_loop(&mut self, offset: isize, body: &[ProgramToken]) {
+    let body = summarize(body);
+
+    // A loop which moves the pointer can run any number of times, so the pointer can end up
+    // anywhere in the direction it moves.
+    if body.movement != PointerRange::exact(0) {
+      self.movement = PointerRange {
+        min: self
+          .movement
+          .min
+          .filter(|_| body.movement.min.map_or(false, |x| x >= 0)),
+        max: self
+          .movement
+          .max
+          .filter(|_| body.movement.max.map_or(false, |x| x <= 0)),
+      };
+    }
+
+    let condition = self.movement.add(PointerRange::exact(offset));
+    self.touch(condition);
+
+    if let Some(touched) = body.touched {
+      let cells = self.movement.add(touched);
+      self.touch(cells);
+    }
+  }
+}
+
+fn summarize(program: &[ProgramToken]) -> Summary {
+  let mut summary = Summary {
+    touched: None,
+    movement: PointerRange::exact(0),
+  };
+
+  for token in program {
+    match token {
+      ChangeAddr(by) => summary.movement = summary.movement.add(PointerRange::exact(*by)),
+      Offset(offset, _) => {
+        let cell = summary.movement.add(PointerRange::exact(*offset));
+        summary.touch(cell);
+      }
+      Loop(body) => summary.add_loop(0, body),
+      OffsetLoop(offset, body) => summary.add_loop(*offset, body),
+      // Procedures can be redefined and run wherever they're called, so calls can touch and move
+      // to any cell.
+      Procedure(_) => {}
+      Call => {
+        summary.touch(PointerRange::unbounded());
+        summary.movement = PointerRange::unbounded();
+      }
+    }
+  }
+
+  summary
+}
+
+// The cells a program can touch, relative to the starting cell, which is always included. Straight
+// code and loops which end where they started are followed exactly, so a bounded range usually
+// means the program needs exactly that many cells.
+pub fn pointer_range(program: &[ProgramToken]) -> PointerRange {
+  let summary = summarize(program);
+
+  match summary.touched {
+    Some(touched) => touched.union(PointerRange::exact(0)),
+    None => PointerRange::exact(0),
+  }
+}
@@ -0,0 +1,118 @@
+use std::io::Write;
+
+use backend::Backend;
+use types::MemoryOp::*;
+use types::ProgramToken;
+
+// Translates the IR back into Brainfuck, which makes the optimizer usable as a source
+// optimizer. The output is a single line, like `bfcrsc fmt --minify`.
+pub struct BfBackend;
+
+pub fn push_repeated(output: &mut String, ch: char, count: usize) {
+  for _ in 0..count {
+    output.push(ch);
+  }
+}
+
+fn push_move(output: &mut String, distance: isize) {
+  let ch = if distance > 0 { '>' } else { '<' };
+  push_repeated(output, ch, distance.abs() as usize);
+}
+
+// Cells wrap around, so adding 255 is the same as subtracting 1.
+fn push_change(output: &mut String, value: i8) {
+  let value = value as u8;
+
+  if value <= 128 {
+    push_repeated(output, '+', value as usize);
+  } else {
+    push_repeated(output, '-', 256 - value as usize);
+  }
+}
+
+// The IR keeps the pointer still and addresses cells by offset, but Brainfuck has to walk to
+// every cell. `head` is where the Brainfuck pointer is relative to the IR pointer, so that
+// consecutive offsets are reached without walking back in between. Loops check the cell under
+// the IR pointer, and so do procedures, so the head returns to it around every bracket. Offset
+// loops check the cell at their offset instead, so the head stays there around them. The head
+// starts and ends at `start`.
+fn compile_tokens(output: &mut String, tokens: &[ProgramToken], start: isize) {
+  let mut head = start;
+
+  for token in tokens {
+    match token {
+      ProgramToken::ChangeAddr(offset) => head -= *offset,
+      ProgramToken::Offset(offset, op) => {
+        push_move(output, *offset - head);
+        head = *offset;
+
+        match op {
+          ChangeValue(value) => push_change(output, *value),
+          SetValue(value) => {
+            output.push_str("[-]");
+            push_change(output, *value);
+          }
+          Print => output.push('.'),
+          Read => output.push(','),
+          Store => output.push('$'),
+          Retrieve => output.push('!'),
+          ShiftLeft => output.push('{'),
+          ShiftRight => output.push('}'),
+          Not => output.push('~'),
+          Xor => output.push('^'),
+          And => output.push('&'),
+          Or => output.push('|'),
+        }
+      }
+      ProgramToken::Loop(body) => {
+        push_move(output, -head);
+        head = 0;
+        output.push('[');
+        compile_tokens(output, body, 0);
+        output.push(']');
+      }
+      ProgramToken::OffsetLoop(offset, body) => {
+        push_move(output, *offset - head);
+        head = *offset;
+        output.push('[');
+        compile_tokens(output, body, *offset);
+        output.push(']');
+      }
+      ProgramToken::Procedure(body) => {
+        push_move(output, -head);
+        head = 0;
+        output.push('(');
+        compile_tokens(output, body, 0);
+        output.push(')');
+      }
+      ProgramToken::Call => {
+        push_move(output, -head);
+        head = 0;
+        output.push(':');
+      }
+    }
+  }
+
+  push_move(output, start - head);
+}
+
+impl Backend for BfBackend {
+  fn extension(&self) -> &'static str {
+    ".bf"
+  }
+
+  fn supports_procedures(&self) -> bool {
+    true
+  }
+
+  fn compile_to_stream(&self, tokens: &[ProgramToken], stream: &mut dyn Write) {
+    let mut output = String::new();
+    compile_tokens(&mut output, tokens, 0);
+
+    if !output.is_empty() {
+      output.push('\n');
+    }
+
+    stream.write_all(output.as_bytes()).unwrap();
+  }
+}
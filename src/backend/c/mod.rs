@@ -1,8 +1,9 @@
 use std::io::Write;
 
 use backend::Backend;
+use optimizer::uses_storage;
 use types::MemoryOp::*;
-use types::ProgramToken;
+use types::{MemoryOp, ProgramToken};
 
 pub struct CBackend;
 
@@ -18,9 +19,17 @@ impl Backend for CBackend {
 
     write!(&mut output, "#include <stdlib.h>\n").unwrap();
     write!(&mut output, "#include <stdio.h>\n").unwrap();
+    // The end of the input reads as 0.
+    write!(&mut output, "static int bf_read(void) {{\n").unwrap();
+    write!(&mut output, "  int c = getchar();\n").unwrap();
+    write!(&mut output, "  return c == EOF ? 0 : c;\n").unwrap();
+    write!(&mut output, "}}\n").unwrap();
     write!(&mut output, "int main() {{\n").unwrap();
     write!(&mut output, "  char* buffer = malloc(30000);\n").unwrap();
     write!(&mut output, "  int pointer = 0;\n").unwrap();
+    if uses_storage(tokens) {
+      write!(&mut output, "  unsigned char storage = 0;\n").unwrap();
+    }
 
     fn compile_tokens(mut output: &mut String, tokens: &[ProgramToken], indent: String) {
       for token in tokens {
@@ -49,15 +58,54 @@ impl Backend for CBackend {
               indent, offset
             ).unwrap();
           }
+          ProgramToken::Offset(offset, Read) => {
+            write!(
+              &mut output,
+              "{}buffer[pointer + {}] = bf_read();\n",
+              indent, offset
+            ).unwrap();
+          }
+          ProgramToken::Offset(offset, op) => {
+            let cell = format!("buffer[pointer + {}]", offset);
+            write!(&mut output, "{}{};\n", indent, storage_statement(op, &cell)).unwrap();
+          }
           ProgramToken::Loop(inner) => {
             write!(&mut output, "{}while (buffer[pointer]) {{\n", indent).unwrap();
             compile_tokens(&mut output, inner, indent.clone() + "  ");
             write!(&mut output, "{}}}\n", indent).unwrap();
           }
+          ProgramToken::OffsetLoop(offset, inner) => {
+            write!(
+              &mut output,
+              "{}while (buffer[pointer + {}]) {{\n",
+              indent, offset
+            )
+            .unwrap();
+            compile_tokens(&mut output, inner, indent.clone() + "  ");
+            write!(&mut output, "{}}}\n", indent).unwrap();
+          }
+          ProgramToken::Procedure(_) | ProgramToken::Call => {
+            panic!("The C backend doesn't support procedures.")
+          }
         }
       }
     }
 
+    // The cells are chars, which can be signed, so they're shifted right as unsigned chars.
+    fn storage_statement(op: &MemoryOp, cell: &str) -> String {
+      match op {
+        Store => format!("storage = {}", cell),
+        Retrieve => format!("{} = storage", cell),
+        ShiftLeft => format!("{} <<= 1", cell),
+        ShiftRight => format!("{} = (unsigned char){} >> 1", cell, cell),
+        Not => format!("{} = ~{}", cell, cell),
+        Xor => format!("{} ^= storage", cell),
+        And => format!("{} &= storage", cell),
+        Or => format!("{} |= storage", cell),
+        ChangeValue(_) | SetValue(_) | Print | Read => unreachable!(),
+      }
+    }
+
     compile_tokens(&mut output, tokens, "  ".to_string());
 
     write!(&mut output, "  free(buffer);\n").unwrap();
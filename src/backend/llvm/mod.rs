@@ -0,0 +1,232 @@
+use std::fmt::Write as FmtWrite;
+use std::io::Write;
+
+use backend::Backend;
+use optimizer::uses_storage;
+use types::MemoryOp::*;
+use types::ProgramToken;
+
+pub struct LlvmBackend;
+
+const TAPE_SIZE: usize = 65536;
+
+struct FunctionWriter {
+  output: String,
+  next_temp: usize,
+  next_label: usize,
+}
+
+impl FunctionWriter {
+  fn new() -> FunctionWriter {
+    FunctionWriter {
+      output: String::new(),
+      next_temp: 0,
+      next_label: 0,
+    }
+  }
+
+  fn temp(&mut self) -> String {
+    let name = format!("%t{}", self.next_temp);
+    self.next_temp += 1;
+    name
+  }
+
+  fn label(&mut self) -> usize {
+    let id = self.next_label;
+    self.next_label += 1;
+    id
+  }
+
+  // Loads the pointer and returns an i8* to the cell at the given offset from it.
+  fn emit_cell_address(&mut self, offset: isize) -> String {
+    let pointer = self.temp();
+    write!(self.output, "  {} = load i64, i64* %pointer\n", pointer).unwrap();
+
+    let index = if offset == 0 {
+      pointer
+    } else {
+      let index = self.temp();
+      write!(
+        self.output,
+        "  {} = add i64 {}, {}\n",
+        index, pointer, offset
+      ).unwrap();
+      index
+    };
+
+    let address = self.temp();
+    write!(
+      self.output,
+      "  {} = getelementptr inbounds [{} x i8], [{} x i8]* @tape, i64 0, i64 {}\n",
+      address, TAPE_SIZE, TAPE_SIZE, index
+    ).unwrap();
+    address
+  }
+
+  // Every loop gets a header which checks its cell, a body and an exit block.
+  fn emit_loop(&mut self, offset: isize, inner: &[ProgramToken]) {
+    let id = self.label();
+    write!(self.output, "  br label %loop{}.head\n", id).unwrap();
+    write!(self.output, "loop{}.head:\n", id).unwrap();
+
+    let address = self.emit_cell_address(offset);
+    let value = self.temp();
+    let is_zero = self.temp();
+    write!(self.output, "  {} = load i8, i8* {}\n", value, address).unwrap();
+    write!(self.output, "  {} = icmp eq i8 {}, 0\n", is_zero, value).unwrap();
+    write!(
+      self.output,
+      "  br i1 {}, label %loop{}.exit, label %loop{}.body\n",
+      is_zero, id, id
+    ).unwrap();
+
+    write!(self.output, "loop{}.body:\n", id).unwrap();
+    self.emit_tokens(inner);
+    write!(self.output, "  br label %loop{}.head\n", id).unwrap();
+    write!(self.output, "loop{}.exit:\n", id).unwrap();
+  }
+
+  fn emit_tokens(&mut self, tokens: &[ProgramToken]) {
+    for token in tokens {
+      match token {
+        ProgramToken::ChangeAddr(offset) => {
+          let pointer = self.temp();
+          let result = self.temp();
+          write!(self.output, "  {} = load i64, i64* %pointer\n", pointer).unwrap();
+          write!(
+            self.output,
+            "  {} = add i64 {}, {}\n",
+            result, pointer, offset
+          ).unwrap();
+          write!(self.output, "  store i64 {}, i64* %pointer\n", result).unwrap();
+        }
+        ProgramToken::Offset(offset, ChangeValue(value)) => {
+          let address = self.emit_cell_address(*offset);
+          let old = self.temp();
+          let new = self.temp();
+          write!(self.output, "  {} = load i8, i8* {}\n", old, address).unwrap();
+          write!(self.output, "  {} = add i8 {}, {}\n", new, old, value).unwrap();
+          write!(self.output, "  store i8 {}, i8* {}\n", new, address).unwrap();
+        }
+        ProgramToken::Offset(offset, SetValue(value)) => {
+          let address = self.emit_cell_address(*offset);
+          write!(self.output, "  store i8 {}, i8* {}\n", value, address).unwrap();
+        }
+        ProgramToken::Offset(offset, Print) => {
+          let address = self.emit_cell_address(*offset);
+          let value = self.temp();
+          let extended = self.temp();
+          let ignored = self.temp();
+          write!(self.output, "  {} = load i8, i8* {}\n", value, address).unwrap();
+          write!(self.output, "  {} = zext i8 {} to i32\n", extended, value).unwrap();
+          write!(
+            self.output,
+            "  {} = call i32 @putchar(i32 {})\n",
+            ignored, extended
+          ).unwrap();
+        }
+        ProgramToken::Offset(offset, Read) => {
+          // getchar returns -1 at the end of the input, which reads as 0.
+          let address = self.emit_cell_address(*offset);
+          let ch = self.temp();
+          let at_end = self.temp();
+          let value = self.temp();
+          let truncated = self.temp();
+          write!(self.output, "  {} = call i32 @getchar()\n", ch).unwrap();
+          write!(self.output, "  {} = icmp eq i32 {}, -1\n", at_end, ch).unwrap();
+          write!(
+            self.output,
+            "  {} = select i1 {}, i32 0, i32 {}\n",
+            value, at_end, ch
+          ).unwrap();
+          write!(self.output, "  {} = trunc i32 {} to i8\n", truncated, value).unwrap();
+          write!(self.output, "  store i8 {}, i8* {}\n", truncated, address).unwrap();
+        }
+        ProgramToken::Offset(offset, Store) => {
+          let address = self.emit_cell_address(*offset);
+          let value = self.temp();
+          write!(self.output, "  {} = load i8, i8* {}\n", value, address).unwrap();
+          write!(self.output, "  store i8 {}, i8* %storage\n", value).unwrap();
+        }
+        ProgramToken::Offset(offset, Retrieve) => {
+          let address = self.emit_cell_address(*offset);
+          let value = self.temp();
+          write!(self.output, "  {} = load i8, i8* %storage\n", value).unwrap();
+          write!(self.output, "  store i8 {}, i8* {}\n", value, address).unwrap();
+        }
+        ProgramToken::Offset(offset, op) => {
+          let address = self.emit_cell_address(*offset);
+          let old = self.temp();
+          write!(self.output, "  {} = load i8, i8* {}\n", old, address).unwrap();
+
+          let operand = match op {
+            ShiftLeft | ShiftRight => "1".to_string(),
+            Not => "-1".to_string(),
+            _ => {
+              let storage = self.temp();
+              write!(self.output, "  {} = load i8, i8* %storage\n", storage).unwrap();
+              storage
+            }
+          };
+          let instruction = match op {
+            ShiftLeft => "shl",
+            ShiftRight => "lshr",
+            Not | Xor => "xor",
+            And => "and",
+            Or => "or",
+            _ => unreachable!(),
+          };
+
+          let new = self.temp();
+          write!(
+            self.output,
+            "  {} = {} i8 {}, {}\n",
+            new, instruction, old, operand
+          )
+          .unwrap();
+          write!(self.output, "  store i8 {}, i8* {}\n", new, address).unwrap();
+        }
+        ProgramToken::Loop(inner) => self.emit_loop(0, inner),
+        ProgramToken::OffsetLoop(offset, inner) => self.emit_loop(*offset, inner),
+        ProgramToken::Procedure(_) | ProgramToken::Call => {
+          panic!("The LLVM backend doesn't support procedures.")
+        }
+      }
+    }
+  }
+}
+
+impl Backend for LlvmBackend {
+  fn extension(&self) -> &'static str {
+    ".ll"
+  }
+
+  fn compile_to_stream(&self, tokens: &[ProgramToken], stream: &mut dyn Write) {
+    let mut output = String::new();
+
+    write!(
+      &mut output,
+      "@tape = internal global [{} x i8] zeroinitializer\n\n",
+      TAPE_SIZE
+    ).unwrap();
+    write!(&mut output, "declare i32 @putchar(i32)\n").unwrap();
+    write!(&mut output, "declare i32 @getchar()\n\n").unwrap();
+    write!(&mut output, "define i32 @main() {{\n").unwrap();
+    write!(&mut output, "entry:\n").unwrap();
+    write!(&mut output, "  %pointer = alloca i64\n").unwrap();
+    write!(&mut output, "  store i64 0, i64* %pointer\n").unwrap();
+    if uses_storage(tokens) {
+      write!(&mut output, "  %storage = alloca i8\n").unwrap();
+      write!(&mut output, "  store i8 0, i8* %storage\n").unwrap();
+    }
+
+    let mut function = FunctionWriter::new();
+    function.emit_tokens(tokens);
+    output.push_str(&function.output);
+
+    write!(&mut output, "  ret i32 0\n").unwrap();
+    write!(&mut output, "}}\n").unwrap();
+
+    stream.write(output.as_bytes()).unwrap();
+  }
+}
@@ -1,19 +1,43 @@
+use std::error::Error;
 use std::io::Write;
+use optimizer::uses_procedures;
 use types::ProgramToken;
 
+pub mod bf;
 pub mod c;
+pub mod llvm;
 pub mod wasm;
 
 pub trait Backend {
   fn extension(&self) -> &'static str;
+  // Panics if the program defines or calls procedures and the backend doesn't support them.
   fn compile_to_stream(&self, tokens: &[ProgramToken], stream: &mut dyn Write);
+
+  // Whether programs may define and call procedures.
+  fn supports_procedures(&self) -> bool {
+    false
+  }
+
+  // Like compile_to_stream, but returns an error for programs the backend can't compile.
+  fn compile(&self, tokens: &[ProgramToken], stream: &mut dyn Write) -> Result<(), Box<Error>> {
+    if uses_procedures(tokens) && !self.supports_procedures() {
+      return Err(From::from("The target doesn't support procedures."));
+    }
+
+    self.compile_to_stream(tokens, stream);
+    Ok(())
+  }
 }
 
 impl Backend {
   pub fn from_name(name: &str) -> Option<Box<Backend>> {
     match name {
+      "bf" => Some(Box::new(self::bf::BfBackend)),
       "c" => Some(Box::new(self::c::CBackend)),
+      "llvm" => Some(Box::new(self::llvm::LlvmBackend)),
       "wasm" => Some(Box::new(self::wasm::WasmBackend)),
+      "wasi" => Some(Box::new(self::wasm::WasiBackend)),
+      "wat" => Some(Box::new(self::wasm::wat::WatBackend)),
       _ => None,
     }
   }
@@ -1,27 +1,45 @@
 use std::error::Error;
-use std::io::Write;
+use std::io::{Read, Write};
 
-use byteorder::WriteBytesExt;
+use byteorder::{ReadBytesExt, WriteBytesExt};
 use leb128;
 
 use backend::wasm::module_builder::WasmType;
 
+#[derive(Debug, PartialEq, Clone)]
 pub enum Instruction {
   GetLocal(LocalHandle),
   SetLocal(LocalHandle),
+  GetGlobal(u32),
+  SetGlobal(u32),
   Load8Signed(u32),
   Load8Unsigned(u32),
   Store8(u32),
+  Load32(u32),
+  Store32(u32),
   PushI32(i32),
   AddI32,
+  SubI32,
+  ShiftLeftI32,
+  ShiftRightUnsignedI32,
+  AndI32,
+  OrI32,
+  XorI32,
+  NotEqualsI32,
+  LessOrEqualSignedI32,
+  MemorySize,
+  MemoryGrow,
   Call(u32),
   BranchIf(u32),
+  // The depth of every target and the default one.
+  BranchTable(Vec<u32>, u32),
   EqualsZeroI32,
   Loop,
   Block,
   End,
   Drop,
   Return,
+  Unreachable,
 }
 
 pub trait CodeStreamExt {
@@ -60,12 +78,41 @@ impl<T: Write> CodeStreamExt for T {
   }
 }
 
-#[derive(Copy, Clone)]
+pub trait CodeStreamReadExt {
+  fn read_leb_u32(&mut self) -> Result<u32, Box<Error>>;
+  fn read_leb_i32(&mut self) -> Result<i32, Box<Error>>;
+  fn read_local(&mut self) -> Result<LocalHandle, Box<Error>>;
+  fn read_str(&mut self) -> Result<String, Box<Error>>;
+}
+
+impl<T: Read> CodeStreamReadExt for T {
+  fn read_leb_u32(&mut self) -> Result<u32, Box<Error>> {
+    Ok(leb128::read::unsigned(self)? as u32)
+  }
+
+  fn read_leb_i32(&mut self) -> Result<i32, Box<Error>> {
+    Ok(leb128::read::signed(self)? as i32)
+  }
+
+  fn read_local(&mut self) -> Result<LocalHandle, Box<Error>> {
+    Ok(LocalHandle(self.read_leb_u32()?))
+  }
+
+  fn read_str(&mut self) -> Result<String, Box<Error>> {
+    let len = self.read_leb_u32()?;
+    let mut buffer = vec![0u8; len as usize];
+    self.read_exact(&mut buffer)?;
+    Ok(String::from_utf8(buffer)?)
+  }
+}
+
+#[derive(Debug, PartialEq, Copy, Clone)]
 pub struct LocalHandle(pub u32);
 
 pub struct CodeStreamWriter<'a, T: Write + 'a> {
   stream: &'a mut T,
   locals: Vec<WasmType>,
+  position: usize,
 }
 
 impl<'a, T: Write + 'a> CodeStreamWriter<'a, T> {
@@ -73,9 +120,15 @@ impl<'a, T: Write + 'a> CodeStreamWriter<'a, T> {
     CodeStreamWriter {
       stream,
       locals: Vec::new(),
+      position: 0,
     }
   }
 
+  // Number of bytes emitted so far.
+  pub fn position(&self) -> usize {
+    self.position
+  }
+
   pub fn declare_local(&mut self, local_type: WasmType) -> LocalHandle {
     let handle = LocalHandle(self.locals.len() as u32);
     self.locals.push(local_type);
@@ -93,68 +146,219 @@ impl<'a, T: Write + 'a> CodeStreamWriter<'a, T> {
   pub fn emit(&mut self, op: Instruction) -> Result<(), Box<Error>> {
     use self::Instruction::*;
 
+    // Instructions are assembled separately so we can keep track of the offset in the stream.
+    let mut buffer: Vec<u8> = Vec::new();
+
     match op {
       GetLocal(handle) => {
-        self.stream.write_u8(0x20)?;
-        self.stream.write_local(handle);
+        buffer.write_u8(0x20)?;
+        buffer.write_local(handle);
       }
       SetLocal(handle) => {
-        self.stream.write_u8(0x21)?;
-        self.stream.write_local(handle);
+        buffer.write_u8(0x21)?;
+        buffer.write_local(handle);
+      }
+      GetGlobal(index) => {
+        buffer.write_u8(0x23)?;
+        buffer.write_leb_u32(index);
+      }
+      SetGlobal(index) => {
+        buffer.write_u8(0x24)?;
+        buffer.write_leb_u32(index);
       }
       PushI32(value) => {
-        self.stream.write_u8(0x41)?;
-        self.stream.write_leb_i32(value);
+        buffer.write_u8(0x41)?;
+        buffer.write_leb_i32(value);
       }
       Load8Signed(offset) => {
-        self.stream.write_u8(0x2C)?;
-        self.stream.write_leb_u32(0);
-        self.stream.write_leb_u32(offset);
+        buffer.write_u8(0x2C)?;
+        buffer.write_leb_u32(0);
+        buffer.write_leb_u32(offset);
       }
       Load8Unsigned(offset) => {
-        self.stream.write_u8(0x2D)?;
-        self.stream.write_leb_u32(0);
-        self.stream.write_leb_u32(offset);
+        buffer.write_u8(0x2D)?;
+        buffer.write_leb_u32(0);
+        buffer.write_leb_u32(offset);
       }
       Store8(offset) => {
-        self.stream.write_u8(0x3A)?;
-        self.stream.write_leb_u32(0);
-        self.stream.write_leb_u32(offset);
+        buffer.write_u8(0x3A)?;
+        buffer.write_leb_u32(0);
+        buffer.write_leb_u32(offset);
+      }
+      Load32(offset) => {
+        buffer.write_u8(0x28)?;
+        buffer.write_leb_u32(2);
+        buffer.write_leb_u32(offset);
+      }
+      Store32(offset) => {
+        buffer.write_u8(0x36)?;
+        buffer.write_leb_u32(2);
+        buffer.write_leb_u32(offset);
       }
       AddI32 => {
-        self.stream.write_u8(0x6A)?;
+        buffer.write_u8(0x6A)?;
+      }
+      SubI32 => {
+        buffer.write_u8(0x6B)?;
+      }
+      ShiftLeftI32 => {
+        buffer.write_u8(0x74)?;
+      }
+      ShiftRightUnsignedI32 => {
+        buffer.write_u8(0x76)?;
+      }
+      AndI32 => {
+        buffer.write_u8(0x71)?;
+      }
+      OrI32 => {
+        buffer.write_u8(0x72)?;
+      }
+      XorI32 => {
+        buffer.write_u8(0x73)?;
+      }
+      NotEqualsI32 => {
+        buffer.write_u8(0x47)?;
+      }
+      LessOrEqualSignedI32 => {
+        buffer.write_u8(0x4C)?;
+      }
+      // Both take a reserved memory index, which must be 0.
+      MemorySize => {
+        buffer.write_u8(0x3F)?;
+        buffer.write_u8(0)?;
+      }
+      MemoryGrow => {
+        buffer.write_u8(0x40)?;
+        buffer.write_u8(0)?;
       }
       Call(function) => {
-        self.stream.write_u8(0x10)?;
-        self.stream.write_leb_u32(function);
+        buffer.write_u8(0x10)?;
+        buffer.write_leb_u32(function);
       }
       BranchIf(depth) => {
-        self.stream.write_u8(0x0D)?;
-        self.stream.write_leb_u32(depth);
+        buffer.write_u8(0x0D)?;
+        buffer.write_leb_u32(depth);
+      }
+      BranchTable(targets, default) => {
+        buffer.write_u8(0x0E)?;
+        buffer.write_leb_u32(targets.len() as u32);
+        for target in targets {
+          buffer.write_leb_u32(target);
+        }
+        buffer.write_leb_u32(default);
       }
       EqualsZeroI32 => {
-        self.stream.write_u8(0x45)?;
+        buffer.write_u8(0x45)?;
       }
       Loop => {
-        self.stream.write_u8(0x03)?;
+        buffer.write_u8(0x03)?;
         // Loops can return values, but 0x40 indicates this one doesn't.
-        self.stream.write_u8(0x40)?;
+        buffer.write_u8(0x40)?;
       }
       Block => {
-        self.stream.write_u8(0x02)?;
-        self.stream.write_u8(0x40)?;
+        buffer.write_u8(0x02)?;
+        buffer.write_u8(0x40)?;
       }
       End => {
-        self.stream.write_u8(0x0B)?;
+        buffer.write_u8(0x0B)?;
       }
       Drop => {
-        self.stream.write_u8(0x1A)?;
+        buffer.write_u8(0x1A)?;
       }
       Return => {
-        self.stream.write_u8(0x0F)?;
+        buffer.write_u8(0x0F)?;
+      }
+      Unreachable => {
+        buffer.write_u8(0x00)?;
       }
     }
 
+    self.stream.write_all(&buffer)?;
+    self.position += buffer.len();
+
     Ok(())
   }
 }
+
+// The inverse of CodeStreamWriter::emit. Only understands the instructions we generate.
+pub fn read_instruction<T: Read>(stream: &mut T) -> Result<Instruction, Box<Error>> {
+  use self::Instruction::*;
+
+  let opcode = stream.read_u8()?;
+
+  let instruction = match opcode {
+    0x20 => GetLocal(stream.read_local()?),
+    0x21 => SetLocal(stream.read_local()?),
+    0x23 => GetGlobal(stream.read_leb_u32()?),
+    0x24 => SetGlobal(stream.read_leb_u32()?),
+    0x41 => PushI32(stream.read_leb_i32()?),
+    0x2C | 0x2D | 0x3A | 0x28 | 0x36 => {
+      // Alignment
+      stream.read_leb_u32()?;
+      let offset = stream.read_leb_u32()?;
+
+      match opcode {
+        0x2C => Load8Signed(offset),
+        0x2D => Load8Unsigned(offset),
+        0x3A => Store8(offset),
+        0x28 => Load32(offset),
+        _ => Store32(offset),
+      }
+    }
+    0x6A => AddI32,
+    0x6B => SubI32,
+    0x74 => ShiftLeftI32,
+    0x76 => ShiftRightUnsignedI32,
+    0x71 => AndI32,
+    0x72 => OrI32,
+    0x73 => XorI32,
+    0x47 => NotEqualsI32,
+    0x4C => LessOrEqualSignedI32,
+    0x3F | 0x40 => {
+      let memory = stream.read_u8()?;
+      if memory != 0 {
+        return Err(From::from(format!("Unsupported memory index: {}", memory)));
+      }
+
+      if opcode == 0x3F {
+        MemorySize
+      } else {
+        MemoryGrow
+      }
+    }
+    0x10 => Call(stream.read_leb_u32()?),
+    0x0D => BranchIf(stream.read_leb_u32()?),
+    0x0E => {
+      let count = stream.read_leb_u32()?;
+      let targets = (0..count)
+        .map(|_| stream.read_leb_u32())
+        .collect::<Result<Vec<_>, _>>()?;
+      BranchTable(targets, stream.read_leb_u32()?)
+    }
+    0x45 => EqualsZeroI32,
+    0x03 | 0x02 => {
+      let block_type = stream.read_u8()?;
+      if block_type != 0x40 {
+        return Err(From::from(format!(
+          "Unsupported block type: 0x{:02X}",
+          block_type
+        )));
+      }
+
+      if opcode == 0x03 {
+        Loop
+      } else {
+        Block
+      }
+    }
+    0x0B => End,
+    0x1A => Drop,
+    0x0F => Return,
+    0x00 => Unreachable,
+    other => {
+      return Err(From::from(format!("Unsupported opcode: 0x{:02X}", other)));
+    }
+  };
+
+  Ok(instruction)
+}
@@ -0,0 +1,341 @@
+use std::collections::HashMap;
+use std::error::Error;
+use std::io::{Cursor, Read};
+
+use byteorder::{LittleEndian, ReadBytesExt};
+
+use backend::wasm::code_stream::{read_instruction, CodeStreamReadExt, Instruction};
+use backend::wasm::module_builder::{WasmType, BFCRS_SECTION_VERSION};
+
+pub struct FunctionType {
+  pub params: Vec<WasmType>,
+  pub results: Vec<WasmType>,
+}
+
+pub struct Import {
+  pub module: String,
+  pub field: String,
+  pub type_index: u32,
+}
+
+pub struct ResizableLimits {
+  pub initial: u32,
+  pub maximum: Option<u32>,
+}
+
+pub struct Global {
+  pub value_type: WasmType,
+  pub mutable: bool,
+  // The value of the i32.const the global is initialized with.
+  pub initial: i32,
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum ExternalKind {
+  Function,
+  Table,
+  Memory,
+  Global,
+}
+
+pub struct Export {
+  pub name: String,
+  pub kind: ExternalKind,
+  pub index: u32,
+}
+
+pub struct FunctionBody {
+  pub locals: Vec<WasmType>,
+  pub code: Vec<Instruction>,
+}
+
+// Contents of the "name" section.
+pub struct Names {
+  pub functions: HashMap<u32, String>,
+  // Local names by function index.
+  pub locals: HashMap<u32, HashMap<u32, String>>,
+}
+
+// Contents of our own "bfcrs" section.
+pub struct SourceInfo {
+  pub source_hash: u64,
+  pub source_length: u32,
+  pub options: Vec<(String, String)>,
+  pub source: Option<String>,
+  pub entry_function: u32,
+  // Pairs of (code offset in the entry function, source offset).
+  pub source_map: Vec<(u32, u32)>,
+}
+
+// A decoded module. Only covers the subset of WebAssembly our own backend produces.
+pub struct Module {
+  pub types: Vec<FunctionType>,
+  pub imports: Vec<Import>,
+  pub functions: Vec<u32>,
+  pub memories: Vec<ResizableLimits>,
+  pub globals: Vec<Global>,
+  pub exports: Vec<Export>,
+  pub bodies: Vec<FunctionBody>,
+  pub names: Names,
+  pub source_info: Option<SourceInfo>,
+}
+
+fn read_type<T: Read>(stream: &mut T) -> Result<WasmType, Box<Error>> {
+  match stream.read_u8()? {
+    0x7F => Ok(WasmType::I32),
+    other => Err(From::from(format!(
+      "Unsupported value type: 0x{:02X}",
+      other
+    ))),
+  }
+}
+
+fn read_types<T: Read>(stream: &mut T) -> Result<Vec<WasmType>, Box<Error>> {
+  let count = stream.read_leb_u32()?;
+  (0..count).map(|_| read_type(stream)).collect()
+}
+
+fn read_entries<T, F>(stream: &mut Cursor<&[u8]>, mut read_entry: F) -> Result<Vec<T>, Box<Error>>
+where
+  F: FnMut(&mut Cursor<&[u8]>) -> Result<T, Box<Error>>,
+{
+  let count = stream.read_leb_u32()?;
+  let mut entries = Vec::with_capacity(count as usize);
+
+  for _ in 0..count {
+    entries.push(read_entry(stream)?);
+  }
+
+  Ok(entries)
+}
+
+fn read_function_type(stream: &mut Cursor<&[u8]>) -> Result<FunctionType, Box<Error>> {
+  let form = stream.read_u8()?;
+  if form != 0x60 {
+    return Err(From::from(format!("Unsupported type form: 0x{:02X}", form)));
+  }
+
+  let params = read_types(stream)?;
+  let results = read_types(stream)?;
+  Ok(FunctionType { params, results })
+}
+
+fn read_import(stream: &mut Cursor<&[u8]>) -> Result<Import, Box<Error>> {
+  let module = stream.read_str()?;
+  let field = stream.read_str()?;
+
+  match stream.read_u8()? {
+    0 => Ok(Import {
+      module,
+      field,
+      type_index: stream.read_leb_u32()?,
+    }),
+    other => Err(From::from(format!("Unsupported import kind: {}", other))),
+  }
+}
+
+fn read_limits(stream: &mut Cursor<&[u8]>) -> Result<ResizableLimits, Box<Error>> {
+  let flags = stream.read_u8()?;
+  let initial = stream.read_leb_u32()?;
+  let maximum = if flags & 1 != 0 {
+    Some(stream.read_leb_u32()?)
+  } else {
+    None
+  };
+
+  Ok(ResizableLimits { initial, maximum })
+}
+
+fn read_global(stream: &mut Cursor<&[u8]>) -> Result<Global, Box<Error>> {
+  let value_type = read_type(stream)?;
+  let mutable = stream.read_u8()? != 0;
+
+  let initial = match (read_instruction(stream)?, read_instruction(stream)?) {
+    (Instruction::PushI32(value), Instruction::End) => value,
+    _ => return Err(From::from("Unsupported global initializer.")),
+  };
+
+  Ok(Global {
+    value_type,
+    mutable,
+    initial,
+  })
+}
+
+fn read_export(stream: &mut Cursor<&[u8]>) -> Result<Export, Box<Error>> {
+  let name = stream.read_str()?;
+  let kind = match stream.read_u8()? {
+    0 => ExternalKind::Function,
+    1 => ExternalKind::Table,
+    2 => ExternalKind::Memory,
+    3 => ExternalKind::Global,
+    other => {
+      return Err(From::from(format!("Unsupported export kind: {}", other)));
+    }
+  };
+  let index = stream.read_leb_u32()?;
+
+  Ok(Export { name, kind, index })
+}
+
+fn read_function_body(stream: &mut Cursor<&[u8]>) -> Result<FunctionBody, Box<Error>> {
+  let body_size = stream.read_leb_u32()? as u64;
+  let body_end = stream.position() + body_size;
+
+  let mut locals = Vec::new();
+  let group_count = stream.read_leb_u32()?;
+
+  for _ in 0..group_count {
+    let count = stream.read_leb_u32()?;
+    let local_type = read_type(stream)?;
+
+    for _ in 0..count {
+      locals.push(local_type);
+    }
+  }
+
+  let mut code = Vec::new();
+
+  while stream.position() < body_end {
+    code.push(read_instruction(stream)?);
+  }
+
+  Ok(FunctionBody { locals, code })
+}
+
+fn read_name_map(stream: &mut Cursor<&[u8]>) -> Result<HashMap<u32, String>, Box<Error>> {
+  let entries = read_entries(stream, |s| Ok((s.read_leb_u32()?, s.read_str()?)))?;
+  Ok(entries.into_iter().collect())
+}
+
+fn read_names(stream: &mut Cursor<&[u8]>) -> Result<Names, Box<Error>> {
+  let mut names = Names {
+    functions: HashMap::new(),
+    locals: HashMap::new(),
+  };
+
+  let length = stream.get_ref().len() as u64;
+
+  while stream.position() < length {
+    let id = stream.read_u8()?;
+    let size = stream.read_leb_u32()? as u64;
+    let end = stream.position() + size;
+
+    match id {
+      1 => names.functions = read_name_map(stream)?,
+      2 => {
+        let locals = read_entries(stream, |s| Ok((s.read_leb_u32()?, read_name_map(s)?)))?;
+        names.locals = locals.into_iter().collect();
+      }
+      // Module names and newer subsections aren't interesting to us.
+      _ => {}
+    }
+
+    stream.set_position(end);
+  }
+
+  Ok(names)
+}
+
+fn read_source_info(stream: &mut Cursor<&[u8]>) -> Result<SourceInfo, Box<Error>> {
+  let version = stream.read_u8()?;
+  if version != BFCRS_SECTION_VERSION {
+    return Err(From::from(format!(
+      "Unsupported bfcrs section version: {}",
+      version
+    )));
+  }
+
+  let source_hash = stream.read_u64::<LittleEndian>()?;
+  let source_length = stream.read_leb_u32()?;
+  let options = read_entries(stream, |s| Ok((s.read_str()?, s.read_str()?)))?;
+
+  let source = if stream.read_u8()? != 0 {
+    Some(stream.read_str()?)
+  } else {
+    None
+  };
+
+  let entry_function = stream.read_leb_u32()?;
+  let source_map = read_entries(stream, |s| Ok((s.read_leb_u32()?, s.read_leb_u32()?)))?;
+
+  Ok(SourceInfo {
+    source_hash,
+    source_length,
+    options,
+    source,
+    entry_function,
+    source_map,
+  })
+}
+
+pub fn decode_module(bytes: &[u8]) -> Result<Module, Box<Error>> {
+  let mut stream = Cursor::new(bytes);
+
+  if stream.read_u32::<LittleEndian>()? != 0x6d736100 {
+    return Err(From::from("Not a WebAssembly module."));
+  }
+
+  let version = stream.read_u32::<LittleEndian>()?;
+  if version != 1 {
+    return Err(From::from(format!("Unsupported version: {}", version)));
+  }
+
+  let mut module = Module {
+    types: Vec::new(),
+    imports: Vec::new(),
+    functions: Vec::new(),
+    memories: Vec::new(),
+    globals: Vec::new(),
+    exports: Vec::new(),
+    bodies: Vec::new(),
+    names: Names {
+      functions: HashMap::new(),
+      locals: HashMap::new(),
+    },
+    source_info: None,
+  };
+
+  while (stream.position() as usize) < bytes.len() {
+    let id = stream.read_u8()?;
+    let size = stream.read_leb_u32()? as usize;
+    let start = stream.position() as usize;
+
+    if start + size > bytes.len() {
+      return Err(From::from(format!("Section {} is truncated.", id)));
+    }
+
+    let mut section = Cursor::new(&bytes[start..start + size]);
+
+    match id {
+      0 => {
+        let name = section.read_str()?;
+        let payload_start = start + section.position() as usize;
+        let mut payload = Cursor::new(&bytes[payload_start..start + size]);
+
+        match name.as_str() {
+          "name" => module.names = read_names(&mut payload)?,
+          "bfcrs" => module.source_info = Some(read_source_info(&mut payload)?),
+          _ => {}
+        }
+      }
+      1 => module.types = read_entries(&mut section, read_function_type)?,
+      2 => module.imports = read_entries(&mut section, read_import)?,
+      3 => module.functions = read_entries(&mut section, |s| s.read_leb_u32())?,
+      5 => module.memories = read_entries(&mut section, read_limits)?,
+      6 => module.globals = read_entries(&mut section, read_global)?,
+      7 => module.exports = read_entries(&mut section, read_export)?,
+      10 => module.bodies = read_entries(&mut section, read_function_body)?,
+      // Sections we never emit are skipped.
+      _ => {}
+    }
+
+    stream.set_position((start + size) as u64);
+  }
+
+  if module.functions.len() != module.bodies.len() {
+    return Err(From::from("Function and code section sizes don't match."));
+  }
+
+  Ok(module)
+}
@@ -0,0 +1,754 @@
+use std::error::Error;
+
+use byteorder::{ByteOrder, LittleEndian};
+
+use backend::wasm::code_stream::Instruction;
+use backend::wasm::decoder::{decode_module, ExternalKind, Import, Module};
+use interpreter::{self, BfIo};
+use types::State;
+
+const PAGE_SIZE: usize = 65536;
+// A 32-bit address space has room for this many pages.
+const MAX_PAGES: u32 = 65536;
+// Calls are executed recursively, so they're limited before they overflow the native stack. A
+// procedure call of a bfcrs program is two calls, to dispatch and the procedure, so procedures can
+// be nested as deeply as in the interpreter.
+const MAX_CALL_DEPTH: usize = 2 * interpreter::MAX_CALL_DEPTH + 1;
+
+// Provides the functions a module imports.
+pub trait HostImports {
+  fn call_import(
+    &mut self,
+    import: &Import,
+    args: &[i32],
+    memory: &mut Vec<u8>,
+  ) -> Result<Option<i32>, Box<Error>>;
+}
+
+// The imports of modules built for WasmTarget::Bfcrs, backed by a BfIo.
+pub struct BfcrsImports<'a, T: BfIo + 'a> {
+  pub io: &'a mut T,
+}
+
+impl<'a, T: BfIo + 'a> HostImports for BfcrsImports<'a, T> {
+  fn call_import(
+    &mut self,
+    import: &Import,
+    args: &[i32],
+    _memory: &mut Vec<u8>,
+  ) -> Result<Option<i32>, Box<Error>> {
+    match (&import.module[..], &import.field[..]) {
+      ("bfcrs", "print") => {
+        self.io.print(args[0] as u8);
+        Ok(None)
+      }
+      ("bfcrs", "read") => Ok(Some(self.io.read() as i32)),
+      (module, field) => Err(From::from(format!("Unknown import: {}.{}", module, field))),
+    }
+  }
+}
+
+// Just enough of WASI to run modules built for WasmTarget::Wasi, backed by a BfIo.
+pub struct WasiImports<'a, T: BfIo + 'a> {
+  pub io: &'a mut T,
+}
+
+impl<'a, T: BfIo + 'a> HostImports for WasiImports<'a, T> {
+  fn call_import(
+    &mut self,
+    import: &Import,
+    args: &[i32],
+    memory: &mut Vec<u8>,
+  ) -> Result<Option<i32>, Box<Error>> {
+    let field = match &import.module[..] {
+      "wasi_snapshot_preview1" => &import.field[..],
+      module => {
+        return Err(From::from(format!("Unknown import module: {}", module)));
+      }
+    };
+
+    let (iovs, iovs_len, result_address) = (args[1] as usize, args[2] as usize, args[3] as usize);
+    let mut transferred = 0;
+
+    for i in 0..iovs_len {
+      let iovec = check_bounds(memory, iovs + i * 8, 8)?;
+      let buffer = LittleEndian::read_u32(&memory[iovec..]) as usize;
+      let length = LittleEndian::read_u32(&memory[iovec + 4..]) as usize;
+      let buffer = check_bounds(memory, buffer, length)?;
+
+      for address in buffer..buffer + length {
+        match field {
+          "fd_write" => self.io.print(memory[address]),
+          "fd_read" => memory[address] = self.io.read(),
+          other => {
+            return Err(From::from(format!("Unsupported WASI function: {}", other)));
+          }
+        }
+      }
+
+      transferred += length;
+    }
+
+    let result_address = check_bounds(memory, result_address, 4)?;
+    LittleEndian::write_u32(&mut memory[result_address..], transferred as u32);
+
+    // errno 0: success
+    Ok(Some(0))
+  }
+}
+
+fn check_bounds(memory: &[u8], address: usize, length: usize) -> Result<usize, Box<Error>> {
+  if address
+    .checked_add(length)
+    .map_or(true, |end| end > memory.len())
+  {
+    Err(From::from(format!(
+      "Out of bounds memory access at {}",
+      address
+    )))
+  } else {
+    Ok(address)
+  }
+}
+
+fn effective_address(
+  memory: &[u8],
+  base: i32,
+  offset: u32,
+  length: usize,
+) -> Result<usize, Box<Error>> {
+  check_bounds(memory, base as u32 as usize + offset as usize, length)
+}
+
+// A memory address of the form local + displacement + offset.
+#[derive(Clone, Copy, PartialEq)]
+struct Address {
+  local: u32,
+  displacement: i32,
+  offset: u32,
+}
+
+fn fused_address(memory: &[u8], locals: &[i32], address: Address) -> Result<usize, Box<Error>> {
+  let base = locals[address.local as usize].wrapping_add(address.displacement);
+  effective_address(memory, base, address.offset, 1)
+}
+
+// Instructions with their branch targets resolved ahead of time, so that running them doesn't
+// require keeping track of blocks. The sequences our backend emits for each ProgramToken are
+// fused into single ops, since executing them one by one is several times slower.
+#[derive(Clone, Copy)]
+enum Op {
+  GetLocal(u32),
+  SetLocal(u32),
+  GetGlobal(u32),
+  SetGlobal(u32),
+  Load8Signed(u32),
+  Load8Unsigned(u32),
+  Store8(u32),
+  Load32(u32),
+  Store32(u32),
+  PushI32(i32),
+  AddI32,
+  SubI32,
+  ShiftLeftI32,
+  ShiftRightUnsignedI32,
+  AndI32,
+  OrI32,
+  XorI32,
+  NotEqualsI32,
+  LessOrEqualSignedI32,
+  EqualsZeroI32,
+  MemorySize,
+  MemoryGrow,
+  Call(u32),
+  // Target index and the stack height of the target block.
+  BranchIf(usize, usize),
+  // Index of the function's branch table.
+  BranchTable(usize),
+  Drop,
+  Return,
+  Unreachable,
+  // PushI32 + AddI32
+  AddI32Immediate(i32),
+  // GetLocal + PushI32 + AddI32
+  GetLocalPlus(u32, i32),
+  // GetLocal + PushI32 + AddI32 + SetLocal
+  AddToLocal(u32, i32),
+  // GetLocal (+ PushI32 + AddI32) + Load8Unsigned
+  LoadMemory8(Address),
+  // A load, an addition and a store to the same address
+  AddToMemory8(Address, i32),
+  // LoadMemory8 (+ EqualsZeroI32) + BranchIf
+  BranchIfMemory8 {
+    address: Address,
+    if_zero: bool,
+    target: usize,
+    stack_height: usize,
+  },
+}
+
+struct Frame {
+  is_loop: bool,
+  start: usize,
+  stack_height: usize,
+  // Branches out of a block which jump to its end, which isn't known until we get there.
+  forward_branches: Vec<usize>,
+  // The same for entries of branch tables, as the table and entry index.
+  forward_table_entries: Vec<(usize, usize)>,
+}
+
+// The targets of a br_table with the stack height of each, followed by the default one.
+type BranchTable = Vec<(usize, usize)>;
+
+struct Signature {
+  params: usize,
+  results: usize,
+}
+
+fn local_plus(op: &Op) -> Option<(u32, i32)> {
+  match *op {
+    Op::GetLocal(local) => Some((local, 0)),
+    Op::GetLocalPlus(local, displacement) => Some((local, displacement)),
+    _ => None,
+  }
+}
+
+// Branch targets are always at a boundary, so ops before it must not be fused with ones after it.
+fn push_fused(ops: &mut Vec<Op>, boundary: usize, op: Op) {
+  let fused = match (&ops[boundary..], op) {
+    (&[.., Op::PushI32(value)], Op::AddI32) => Some((1, Op::AddI32Immediate(value))),
+    (&[.., Op::GetLocal(local)], Op::AddI32Immediate(value)) => {
+      Some((1, Op::GetLocalPlus(local, value)))
+    }
+    (&[.., Op::GetLocalPlus(a, value)], Op::SetLocal(b)) if a == b => {
+      Some((1, Op::AddToLocal(a, value)))
+    }
+    (&[.., ref base], Op::Load8Unsigned(offset)) if local_plus(base).is_some() => {
+      let (local, displacement) = local_plus(base).unwrap();
+      Some((
+        1,
+        Op::LoadMemory8(Address {
+          local,
+          displacement,
+          offset,
+        }),
+      ))
+    }
+    (&[.., ref base, Op::LoadMemory8(address), Op::AddI32Immediate(value)], Op::Store8(offset))
+      if local_plus(base) == Some((address.local, address.displacement))
+        && address.offset == offset =>
+    {
+      Some((3, Op::AddToMemory8(address, value)))
+    }
+    _ => None,
+  };
+
+  match fused {
+    Some((replaced, fused_op)) => {
+      let len = ops.len();
+      ops.truncate(len - replaced);
+      push_fused(ops, boundary, fused_op);
+    }
+    None => ops.push(op),
+  }
+}
+
+fn translate_body(
+  code: &[Instruction],
+  signatures: &[Signature],
+) -> Result<(Vec<Op>, Vec<BranchTable>), Box<Error>> {
+  let mut ops = Vec::with_capacity(code.len());
+  let mut tables: Vec<BranchTable> = Vec::new();
+  let mut frames: Vec<Frame> = Vec::new();
+  let mut stack_height: usize = 0;
+  let mut boundary = 0;
+
+  for instruction in code {
+    let (op, popped, pushed) = match *instruction {
+      Instruction::GetLocal(handle) => (Op::GetLocal(handle.0), 0, 1),
+      Instruction::SetLocal(handle) => (Op::SetLocal(handle.0), 1, 0),
+      Instruction::GetGlobal(index) => (Op::GetGlobal(index), 0, 1),
+      Instruction::SetGlobal(index) => (Op::SetGlobal(index), 1, 0),
+      Instruction::Load8Signed(offset) => (Op::Load8Signed(offset), 1, 1),
+      Instruction::Load8Unsigned(offset) => (Op::Load8Unsigned(offset), 1, 1),
+      Instruction::Store8(offset) => (Op::Store8(offset), 2, 0),
+      Instruction::Load32(offset) => (Op::Load32(offset), 1, 1),
+      Instruction::Store32(offset) => (Op::Store32(offset), 2, 0),
+      Instruction::PushI32(value) => (Op::PushI32(value), 0, 1),
+      Instruction::AddI32 => (Op::AddI32, 2, 1),
+      Instruction::SubI32 => (Op::SubI32, 2, 1),
+      Instruction::ShiftLeftI32 => (Op::ShiftLeftI32, 2, 1),
+      Instruction::ShiftRightUnsignedI32 => (Op::ShiftRightUnsignedI32, 2, 1),
+      Instruction::AndI32 => (Op::AndI32, 2, 1),
+      Instruction::OrI32 => (Op::OrI32, 2, 1),
+      Instruction::XorI32 => (Op::XorI32, 2, 1),
+      Instruction::NotEqualsI32 => (Op::NotEqualsI32, 2, 1),
+      Instruction::LessOrEqualSignedI32 => (Op::LessOrEqualSignedI32, 2, 1),
+      Instruction::EqualsZeroI32 => (Op::EqualsZeroI32, 1, 1),
+      Instruction::MemorySize => (Op::MemorySize, 0, 1),
+      Instruction::MemoryGrow => (Op::MemoryGrow, 1, 1),
+      Instruction::Call(function) => {
+        let signature = signatures
+          .get(function as usize)
+          .ok_or("Call to a missing function.")?;
+        (Op::Call(function), signature.params, signature.results)
+      }
+      Instruction::Drop => (Op::Drop, 1, 0),
+      Instruction::Return => (Op::Return, 0, 0),
+      Instruction::Unreachable => (Op::Unreachable, 0, 0),
+      Instruction::Block | Instruction::Loop => {
+        frames.push(Frame {
+          is_loop: *instruction == Instruction::Loop,
+          start: ops.len(),
+          stack_height,
+          forward_branches: Vec::new(),
+          forward_table_entries: Vec::new(),
+        });
+        boundary = ops.len();
+        continue;
+      }
+      Instruction::End => {
+        match frames.pop() {
+          Some(frame) => {
+            let end = ops.len();
+
+            for branch in frame.forward_branches {
+              match ops[branch] {
+                Op::BranchIf(ref mut target, _) | Op::BranchIfMemory8 { ref mut target, .. } => {
+                  *target = end;
+                }
+                _ => unreachable!(),
+              }
+            }
+
+            for (table, entry) in frame.forward_table_entries {
+              tables[table][entry].0 = end;
+            }
+          }
+          // The end of the function itself.
+          None => ops.push(Op::Return),
+        }
+        boundary = ops.len();
+        continue;
+      }
+      Instruction::BranchIf(depth) => {
+        let target = frames
+          .len()
+          .checked_sub(depth as usize + 1)
+          .ok_or("Branch to a missing label.")?;
+        let frame = &mut frames[target];
+
+        stack_height = stack_height
+          .checked_sub(1)
+          .ok_or("Value stack underflow.")?;
+
+        let target = if frame.is_loop { frame.start } else { 0 };
+
+        let fused = match ops[boundary..] {
+          [.., Op::LoadMemory8(address)] => Some((1, address, false)),
+          [.., Op::LoadMemory8(address), Op::EqualsZeroI32] => Some((2, address, true)),
+          _ => None,
+        };
+
+        let branch = match fused {
+          Some((replaced, address, if_zero)) => {
+            let len = ops.len();
+            ops.truncate(len - replaced);
+
+            Op::BranchIfMemory8 {
+              address,
+              if_zero,
+              target,
+              stack_height: frame.stack_height,
+            }
+          }
+          None => Op::BranchIf(target, frame.stack_height),
+        };
+
+        if !frame.is_loop {
+          frame.forward_branches.push(ops.len());
+        }
+
+        ops.push(branch);
+        continue;
+      }
+      Instruction::BranchTable(ref depths, default) => {
+        stack_height = stack_height
+          .checked_sub(1)
+          .ok_or("Value stack underflow.")?;
+
+        let table = tables.len();
+        let mut targets = Vec::with_capacity(depths.len() + 1);
+
+        for (entry, &depth) in depths.iter().chain(Some(&default)).enumerate() {
+          let target = frames
+            .len()
+            .checked_sub(depth as usize + 1)
+            .ok_or("Branch to a missing label.")?;
+          let frame = &mut frames[target];
+
+          if frame.is_loop {
+            targets.push((frame.start, frame.stack_height));
+          } else {
+            frame.forward_table_entries.push((table, entry));
+            targets.push((0, frame.stack_height));
+          }
+        }
+
+        tables.push(targets);
+        ops.push(Op::BranchTable(table));
+        boundary = ops.len();
+        continue;
+      }
+    };
+
+    stack_height = stack_height
+      .checked_sub(popped)
+      .ok_or("Value stack underflow.")?
+      + pushed;
+    push_fused(&mut ops, boundary, op);
+  }
+
+  if !frames.is_empty() {
+    return Err(From::from("Unterminated block."));
+  }
+
+  Ok((ops, tables))
+}
+
+struct Function {
+  local_count: usize,
+  ops: Vec<Op>,
+  tables: Vec<BranchTable>,
+}
+
+struct Program<'a> {
+  imports: &'a [Import],
+  // Signatures of every function, including imported ones.
+  signatures: Vec<Signature>,
+  functions: Vec<Function>,
+  max_pages: u32,
+}
+
+impl<'a> Program<'a> {
+  fn call(
+    &self,
+    memory: &mut Vec<u8>,
+    globals: &mut [i32],
+    imports: &mut dyn HostImports,
+    function: usize,
+    args: Vec<i32>,
+    depth: usize,
+  ) -> Result<Option<i32>, Box<Error>> {
+    if function < self.imports.len() {
+      return imports.call_import(&self.imports[function], &args, memory);
+    }
+
+    if depth >= MAX_CALL_DEPTH {
+      return Err(From::from("Call stack exhausted."));
+    }
+
+    let Function {
+      local_count,
+      ref ops,
+      ref tables,
+    } = self.functions[function - self.imports.len()];
+
+    let mut locals = args;
+    locals.extend((0..local_count).map(|_| 0));
+
+    let mut stack: Vec<i32> = Vec::with_capacity(16);
+    let mut pc = 0;
+
+    macro_rules! pop {
+      () => {
+        stack.pop().ok_or("Value stack underflow.")?
+      };
+    }
+
+    loop {
+      let op = ops[pc];
+      pc += 1;
+
+      match op {
+        Op::GetLocal(local) => stack.push(locals[local as usize]),
+        Op::SetLocal(local) => locals[local as usize] = pop!(),
+        Op::GetGlobal(global) => {
+          let value = *globals.get(global as usize).ok_or("Missing global.")?;
+          stack.push(value);
+        }
+        Op::SetGlobal(global) => {
+          let value = pop!();
+          *globals.get_mut(global as usize).ok_or("Missing global.")? = value;
+        }
+        Op::Load8Signed(offset) => {
+          let address = effective_address(memory, pop!(), offset, 1)?;
+          stack.push(memory[address] as i8 as i32);
+        }
+        Op::Load8Unsigned(offset) => {
+          let address = effective_address(memory, pop!(), offset, 1)?;
+          stack.push(memory[address] as i32);
+        }
+        Op::Store8(offset) => {
+          let value = pop!();
+          let address = effective_address(memory, pop!(), offset, 1)?;
+          memory[address] = value as u8;
+        }
+        Op::Load32(offset) => {
+          let address = effective_address(memory, pop!(), offset, 4)?;
+          stack.push(LittleEndian::read_i32(&memory[address..]));
+        }
+        Op::Store32(offset) => {
+          let value = pop!();
+          let address = effective_address(memory, pop!(), offset, 4)?;
+          LittleEndian::write_i32(&mut memory[address..], value);
+        }
+        Op::PushI32(value) => stack.push(value),
+        Op::AddI32 => {
+          let b = pop!();
+          let a = pop!();
+          stack.push(a.wrapping_add(b));
+        }
+        Op::SubI32 => {
+          let b = pop!();
+          let a = pop!();
+          stack.push(a.wrapping_sub(b));
+        }
+        Op::ShiftLeftI32 => {
+          let b = pop!();
+          let a = pop!();
+          stack.push(a.wrapping_shl(b as u32 % 32));
+        }
+        Op::ShiftRightUnsignedI32 => {
+          let b = pop!();
+          let a = pop!();
+          stack.push(((a as u32) >> (b as u32 % 32)) as i32);
+        }
+        Op::AndI32 => {
+          let b = pop!();
+          let a = pop!();
+          stack.push(a & b);
+        }
+        Op::OrI32 => {
+          let b = pop!();
+          let a = pop!();
+          stack.push(a | b);
+        }
+        Op::XorI32 => {
+          let b = pop!();
+          let a = pop!();
+          stack.push(a ^ b);
+        }
+        Op::NotEqualsI32 => {
+          let b = pop!();
+          let a = pop!();
+          stack.push((a != b) as i32);
+        }
+        Op::LessOrEqualSignedI32 => {
+          let b = pop!();
+          let a = pop!();
+          stack.push((a <= b) as i32);
+        }
+        Op::EqualsZeroI32 => {
+          let a = pop!();
+          stack.push((a == 0) as i32);
+        }
+        Op::MemorySize => stack.push((memory.len() / PAGE_SIZE) as i32),
+        Op::MemoryGrow => {
+          let delta = pop!() as u32;
+          let pages = (memory.len() / PAGE_SIZE) as u32;
+
+          match pages.checked_add(delta) {
+            Some(new_pages) if new_pages <= self.max_pages => {
+              memory.resize(new_pages as usize * PAGE_SIZE, 0);
+              stack.push(pages as i32);
+            }
+            _ => stack.push(-1),
+          }
+        }
+        Op::Call(target) => {
+          let param_count = self.signatures[target as usize].params;
+          let call_args = stack.split_off(stack.len() - param_count);
+
+          let result = self.call(
+            memory,
+            globals,
+            imports,
+            target as usize,
+            call_args,
+            depth + 1,
+          )?;
+
+          if let Some(result) = result {
+            stack.push(result);
+          }
+        }
+        Op::BranchIf(target, stack_height) => {
+          if pop!() != 0 {
+            stack.truncate(stack_height);
+            pc = target;
+          }
+        }
+        Op::BranchTable(table) => {
+          let targets = &tables[table];
+          let index = (pop!() as u32 as usize).min(targets.len() - 1);
+          let (target, stack_height) = targets[index];
+          stack.truncate(stack_height);
+          pc = target;
+        }
+        Op::Drop => {
+          pop!();
+        }
+        Op::Return => {
+          break;
+        }
+        Op::Unreachable => {
+          return Err(From::from("Reached unreachable code."));
+        }
+        Op::AddI32Immediate(value) => {
+          let a = pop!();
+          stack.push(a.wrapping_add(value));
+        }
+        Op::GetLocalPlus(local, value) => {
+          stack.push(locals[local as usize].wrapping_add(value));
+        }
+        Op::AddToLocal(local, value) => {
+          locals[local as usize] = locals[local as usize].wrapping_add(value);
+        }
+        Op::LoadMemory8(address) => {
+          let address = fused_address(memory, &locals, address)?;
+          stack.push(memory[address] as i32);
+        }
+        Op::AddToMemory8(address, value) => {
+          let address = fused_address(memory, &locals, address)?;
+          memory[address] = (memory[address] as i32).wrapping_add(value) as u8;
+        }
+        Op::BranchIfMemory8 {
+          address,
+          if_zero,
+          target,
+          stack_height,
+        } => {
+          let address = fused_address(memory, &locals, address)?;
+          if (memory[address] == 0) == if_zero {
+            stack.truncate(stack_height);
+            pc = target;
+          }
+        }
+      }
+    }
+
+    if self.signatures[function].results > 0 {
+      Ok(Some(pop!()))
+    } else {
+      Ok(None)
+    }
+  }
+}
+
+pub struct Instance<'a> {
+  module: &'a Module,
+  program: Program<'a>,
+  pub memory: Vec<u8>,
+  pub globals: Vec<i32>,
+}
+
+impl<'a> Instance<'a> {
+  pub fn new(module: &'a Module) -> Result<Instance<'a>, Box<Error>> {
+    let type_indices = module
+      .imports
+      .iter()
+      .map(|import| import.type_index)
+      .chain(module.functions.iter().cloned());
+
+    let mut signatures = Vec::new();
+
+    for type_index in type_indices {
+      let function_type = module
+        .types
+        .get(type_index as usize)
+        .ok_or("Function refers to a missing type.")?;
+
+      signatures.push(Signature {
+        params: function_type.params.len(),
+        results: function_type.results.len(),
+      });
+    }
+
+    let mut functions = Vec::with_capacity(module.bodies.len());
+
+    for body in &module.bodies {
+      let (ops, tables) = translate_body(&body.code, &signatures)?;
+
+      functions.push(Function {
+        local_count: body.locals.len(),
+        ops,
+        tables,
+      });
+    }
+
+    let pages = module.memories.first().map_or(0, |memory| memory.initial);
+    let max_pages = module
+      .memories
+      .first()
+      .and_then(|memory| memory.maximum)
+      .unwrap_or(MAX_PAGES);
+
+    Ok(Instance {
+      module,
+      program: Program {
+        imports: &module.imports,
+        signatures,
+        functions,
+        max_pages,
+      },
+      memory: vec![0u8; pages as usize * PAGE_SIZE],
+      globals: module.globals.iter().map(|global| global.initial).collect(),
+    })
+  }
+
+  pub fn invoke(
+    &mut self,
+    name: &str,
+    imports: &mut dyn HostImports,
+  ) -> Result<Option<i32>, Box<Error>> {
+    let function = self
+      .module
+      .exports
+      .iter()
+      .find(|export| export.name == name && export.kind == ExternalKind::Function)
+      .ok_or_else(|| format!("No exported function called {}", name))?
+      .index;
+
+    self.program.call(
+      &mut self.memory,
+      &mut self.globals,
+      imports,
+      function as usize,
+      Vec::new(),
+      0,
+    )
+  }
+}
+
+// Runs a module built for WasmTarget::Bfcrs and returns its final state.
+pub fn run_wasm(code: &[u8], io: &mut impl BfIo) -> Result<State, Box<Error>> {
+  let module = decode_module(code)?;
+  let mut instance = Instance::new(&module)?;
+  let pointer = instance
+    .invoke("main", &mut BfcrsImports { io })?
+    .ok_or("main should return the pointer.")?;
+
+  Ok(State {
+    pointer: pointer as u32 as usize,
+    memory: instance.memory,
+  })
+}
+
+// Runs a module built for WasmTarget::Wasi and returns its linear memory.
+pub fn run_wasi(code: &[u8], io: &mut impl BfIo) -> Result<Vec<u8>, Box<Error>> {
+  let module = decode_module(code)?;
+  let mut instance = Instance::new(&module)?;
+  instance.invoke("_start", &mut WasiImports { io })?;
+  Ok(instance.memory)
+}
@@ -4,17 +4,41 @@ use backend::Backend;
 use types::ProgramToken;
 
 pub mod code_stream;
+pub mod decoder;
+pub mod executor;
 mod module_builder;
-use self::module_builder::WasmModule;
+pub mod wat;
+pub use self::module_builder::{source_hash, DebugInfo, WasmModule, WasmOptions, WasmTarget};
 
 pub struct WasmBackend;
 
+// Produces modules which run directly under any WASI runtime, without our JS glue.
+pub struct WasiBackend;
+
 impl Backend for WasmBackend {
   fn extension(&self) -> &'static str {
     ".wasm"
   }
 
+  fn supports_procedures(&self) -> bool {
+    true
+  }
+
   fn compile_to_stream(&self, tokens: &[ProgramToken], stream: &mut dyn Write) {
     WasmModule::write_to_stream(stream, tokens).unwrap();
   }
 }
+
+impl Backend for WasiBackend {
+  fn extension(&self) -> &'static str {
+    ".wasm"
+  }
+
+  fn supports_procedures(&self) -> bool {
+    true
+  }
+
+  fn compile_to_stream(&self, tokens: &[ProgramToken], stream: &mut dyn Write) {
+    WasmModule::write_target_to_stream(stream, tokens, WasmTarget::Wasi).unwrap();
+  }
+}
@@ -6,6 +6,7 @@ use byteorder::{LittleEndian, WriteBytesExt};
 use backend::wasm::code_stream::LocalHandle;
 use backend::wasm::code_stream::{CodeStreamExt, CodeStreamWriter, Instruction};
 
+use optimizer::{uses_procedures, uses_storage};
 use types::MemoryOp::*;
 use types::ProgramToken;
 use types::ProgramToken::*;
@@ -37,9 +38,18 @@ impl ModuleBuilder {
     self.sections.push(Section { id, data });
   }
 
+  pub fn add_custom_section(&mut self, name: &str, payload: &[u8]) {
+    let mut data = Vec::new();
+    data.write_str(name);
+    data.write(payload).unwrap();
+
+    self.sections.push(Section { id: 0, data });
+  }
+
   pub fn write_to_stream(mut self, mut stream: &mut dyn Write) -> Result<(), Box<Error>> {
     let sorted = self.sections.as_mut_slice();
-    sorted.sort_unstable_by_key(|x| x.id);
+    // Custom sections go after everything else, in the order they were added.
+    sorted.sort_by_key(|x| if x.id == 0 { u8::max_value() } else { x.id });
 
     // Header:
     // Magic
@@ -59,19 +69,81 @@ impl ModuleBuilder {
 
 pub struct WasmModule;
 
-fn emit_token<T: Write>(
-  writer: &mut CodeStreamWriter<T>,
+#[derive(Copy, Clone, PartialEq)]
+pub enum WasmTarget {
+  // Imports bfcrs.print and bfcrs.read, which are provided by wasm_test_runner.
+  Bfcrs,
+  // Imports fd_write and fd_read from WASI and exports _start.
+  Wasi,
+}
+
+#[derive(Copy, Clone)]
+pub struct WasmOptions {
+  pub target: WasmTarget,
+  // When set, the memory grows whenever the pointer moves past its end, up to this many pages.
+  pub max_pages: Option<u32>,
+}
+
+impl WasmOptions {
+  pub fn new(target: WasmTarget) -> WasmOptions {
+    WasmOptions {
+      target,
+      max_pages: None,
+    }
+  }
+}
+
+pub struct DebugInfo<'a> {
+  pub source: &'a str,
+  // Compiler options as key-value pairs, e.g. ("target", "wasm").
+  pub options: Vec<(String, String)>,
+  pub embed_source: bool,
+  // Source offset of every program token in pre-order. Only known for unoptimized programs.
+  pub token_offsets: Option<Vec<usize>>,
+}
+
+struct EmitContext<'a> {
+  target: WasmTarget,
   pointer: LocalHandle,
-  token: &ProgramToken,
+  print_function: u32,
+  grow_function: Option<u32>,
+  // The largest offset from the pointer the program accesses.
+  reach: i32,
+  // The smallest offset from the pointer the program accesses, at most 0.
+  low_reach: i32,
+  // Where the tape starts and ends, if the program has to trap before it moves out of it.
+  tape_start: Option<i32>,
+  tape_end: Option<i32>,
+  // Code offset of every emitted token in pre-order.
+  token_offsets: Vec<usize>,
+  procedure_table: u32,
+  dispatch_function: u32,
+  // The body of every procedure definition found so far. Each becomes a function after dispatch.
+  procedures: Vec<&'a [ProgramToken]>,
+}
+
+fn emit_token<'a, T: Write>(
+  writer: &mut CodeStreamWriter<T>,
+  context: &mut EmitContext<'a>,
+  token: &'a ProgramToken,
 ) -> Result<(), Box<Error>> {
   use self::Instruction::*;
 
+  let pointer = context.pointer;
+  context.token_offsets.push(writer.position());
+
   match token {
     ChangeAddr(by) => {
       writer.emit(GetLocal(pointer))?;
       writer.emit(PushI32(*by as i32))?;
       writer.emit(AddI32)?;
       writer.emit(SetLocal(pointer))?;
+
+      if *by > 0 {
+        emit_grow_check(writer, context)?;
+      } else if *by < 0 {
+        emit_start_check(writer, context)?;
+      }
     }
     Offset(0, ChangeValue(value)) => {
       writer.emit(GetLocal(pointer))?;
@@ -117,12 +189,12 @@ fn emit_token<T: Write>(
     Offset(0, Print) => {
       writer.emit(GetLocal(pointer))?;
       writer.emit(Load8Unsigned(0))?;
-      writer.emit(Call(0))?;
+      writer.emit(Call(context.print_function))?;
     }
     Offset(addr_offset, Print) if *addr_offset > 0 => {
       writer.emit(GetLocal(pointer))?;
       writer.emit(Load8Unsigned(*addr_offset as u32))?;
-      writer.emit(Call(0))?;
+      writer.emit(Call(context.print_function))?;
     }
     Offset(addr_offset, Print) => {
       writer.emit(GetLocal(pointer))?;
@@ -130,7 +202,10 @@ fn emit_token<T: Write>(
       writer.emit(AddI32)?;
 
       writer.emit(Load8Unsigned(0))?;
-      writer.emit(Call(0))?;
+      writer.emit(Call(context.print_function))?;
+    }
+    Offset(addr_offset, Read) => {
+      emit_read(writer, context, *addr_offset)?;
     }
     Offset(0, SetValue(value)) => {
       writer.emit(GetLocal(pointer))?;
@@ -149,71 +224,227 @@ fn emit_token<T: Write>(
       writer.emit(PushI32(*value as i32))?;
       writer.emit(Store8(0))?;
     }
-    ProgramToken::Loop(body) => {
-      // This is essentially compiled into the following pseudocode:
-      // if memory[pointer] != 0 {
-      //   do {
-      //     * stuff*
-      //   } while memory[pointer != 0]
-      // }
+    Offset(addr_offset, Store) => {
+      let immediate = emit_cell_address(writer, pointer, *addr_offset)?;
+      writer.emit(Load8Unsigned(immediate))?;
+      writer.emit(SetGlobal(STORAGE_GLOBAL))?;
+    }
+    Offset(addr_offset, Retrieve) => {
+      let immediate = emit_cell_address(writer, pointer, *addr_offset)?;
+      writer.emit(GetGlobal(STORAGE_GLOBAL))?;
+      writer.emit(Store8(immediate))?;
+    }
+    Offset(addr_offset, op) => {
+      // Push the address for the store, then compute the value. Store8 drops any bits that
+      // were shifted out of the byte.
+      let immediate = emit_cell_address(writer, pointer, *addr_offset)?;
+      emit_cell_address(writer, pointer, *addr_offset)?;
+      writer.emit(Load8Unsigned(immediate))?;
 
-      writer.emit(Block)?;
+      match op {
+        ShiftLeft => {
+          writer.emit(PushI32(1))?;
+          writer.emit(ShiftLeftI32)?;
+        }
+        ShiftRight => {
+          writer.emit(PushI32(1))?;
+          writer.emit(ShiftRightUnsignedI32)?;
+        }
+        Not => {
+          writer.emit(PushI32(-1))?;
+          writer.emit(XorI32)?;
+        }
+        Xor | And | Or => {
+          writer.emit(GetGlobal(STORAGE_GLOBAL))?;
+          writer.emit(match op {
+            Xor => XorI32,
+            And => AndI32,
+            _ => OrI32,
+          })?;
+        }
+        _ => unreachable!(),
+      }
 
+      writer.emit(Store8(immediate))?;
+    }
+    ProgramToken::Loop(body) => emit_loop(writer, context, 0, body)?,
+    OffsetLoop(offset, body) => emit_loop(writer, context, *offset, body)?,
+    Procedure(body) => {
+      // table[cell] = definition + 1
       writer.emit(GetLocal(pointer))?;
       writer.emit(Load8Unsigned(0))?;
-      writer.emit(EqualsZeroI32)?;
-      writer.emit(BranchIf(0))?;
+      writer.emit(PushI32(2))?;
+      writer.emit(ShiftLeftI32)?;
+      writer.emit(PushI32(context.procedures.len() as i32 + 1))?;
+      writer.emit(Store32(context.procedure_table))?;
+
+      context.procedures.push(body);
+    }
+    ProgramToken::Call => {
+      writer.emit(GetLocal(pointer))?;
+      writer.emit(Call(context.dispatch_function))?;
+      writer.emit(SetLocal(pointer))?;
+    }
+  }
+  Ok(())
+}
 
-      writer.emit(Loop)?;
+fn emit_loop<'a, T: Write>(
+  writer: &mut CodeStreamWriter<T>,
+  context: &mut EmitContext<'a>,
+  offset: isize,
+  body: &'a [ProgramToken],
+) -> Result<(), Box<Error>> {
+  use self::Instruction::*;
 
-      for token in body {
-        emit_token(writer, pointer, token)?;
+  let pointer = context.pointer;
+
+  // This is essentially compiled into the following pseudocode:
+  // if memory[pointer + offset] != 0 {
+  //   do {
+  //     * stuff*
+  //   } while memory[pointer + offset] != 0
+  // }
+
+  writer.emit(Block)?;
+
+  let immediate = emit_cell_address(writer, pointer, offset)?;
+  writer.emit(Load8Unsigned(immediate))?;
+  writer.emit(EqualsZeroI32)?;
+  writer.emit(BranchIf(0))?;
+
+  writer.emit(Loop)?;
+
+  for token in body {
+    emit_token(writer, context, token)?;
+  }
+
+  let immediate = emit_cell_address(writer, pointer, offset)?;
+  writer.emit(Load8Unsigned(immediate))?;
+  writer.emit(BranchIf(0))?;
+
+  writer.emit(End)?;
+  writer.emit(End)?;
+  Ok(())
+}
+
+// Pushes the address of the cell at the given offset from the pointer, and returns the offset
+// that still has to be added by the load or store.
+fn emit_cell_address<T: Write>(
+  writer: &mut CodeStreamWriter<T>,
+  pointer: LocalHandle,
+  offset: isize,
+) -> Result<u32, Box<Error>> {
+  use self::Instruction::*;
+
+  writer.emit(GetLocal(pointer))?;
+
+  if offset >= 0 {
+    return Ok(offset as u32);
+  }
+
+  writer.emit(PushI32(offset as i32))?;
+  writer.emit(AddI32)?;
+  Ok(0)
+}
+
+fn emit_read<T: Write>(
+  writer: &mut CodeStreamWriter<T>,
+  context: &EmitContext,
+  offset: isize,
+) -> Result<(), Box<Error>> {
+  use self::Instruction::*;
+
+  match context.target {
+    WasmTarget::Bfcrs => {
+      let immediate = emit_cell_address(writer, context.pointer, offset)?;
+      writer.emit(Call(BFCRS_READ))?;
+      writer.emit(Store8(immediate))?;
+    }
+    WasmTarget::Wasi => {
+      // Prompts should be visible before waiting for input.
+      writer.emit(Call(WASI_FLUSH))?;
+
+      // cell = 0, which is what's left at the end of the input
+      let immediate = emit_cell_address(writer, context.pointer, offset)?;
+      writer.emit(PushI32(0))?;
+      writer.emit(Store8(immediate))?;
+
+      // iovec.buf = &cell
+      writer.emit(PushI32(0))?;
+      emit_cell_address(writer, context.pointer, offset)?;
+      if immediate > 0 {
+        writer.emit(PushI32(immediate as i32))?;
+        writer.emit(AddI32)?;
       }
+      writer.emit(Store32(WASI_IOVEC))?;
 
-      writer.emit(GetLocal(pointer))?;
-      writer.emit(Load8Unsigned(0))?;
-      writer.emit(BranchIf(0))?;
+      // iovec.len = 1
+      writer.emit(PushI32(0))?;
+      writer.emit(PushI32(1))?;
+      writer.emit(Store32(WASI_IOVEC + 4))?;
 
-      writer.emit(End)?;
-      writer.emit(End)?;
+      // fd_read(stdin, &iovec, 1, &nread)
+      writer.emit(PushI32(0))?;
+      writer.emit(PushI32(WASI_IOVEC as i32))?;
+      writer.emit(PushI32(1))?;
+      writer.emit(PushI32(WASI_NWRITTEN as i32))?;
+      writer.emit(Call(WASI_FD_READ))?;
+      writer.emit(Drop)?;
     }
   }
+
   Ok(())
 }
 
-fn add_type_section(builder: &mut ModuleBuilder) {
-  builder.add_section(
-    1,
-    &[
-      // print
-      Box::new(|mut writer| {
-        writer.write_u8(0x60).unwrap();
-        // One integer param
-        writer.write_leb_u32(1);
-        writer.write_u8(0x7F).unwrap();
-        // Doesn't read anything
-        writer.write_leb_u32(0);
-      }),
-      // read
-      Box::new(|mut writer| {
-        writer.write_u8(0x60).unwrap();
-        // No params
-        writer.write_leb_u32(0);
-        // Returns an integer
-        writer.write_leb_u32(1);
-        writer.write_u8(0x7F).unwrap();
-      }),
-      // Main
-      Box::new(|mut writer| {
-        writer.write_u8(0x60).unwrap();
-        // No params
-        writer.write_leb_u32(0);
-        // Returns the instruction pointer
-        writer.write_leb_u32(1);
-        writer.write_u8(0x7F).unwrap();
-      }),
-    ],
-  )
+// Both targets have three types before this one.
+const PROCEDURE_TYPE: u32 = 3;
+
+// dispatch and the procedures take the pointer and return where they left it.
+fn write_procedure_type(mut writer: &mut dyn Write) {
+  writer.write_u8(0x60).unwrap();
+  writer.write_leb_u32(1);
+  writer.write_u8(0x7F).unwrap();
+  writer.write_leb_u32(1);
+  writer.write_u8(0x7F).unwrap();
+}
+
+fn add_type_section(builder: &mut ModuleBuilder, procedures: bool) {
+  let mut entries: Vec<Box<Fn(&mut dyn Write)>> = vec![
+    // print
+    Box::new(|mut writer| {
+      writer.write_u8(0x60).unwrap();
+      // One integer param
+      writer.write_leb_u32(1);
+      writer.write_u8(0x7F).unwrap();
+      // Doesn't read anything
+      writer.write_leb_u32(0);
+    }),
+    // read
+    Box::new(|mut writer| {
+      writer.write_u8(0x60).unwrap();
+      // No params
+      writer.write_leb_u32(0);
+      // Returns an integer
+      writer.write_leb_u32(1);
+      writer.write_u8(0x7F).unwrap();
+    }),
+    // Main
+    Box::new(|mut writer| {
+      writer.write_u8(0x60).unwrap();
+      // No params
+      writer.write_leb_u32(0);
+      // Returns the instruction pointer
+      writer.write_leb_u32(1);
+      writer.write_u8(0x7F).unwrap();
+    }),
+  ];
+
+  if procedures {
+    entries.push(Box::new(write_procedure_type));
+  }
+
+  builder.add_section(1, &entries);
 }
 
 fn add_import_section(builder: &mut ModuleBuilder) {
@@ -236,27 +467,70 @@ fn add_import_section(builder: &mut ModuleBuilder) {
   );
 }
 
-fn add_memory_section(builder: &mut ModuleBuilder, page_count: u32) {
+fn add_memory_section(builder: &mut ModuleBuilder, page_count: u32, max_pages: Option<u32>) {
   builder.add_section(
     5,
-    &[Box::new(move |mut writer| {
-      // resizable_limits.flags
-      writer.write_u8(0).unwrap();
-      // resizable_limits.initial
-      writer.write_leb_u32(page_count);
+    &[Box::new(move |mut writer| match max_pages {
+      Some(max_pages) => {
+        // resizable_limits.flags, 1 means there's a maximum
+        writer.write_u8(1).unwrap();
+        writer.write_leb_u32(page_count);
+        writer.write_leb_u32(max_pages);
+      }
+      None => {
+        // resizable_limits.flags
+        writer.write_u8(0).unwrap();
+        // resizable_limits.initial
+        writer.write_leb_u32(page_count);
+      }
     })],
   );
 }
 
-fn add_function_section(builder: &mut ModuleBuilder) {
+// The storage byte of Extended Brainfuck Type I is kept in a global, so that procedures share it.
+const STORAGE_GLOBAL: u32 = 0;
+
+fn add_global_section(builder: &mut ModuleBuilder) {
   builder.add_section(
-    3,
+    6,
     &[Box::new(|writer| {
-      writer.write_u8(2).unwrap();
+      // A mutable i32
+      writer.write_u8(0x7F).unwrap();
+      writer.write_u8(1).unwrap();
+
+      // Initialized to 0
+      let mut init: Vec<u8> = Vec::new();
+      {
+        let mut init_writer = CodeStreamWriter::new(&mut init);
+        init_writer.emit(Instruction::PushI32(0)).unwrap();
+        init_writer.emit(Instruction::End).unwrap();
+      }
+      writer.write(&init).unwrap();
     })],
   );
 }
 
+fn add_function_section(builder: &mut ModuleBuilder, growable: bool, procedure_functions: usize) {
+  let mut entries: Vec<Box<Fn(&mut dyn Write)>> = vec![Box::new(|writer| {
+    writer.write_u8(2).unwrap();
+  })];
+
+  if growable {
+    // grow has the same signature as print.
+    entries.push(Box::new(|writer| {
+      writer.write_u8(0).unwrap();
+    }));
+  }
+
+  for _ in 0..procedure_functions {
+    entries.push(Box::new(|mut writer| {
+      writer.write_leb_u32(PROCEDURE_TYPE);
+    }));
+  }
+
+  builder.add_section(3, &entries);
+}
+
 fn add_export_section(builder: &mut ModuleBuilder) {
   builder.add_section(
     7,
@@ -275,58 +549,832 @@ fn add_export_section(builder: &mut ModuleBuilder) {
   );
 }
 
-fn add_code_section(builder: &mut ModuleBuilder, tokens: Vec<ProgramToken>) {
-  builder.add_section(
-    10,
-    &[Box::new(move |writer| {
-      let mut code: Vec<u8> = Vec::new();
+fn write_function_body(writer: &mut dyn Write, local_count: u32, code: &[u8]) {
+  let mut code_body: Vec<u8> = Vec::new();
 
-      {
-        let mut writer = CodeStreamWriter::new(&mut code);
-        let pointer = writer.declare_local(WasmType::I32);
+  if local_count > 0 {
+    // Number of locals
+    code_body.write_leb_u32(1);
+    // Number of locals of this type
+    code_body.write_leb_u32(local_count);
+    // Type of the local
+    code_body.write_u8(0x7F).unwrap();
+  } else {
+    code_body.write_leb_u32(0);
+  }
 
-        for token in &tokens {
-          emit_token(&mut writer, pointer, token).unwrap();
-        }
+  code_body.write(code).unwrap();
+
+  let mut code_entry: Vec<u8> = Vec::new();
+  code_entry.write_leb_u32(code_body.len() as u32);
+  code_entry.write(&code_body).unwrap();
+
+  writer.write(&code_entry).unwrap();
+}
+
+fn max_offset(tokens: &[ProgramToken]) -> isize {
+  tokens
+    .iter()
+    .map(|token| match token {
+      ChangeAddr(_) => 0,
+      Offset(offset, _) => *offset,
+      ProgramToken::Loop(body) | Procedure(body) => max_offset(body),
+      OffsetLoop(offset, body) => max_offset(body).max(*offset),
+      ProgramToken::Call => 0,
+    })
+    .max()
+    .unwrap_or(0)
+    .max(0)
+}
+
+fn min_offset(tokens: &[ProgramToken]) -> isize {
+  tokens
+    .iter()
+    .map(|token| match token {
+      ChangeAddr(_) => 0,
+      Offset(offset, _) => *offset,
+      ProgramToken::Loop(body) | Procedure(body) => min_offset(body),
+      OffsetLoop(offset, body) => min_offset(body).min(*offset),
+      ProgramToken::Call => 0,
+    })
+    .min()
+    .unwrap_or(0)
+    .min(0)
+}
+
+// grow(address) makes sure the memory extends past the given address, or traps if it can't.
+fn emit_grow_function<T: Write>(writer: &mut CodeStreamWriter<T>) -> Result<(), Box<Error>> {
+  use self::Instruction::*;
+
+  let address = writer.declare_local(WasmType::I32);
+  let missing = writer.declare_local(WasmType::I32);
+
+  // missing = (address >> 16) + 1 - memory.size
+  writer.emit(GetLocal(address))?;
+  writer.emit(PushI32(16))?;
+  writer.emit(ShiftRightUnsignedI32)?;
+  writer.emit(PushI32(1))?;
+  writer.emit(AddI32)?;
+  writer.emit(MemorySize)?;
+  writer.emit(SubI32)?;
+  writer.emit(SetLocal(missing))?;
+
+  writer.emit(Block)?;
+
+  writer.emit(GetLocal(missing))?;
+  writer.emit(PushI32(0))?;
+  writer.emit(LessOrEqualSignedI32)?;
+  writer.emit(BranchIf(0))?;
+
+  // memory.grow returns -1 if the memory can't grow past its maximum.
+  writer.emit(GetLocal(missing))?;
+  writer.emit(MemoryGrow)?;
+  writer.emit(PushI32(-1))?;
+  writer.emit(NotEqualsI32)?;
+  writer.emit(BranchIf(0))?;
+  writer.emit(Unreachable)?;
+
+  writer.emit(End)?;
 
-        writer.emit(Instruction::GetLocal(pointer)).unwrap();
-        writer.emit(Instruction::Return).unwrap();
+  writer.emit(End)?;
+  Ok(())
+}
+
+fn emit_grow_check<T: Write>(
+  writer: &mut CodeStreamWriter<T>,
+  context: &EmitContext,
+) -> Result<(), Box<Error>> {
+  use self::Instruction::*;
+
+  if let Some(grow_function) = context.grow_function {
+    writer.emit(GetLocal(context.pointer))?;
+    writer.emit(PushI32(context.reach))?;
+    writer.emit(AddI32)?;
+    writer.emit(Call(grow_function))?;
+  }
+
+  if let Some(tape_end) = context.tape_end {
+    writer.emit(Block)?;
+    writer.emit(GetLocal(context.pointer))?;
+    writer.emit(PushI32(context.reach))?;
+    writer.emit(AddI32)?;
+    writer.emit(PushI32(tape_end - 1))?;
+    writer.emit(LessOrEqualSignedI32)?;
+    writer.emit(BranchIf(0))?;
+    writer.emit(Unreachable)?;
+    writer.emit(End)?;
+  }
+
+  Ok(())
+}
+
+fn emit_start_check<T: Write>(
+  writer: &mut CodeStreamWriter<T>,
+  context: &EmitContext,
+) -> Result<(), Box<Error>> {
+  use self::Instruction::*;
+
+  if let Some(tape_start) = context.tape_start {
+    writer.emit(Block)?;
+    writer.emit(PushI32(tape_start))?;
+    writer.emit(GetLocal(context.pointer))?;
+    writer.emit(PushI32(context.low_reach))?;
+    writer.emit(AddI32)?;
+    writer.emit(LessOrEqualSignedI32)?;
+    writer.emit(BranchIf(0))?;
+    writer.emit(Unreachable)?;
+    writer.emit(End)?;
+  }
+
+  Ok(())
+}
+
+// dispatch(pointer) calls the procedure numbered by the cell under the pointer and returns
+// where it left the pointer. The br_table jumps to the end of the block which calls the right
+// function, or to the innermost one if the procedure isn't defined, which traps.
+fn emit_dispatch_function<T: Write>(
+  writer: &mut CodeStreamWriter<T>,
+  procedure_table: u32,
+  first_procedure: u32,
+  procedure_count: u32,
+) -> Result<(), Box<Error>> {
+  use self::Instruction::*;
+
+  let pointer = writer.declare_local(WasmType::I32);
+
+  for _ in 0..procedure_count + 1 {
+    writer.emit(Block)?;
+  }
+
+  writer.emit(GetLocal(pointer))?;
+  writer.emit(Load8Unsigned(0))?;
+  writer.emit(PushI32(2))?;
+  writer.emit(ShiftLeftI32)?;
+  writer.emit(Load32(procedure_table))?;
+  writer.emit(BranchTable((0..procedure_count + 1).collect(), 0))?;
+  writer.emit(End)?;
+  writer.emit(Unreachable)?;
+
+  for procedure in 0..procedure_count {
+    writer.emit(End)?;
+    writer.emit(GetLocal(pointer))?;
+    writer.emit(Call(first_procedure + procedure))?;
+    writer.emit(Return)?;
+  }
+
+  writer.emit(End)?;
+  Ok(())
+}
+
+fn emit_context<'a, T: Write>(
+  writer: &mut CodeStreamWriter<T>,
+  options: &WasmOptions,
+  tokens: &[ProgramToken],
+  procedures: Vec<&'a [ProgramToken]>,
+) -> EmitContext<'a> {
+  let target = options.target;
+
+  EmitContext {
+    target,
+    pointer: writer.declare_local(WasmType::I32),
+    print_function: match target {
+      WasmTarget::Bfcrs => 0,
+      WasmTarget::Wasi => WASI_PRINT,
+    },
+    grow_function: options.max_pages.map(|_| match target {
+      WasmTarget::Bfcrs => BFCRS_GROW,
+      WasmTarget::Wasi => WASI_GROW,
+    }),
+    reach: max_offset(tokens) as i32,
+    low_reach: min_offset(tokens) as i32,
+    tape_start: match target {
+      WasmTarget::Wasi if uses_procedures(tokens) => Some(WASI_TAPE),
+      _ => None,
+    },
+    tape_end: match target {
+      WasmTarget::Bfcrs if uses_procedures(tokens) => Some(BFCRS_PROCEDURE_TABLE as i32),
+      _ => None,
+    },
+    token_offsets: Vec::new(),
+    procedure_table: match target {
+      WasmTarget::Bfcrs => BFCRS_PROCEDURE_TABLE,
+      WasmTarget::Wasi => WASI_PROCEDURE_TABLE,
+    },
+    dispatch_function: dispatch_function(options),
+    procedures,
+  }
+}
+
+// Procedures come right after the entry function and grow, starting with dispatch.
+fn dispatch_function(options: &WasmOptions) -> u32 {
+  let growable = options.max_pages.is_some() as u32;
+
+  match options.target {
+    WasmTarget::Bfcrs => BFCRS_GROW + growable,
+    WasmTarget::Wasi => WASI_GROW + growable,
+  }
+}
 
-        writer.emit(Instruction::End).unwrap();
+// Compiles the program into the body of main (or _start). Also returns the code offset of
+// every token, and the bodies of the procedures it defines.
+fn emit_entry_function<'a>(
+  tokens: &'a [ProgramToken],
+  options: &WasmOptions,
+) -> Result<(Vec<u8>, Vec<usize>, Vec<&'a [ProgramToken]>), Box<Error>> {
+  let target = options.target;
+  let mut code: Vec<u8> = Vec::new();
+  let token_offsets;
+  let procedures;
+
+  {
+    let mut writer = CodeStreamWriter::new(&mut code);
+    let mut context = emit_context(&mut writer, options, tokens, Vec::new());
+
+    if target == WasmTarget::Wasi {
+      writer.emit(Instruction::PushI32(WASI_TAPE))?;
+      writer.emit(Instruction::SetLocal(context.pointer))?;
+    }
+
+    // Offsets can reach past the initial memory or in front of the tape before the pointer has
+    // moved at all.
+    emit_grow_check(&mut writer, &context)?;
+    emit_start_check(&mut writer, &context)?;
+
+    for token in tokens {
+      emit_token(&mut writer, &mut context, token)?;
+    }
+
+    match target {
+      WasmTarget::Bfcrs => {
+        writer.emit(Instruction::GetLocal(context.pointer))?;
+        writer.emit(Instruction::Return)?;
+      }
+      WasmTarget::Wasi => {
+        writer.emit(Instruction::Call(WASI_FLUSH))?;
+      }
+    }
+
+    writer.emit(Instruction::End)?;
+    token_offsets = context.token_offsets;
+    procedures = context.procedures;
+  }
+
+  Ok((code, token_offsets, procedures))
+}
+
+// Compiles dispatch and a function for every procedure. Procedures can define more procedures,
+// which are only found while compiling them, so the list grows along the way.
+fn emit_procedure_functions(
+  tokens: &[ProgramToken],
+  options: &WasmOptions,
+  mut procedures: Vec<&[ProgramToken]>,
+) -> Result<Vec<Vec<u8>>, Box<Error>> {
+  let mut functions = Vec::new();
+
+  while functions.len() < procedures.len() {
+    let body = procedures[functions.len()];
+    let mut code: Vec<u8> = Vec::new();
+
+    {
+      let mut writer = CodeStreamWriter::new(&mut code);
+      let mut context = emit_context(&mut writer, options, tokens, procedures);
+
+      for token in body {
+        emit_token(&mut writer, &mut context, token)?;
       }
 
-      let mut code_body: Vec<u8> = Vec::new();
-      // Number of locals
-      code_body.write_leb_u32(1);
-      // Number of locals of this type
-      code_body.write_leb_u32(1);
-      // Type of the local
-      code_body.write_u8(0x7F).unwrap();
+      writer.emit(Instruction::GetLocal(context.pointer))?;
+      writer.emit(Instruction::End)?;
+      procedures = context.procedures;
+    }
 
-      code_body.write(&code).unwrap();
+    functions.push(code);
+  }
 
-      let mut code_entry: Vec<u8> = Vec::new();
-      code_entry.write_leb_u32(code_body.len() as u32);
-      code_entry.write(&code_body).unwrap();
+  let mut dispatch: Vec<u8> = Vec::new();
+  emit_dispatch_function(
+    &mut CodeStreamWriter::new(&mut dispatch),
+    match options.target {
+      WasmTarget::Bfcrs => BFCRS_PROCEDURE_TABLE,
+      WasmTarget::Wasi => WASI_PROCEDURE_TABLE,
+    },
+    dispatch_function(options) + 1,
+    functions.len() as u32,
+  )?;
+  functions.insert(0, dispatch);
 
-      writer.write(&code_entry).unwrap();
-    })],
+  Ok(functions)
+}
+
+fn add_grow_function_body(writer: &mut dyn Write) {
+  let mut code: Vec<u8> = Vec::new();
+  emit_grow_function(&mut CodeStreamWriter::new(&mut code)).unwrap();
+  // The address is a parameter, so only the second local has to be declared.
+  write_function_body(writer, 1, &code);
+}
+
+// The pointer is a parameter of dispatch and the procedures, so they don't declare any locals.
+fn push_procedure_bodies(entries: &mut Vec<Box<Fn(&mut dyn Write)>>, procedures: Vec<Vec<u8>>) {
+  for code in procedures {
+    entries.push(Box::new(move |writer| {
+      write_function_body(writer, 0, &code);
+    }));
+  }
+}
+
+fn add_code_section(
+  builder: &mut ModuleBuilder,
+  code: Vec<u8>,
+  growable: bool,
+  procedures: Vec<Vec<u8>>,
+) {
+  let mut entries: Vec<Box<Fn(&mut dyn Write)>> = vec![Box::new(move |writer| {
+    write_function_body(writer, 1, &code);
+  })];
+
+  if growable {
+    entries.push(Box::new(add_grow_function_body));
+  }
+
+  push_procedure_bodies(&mut entries, procedures);
+  builder.add_section(10, &entries);
+}
+
+const BFCRS_READ: u32 = 1;
+const BFCRS_GROW: u32 = 3;
+// The procedure table has an i32 for every cell value: 0 if that procedure isn't defined,
+// otherwise the number of its function after dispatch plus one. On this target the tape starts at
+// 0, so the table goes on the second page, and the memory can't grow over it. Programs check the
+// pointer whenever it moves right, so that they trap instead of running off the tape into it.
+const BFCRS_PROCEDURE_TABLE: u32 = 65536;
+
+const BFCRS_FUNCTION_NAMES: &[(u32, &str)] = &[(0, "print"), (1, "read"), (2, "main")];
+const BFCRS_LOCAL_NAMES: &[(u32, &[(u32, &str)])] = &[(2, &[(0, "pointer")])];
+
+const GROW_LOCAL_NAMES: &[(u32, &str)] = &[(0, "address"), (1, "missing")];
+
+// Memory layout of the WASI target. The output buffer and the scratch space needed for calling
+// fd_write occupy the first page, and the tape starts on the second one so that it can grow.
+// Programs with procedures check the pointer whenever it moves left, so that they trap instead of
+// running in front of the tape into the procedure table.
+const WASI_IOVEC: u32 = 0;
+const WASI_NWRITTEN: u32 = WASI_IOVEC + 8;
+const WASI_BUFFER_LENGTH: u32 = WASI_NWRITTEN + 4;
+const WASI_BUFFER: u32 = WASI_BUFFER_LENGTH + 4;
+const WASI_BUFFER_CAPACITY: i32 = 4096;
+const WASI_TAPE: i32 = 65536;
+const WASI_PROCEDURE_TABLE: u32 = 8192;
+
+const WASI_FD_WRITE: u32 = 0;
+const WASI_FD_READ: u32 = 1;
+const WASI_PRINT: u32 = 2;
+const WASI_FLUSH: u32 = 3;
+const WASI_START: u32 = 4;
+const WASI_GROW: u32 = 5;
+
+fn add_wasi_type_section(builder: &mut ModuleBuilder, procedures: bool) {
+  let mut entries: Vec<Box<Fn(&mut dyn Write)>> = vec![
+    // fd_write and fd_read
+    Box::new(|mut writer| {
+      writer.write_u8(0x60).unwrap();
+      // fd, iovs, iovs_len, nwritten / nread
+      writer.write_leb_u32(4);
+      for _ in 0..4 {
+        writer.write_u8(0x7F).unwrap();
+      }
+      // Returns an errno
+      writer.write_leb_u32(1);
+      writer.write_u8(0x7F).unwrap();
+    }),
+    // print
+    Box::new(|mut writer| {
+      writer.write_u8(0x60).unwrap();
+      writer.write_leb_u32(1);
+      writer.write_u8(0x7F).unwrap();
+      writer.write_leb_u32(0);
+    }),
+    // flush and _start
+    Box::new(|mut writer| {
+      writer.write_u8(0x60).unwrap();
+      writer.write_leb_u32(0);
+      writer.write_leb_u32(0);
+    }),
+  ];
+
+  if procedures {
+    entries.push(Box::new(write_procedure_type));
+  }
+
+  builder.add_section(1, &entries);
+}
+
+fn add_wasi_import_section(builder: &mut ModuleBuilder) {
+  builder.add_section(
+    2,
+    &[
+      Box::new(|mut writer| {
+        writer.write_str("wasi_snapshot_preview1");
+        writer.write_str("fd_write");
+        writer.write_u8(0).unwrap();
+        writer.write_leb_u32(0);
+      }),
+      Box::new(|mut writer| {
+        writer.write_str("wasi_snapshot_preview1");
+        writer.write_str("fd_read");
+        writer.write_u8(0).unwrap();
+        writer.write_leb_u32(0);
+      }),
+    ],
+  );
+}
+
+fn add_wasi_function_section(
+  builder: &mut ModuleBuilder,
+  growable: bool,
+  procedure_functions: usize,
+) {
+  let mut entries: Vec<Box<Fn(&mut dyn Write)>> = vec![
+    // print
+    Box::new(|mut writer| {
+      writer.write_leb_u32(1);
+    }),
+    // flush
+    Box::new(|mut writer| {
+      writer.write_leb_u32(2);
+    }),
+    // _start
+    Box::new(|mut writer| {
+      writer.write_leb_u32(2);
+    }),
+  ];
+
+  if growable {
+    // grow has the same signature as print.
+    entries.push(Box::new(|mut writer| {
+      writer.write_leb_u32(1);
+    }));
+  }
+
+  for _ in 0..procedure_functions {
+    entries.push(Box::new(|mut writer| {
+      writer.write_leb_u32(PROCEDURE_TYPE);
+    }));
+  }
+
+  builder.add_section(3, &entries);
+}
+
+fn add_wasi_export_section(builder: &mut ModuleBuilder) {
+  builder.add_section(
+    7,
+    &[
+      Box::new(|mut writer| {
+        writer.write_str("_start");
+        writer.write_u8(0).unwrap();
+        writer.write_leb_u32(WASI_START);
+      }),
+      Box::new(|mut writer| {
+        writer.write_str("memory");
+        writer.write_u8(2).unwrap();
+        writer.write_u8(0).unwrap();
+      }),
+    ],
   );
 }
 
+fn emit_wasi_print<T: Write>(writer: &mut CodeStreamWriter<T>) -> Result<(), Box<Error>> {
+  use self::Instruction::*;
+
+  let ch = writer.declare_local(WasmType::I32);
+
+  // buffer[length] = ch
+  writer.emit(PushI32(0))?;
+  writer.emit(Load32(WASI_BUFFER_LENGTH))?;
+  writer.emit(GetLocal(ch))?;
+  writer.emit(Store8(WASI_BUFFER))?;
+
+  // length += 1
+  writer.emit(PushI32(0))?;
+  writer.emit(PushI32(0))?;
+  writer.emit(Load32(WASI_BUFFER_LENGTH))?;
+  writer.emit(PushI32(1))?;
+  writer.emit(AddI32)?;
+  writer.emit(Store32(WASI_BUFFER_LENGTH))?;
+
+  // Flush if the buffer is full
+  writer.emit(Block)?;
+  writer.emit(PushI32(0))?;
+  writer.emit(Load32(WASI_BUFFER_LENGTH))?;
+  writer.emit(PushI32(WASI_BUFFER_CAPACITY))?;
+  writer.emit(NotEqualsI32)?;
+  writer.emit(BranchIf(0))?;
+  writer.emit(Call(WASI_FLUSH))?;
+  writer.emit(End)?;
+
+  writer.emit(End)?;
+  Ok(())
+}
+
+fn emit_wasi_flush<T: Write>(writer: &mut CodeStreamWriter<T>) -> Result<(), Box<Error>> {
+  use self::Instruction::*;
+
+  // iovec.buf = buffer
+  writer.emit(PushI32(0))?;
+  writer.emit(PushI32(WASI_BUFFER as i32))?;
+  writer.emit(Store32(WASI_IOVEC))?;
+
+  // iovec.len = length
+  writer.emit(PushI32(0))?;
+  writer.emit(PushI32(0))?;
+  writer.emit(Load32(WASI_BUFFER_LENGTH))?;
+  writer.emit(Store32(WASI_IOVEC + 4))?;
+
+  // fd_write(stdout, &iovec, 1, &nwritten)
+  writer.emit(PushI32(1))?;
+  writer.emit(PushI32(WASI_IOVEC as i32))?;
+  writer.emit(PushI32(1))?;
+  writer.emit(PushI32(WASI_NWRITTEN as i32))?;
+  writer.emit(Call(WASI_FD_WRITE))?;
+  writer.emit(Drop)?;
+
+  // length = 0
+  writer.emit(PushI32(0))?;
+  writer.emit(PushI32(0))?;
+  writer.emit(Store32(WASI_BUFFER_LENGTH))?;
+
+  writer.emit(End)?;
+  Ok(())
+}
+
+fn add_wasi_code_section(
+  builder: &mut ModuleBuilder,
+  code: Vec<u8>,
+  growable: bool,
+  procedures: Vec<Vec<u8>>,
+) {
+  let mut entries: Vec<Box<Fn(&mut dyn Write)>> = vec![
+    Box::new(|writer| {
+      let mut code: Vec<u8> = Vec::new();
+      emit_wasi_print(&mut CodeStreamWriter::new(&mut code)).unwrap();
+      // The character is a parameter, not a declared local.
+      write_function_body(writer, 0, &code);
+    }),
+    Box::new(|writer| {
+      let mut code: Vec<u8> = Vec::new();
+      emit_wasi_flush(&mut CodeStreamWriter::new(&mut code)).unwrap();
+      write_function_body(writer, 0, &code);
+    }),
+    Box::new(move |writer| {
+      write_function_body(writer, 1, &code);
+    }),
+  ];
+
+  if growable {
+    entries.push(Box::new(add_grow_function_body));
+  }
+
+  push_procedure_bodies(&mut entries, procedures);
+  builder.add_section(10, &entries);
+}
+
+const WASI_FUNCTION_NAMES: &[(u32, &str)] = &[
+  (0, "fd_write"),
+  (1, "fd_read"),
+  (2, "print"),
+  (3, "flush"),
+  (4, "_start"),
+];
+const WASI_LOCAL_NAMES: &[(u32, &[(u32, &str)])] = &[(2, &[(0, "ch")]), (4, &[(0, "pointer")])];
+
+const PROCEDURE_LOCAL_NAMES: &[(u32, &str)] = &[(0, "pointer")];
+
+fn write_name_subsection(writer: &mut Vec<u8>, id: u8, payload: &[u8]) {
+  writer.write_u8(id).unwrap();
+  writer.write_leb_u32(payload.len() as u32);
+  writer.write(payload).unwrap();
+}
+
+fn write_name_map(writer: &mut Vec<u8>, names: &[(u32, &str)]) {
+  writer.write_leb_u32(names.len() as u32);
+
+  for (index, name) in names {
+    writer.write_leb_u32(*index);
+    writer.write_str(name);
+  }
+}
+
+// The standard "name" section, which debuggers and disassemblers use for function and local names.
+fn add_name_section(
+  builder: &mut ModuleBuilder,
+  function_names: &[(u32, &str)],
+  local_names: &[(u32, &[(u32, &str)])],
+) {
+  let mut data = Vec::new();
+
+  let mut functions = Vec::new();
+  write_name_map(&mut functions, function_names);
+  write_name_subsection(&mut data, 1, &functions);
+
+  let mut locals = Vec::new();
+  locals.write_leb_u32(local_names.len() as u32);
+  for (function, names) in local_names {
+    locals.write_leb_u32(*function);
+    write_name_map(&mut locals, names);
+  }
+  write_name_subsection(&mut data, 2, &locals);
+
+  builder.add_custom_section("name", &data);
+}
+
+// 64-bit FNV-1a, used to tell which source a module was compiled from.
+pub fn source_hash(source: &str) -> u64 {
+  source.bytes().fold(0xcbf29ce484222325, |hash, b| {
+    (hash ^ b as u64).wrapping_mul(0x100000001b3)
+  })
+}
+
+// Layout of the "bfcrs" section:
+// version: u8
+// source hash: u64, source length: varuint32
+// options: vec(key: str, value: str)
+// has source: u8, source: str (if present)
+// entry function index: varuint32
+// source map: vec(code offset: varuint32, source offset: varuint32)
+// Code offsets are relative to the first instruction of the entry function.
+pub const BFCRS_SECTION_VERSION: u8 = 1;
+
+fn add_bfcrs_section(
+  builder: &mut ModuleBuilder,
+  debug_info: &DebugInfo,
+  entry_function: u32,
+  code_offsets: &[usize],
+) {
+  let mut data = Vec::new();
+
+  data.write_u8(BFCRS_SECTION_VERSION).unwrap();
+  data
+    .write_u64::<LittleEndian>(source_hash(debug_info.source)).unwrap();
+  data.write_leb_u32(debug_info.source.len() as u32);
+
+  data.write_leb_u32(debug_info.options.len() as u32);
+  for (key, value) in &debug_info.options {
+    data.write_str(key);
+    data.write_str(value);
+  }
+
+  if debug_info.embed_source {
+    data.write_u8(1).unwrap();
+    data.write_str(debug_info.source);
+  } else {
+    data.write_u8(0).unwrap();
+  }
+
+  data.write_leb_u32(entry_function);
+
+  // Offsets which don't line up with the emitted tokens would be worse than no map at all.
+  match debug_info.token_offsets {
+    Some(ref source_offsets) if source_offsets.len() == code_offsets.len() => {
+      data.write_leb_u32(code_offsets.len() as u32);
+      for (code_offset, source_offset) in code_offsets.iter().zip(source_offsets) {
+        data.write_leb_u32(*code_offset as u32);
+        data.write_leb_u32(*source_offset as u32);
+      }
+    }
+    _ => {
+      data.write_leb_u32(0);
+    }
+  }
+
+  builder.add_custom_section("bfcrs", &data);
+}
+
 impl WasmModule {
   pub fn write_to_stream(
     stream: &mut dyn Write,
     tokens: &[ProgramToken],
+  ) -> Result<(), Box<Error>> {
+    WasmModule::write_target_to_stream(stream, tokens, WasmTarget::Bfcrs)
+  }
+
+  pub fn write_target_to_stream(
+    stream: &mut dyn Write,
+    tokens: &[ProgramToken],
+    target: WasmTarget,
+  ) -> Result<(), Box<Error>> {
+    WasmModule::write_with_options(stream, tokens, &WasmOptions::new(target))
+  }
+
+  pub fn write_with_options(
+    stream: &mut dyn Write,
+    tokens: &[ProgramToken],
+    options: &WasmOptions,
+  ) -> Result<(), Box<Error>> {
+    WasmModule::write_module(stream, tokens, options, None)
+  }
+
+  pub fn write_with_debug_info(
+    stream: &mut dyn Write,
+    tokens: &[ProgramToken],
+    options: &WasmOptions,
+    debug_info: &DebugInfo,
+  ) -> Result<(), Box<Error>> {
+    WasmModule::write_module(stream, tokens, options, Some(debug_info))
+  }
+
+  fn write_module(
+    stream: &mut dyn Write,
+    tokens: &[ProgramToken],
+    options: &WasmOptions,
+    debug_info: Option<&DebugInfo>,
   ) -> Result<(), Box<Error>> {
     let mut builder = ModuleBuilder::new();
+    let (code, code_offsets, procedures) = emit_entry_function(tokens, options)?;
+
+    let growable = options.max_pages.is_some();
+    let has_procedures = uses_procedures(tokens);
+    let initial_pages = match options.target {
+      WasmTarget::Bfcrs if has_procedures => 2,
+      WasmTarget::Bfcrs => 1,
+      WasmTarget::Wasi => 2,
+    };
+
+    if options.max_pages.map_or(false, |max| max < initial_pages) {
+      return Err(From::from(format!(
+        "The memory needs at least {} pages.",
+        initial_pages
+      )));
+    }
+
+    if has_procedures && growable && options.target == WasmTarget::Bfcrs {
+      return Err(From::from(
+        "Procedures can't be combined with a growing memory on the wasm target.",
+      ));
+    }
+
+    let procedure_code = if has_procedures {
+      emit_procedure_functions(tokens, options, procedures)?
+    } else {
+      Vec::new()
+    };
+    let procedure_names = (0..procedure_code.len())
+      .map(|i| match i {
+        0 => "dispatch".to_string(),
+        _ => format!("procedure_{}", i - 1),
+      })
+      .collect::<Vec<_>>();
+
+    let mut function_names;
+    let mut local_names = Vec::new();
+
+    let entry_function = match options.target {
+      WasmTarget::Bfcrs => {
+        add_type_section(&mut builder, has_procedures);
+        add_import_section(&mut builder);
+        add_function_section(&mut builder, growable, procedure_code.len());
+        add_memory_section(&mut builder, initial_pages, options.max_pages);
+        add_export_section(&mut builder);
+        add_code_section(&mut builder, code, growable, procedure_code);
+
+        function_names = BFCRS_FUNCTION_NAMES.to_vec();
+        local_names.extend_from_slice(BFCRS_LOCAL_NAMES);
+        if growable {
+          function_names.push((BFCRS_GROW, "grow"));
+          local_names.push((BFCRS_GROW, GROW_LOCAL_NAMES));
+        }
+        2
+      }
+      WasmTarget::Wasi => {
+        add_wasi_type_section(&mut builder, has_procedures);
+        add_wasi_import_section(&mut builder);
+        add_wasi_function_section(&mut builder, growable, procedure_code.len());
+        add_memory_section(&mut builder, initial_pages, options.max_pages);
+        add_wasi_export_section(&mut builder);
+        add_wasi_code_section(&mut builder, code, growable, procedure_code);
+
+        function_names = WASI_FUNCTION_NAMES.to_vec();
+        local_names.extend_from_slice(WASI_LOCAL_NAMES);
+        if growable {
+          function_names.push((WASI_GROW, "grow"));
+          local_names.push((WASI_GROW, GROW_LOCAL_NAMES));
+        }
+        WASI_START
+      }
+    };
 
-    add_type_section(&mut builder);
-    add_import_section(&mut builder);
-    add_function_section(&mut builder);
-    add_memory_section(&mut builder, 1);
-    add_export_section(&mut builder);
-    add_code_section(&mut builder, tokens.to_vec());
+    if uses_storage(tokens) {
+      add_global_section(&mut builder);
+    }
+
+    let dispatch = dispatch_function(options);
+    for (i, name) in procedure_names.iter().enumerate() {
+      function_names.push((dispatch + i as u32, name));
+      local_names.push((dispatch + i as u32, PROCEDURE_LOCAL_NAMES));
+    }
+    add_name_section(&mut builder, &function_names, &local_names);
+
+    if let Some(debug_info) = debug_info {
+      add_bfcrs_section(&mut builder, debug_info, entry_function, &code_offsets);
+    }
 
     builder.write_to_stream(stream)?;
 
@@ -334,7 +1382,7 @@ impl WasmModule {
   }
 }
 
-#[derive(Copy, Clone)]
+#[derive(Debug, PartialEq, Copy, Clone)]
 pub enum WasmType {
   I32,
 }
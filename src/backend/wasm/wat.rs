@@ -0,0 +1,319 @@
+use std::collections::HashMap;
+use std::error::Error;
+use std::io::Write;
+
+use backend::wasm::code_stream::Instruction;
+use backend::wasm::decoder::{decode_module, ExternalKind, FunctionBody, Module, SourceInfo};
+use backend::wasm::module_builder::{WasmModule, WasmType};
+use backend::Backend;
+use types::ProgramToken;
+
+pub struct WatBackend;
+
+impl Backend for WatBackend {
+  fn extension(&self) -> &'static str {
+    ".wat"
+  }
+
+  fn supports_procedures(&self) -> bool {
+    true
+  }
+
+  fn compile_to_stream(&self, tokens: &[ProgramToken], stream: &mut dyn Write) {
+    // Going through the binary encoding guarantees the text is exactly what WasmBackend emits.
+    let mut binary = Vec::new();
+    WasmModule::write_to_stream(&mut binary, tokens).unwrap();
+    disassemble(&binary, stream).unwrap();
+  }
+}
+
+pub fn disassemble(bytes: &[u8], stream: &mut dyn Write) -> Result<(), Box<Error>> {
+  let module = decode_module(bytes)?;
+  write_wat(&module, stream)
+}
+
+fn type_name(value_type: WasmType) -> &'static str {
+  match value_type {
+    WasmType::I32 => "i32",
+  }
+}
+
+// Refers to a function or local by name when the module has one, like wasm2wat does.
+fn reference(names: Option<&HashMap<u32, String>>, index: u32) -> String {
+  match names.and_then(|names| names.get(&index)) {
+    Some(name) => format!("${}", name),
+    None => index.to_string(),
+  }
+}
+
+// The name and index of a definition, e.g. "$main (;2;)".
+fn label(names: Option<&HashMap<u32, String>>, index: u32) -> String {
+  match names.and_then(|names| names.get(&index)) {
+    Some(name) => format!("${} (;{};)", name, index),
+    None => format!("(;{};)", index),
+  }
+}
+
+fn write_signature(
+  stream: &mut dyn Write,
+  params: &[WasmType],
+  results: &[WasmType],
+  local_names: Option<&HashMap<u32, String>>,
+) -> Result<(), Box<Error>> {
+  for (i, param) in params.iter().enumerate() {
+    match local_names.and_then(|names| names.get(&(i as u32))) {
+      Some(name) => write!(stream, " (param ${} {})", name, type_name(*param))?,
+      None => write!(stream, " (param {})", type_name(*param))?,
+    }
+  }
+
+  for result in results {
+    write!(stream, " (result {})", type_name(*result))?;
+  }
+
+  Ok(())
+}
+
+fn instruction_text(
+  instruction: &Instruction,
+  function_names: &HashMap<u32, String>,
+  local_names: Option<&HashMap<u32, String>>,
+) -> String {
+  use self::Instruction::*;
+
+  fn with_offset(name: &str, offset: u32) -> String {
+    if offset == 0 {
+      name.to_string()
+    } else {
+      format!("{} offset={}", name, offset)
+    }
+  }
+
+  match *instruction {
+    GetLocal(handle) => format!("local.get {}", reference(local_names, handle.0)),
+    SetLocal(handle) => format!("local.set {}", reference(local_names, handle.0)),
+    GetGlobal(index) => format!("global.get {}", index),
+    SetGlobal(index) => format!("global.set {}", index),
+    Load8Signed(offset) => with_offset("i32.load8_s", offset),
+    Load8Unsigned(offset) => with_offset("i32.load8_u", offset),
+    Store8(offset) => with_offset("i32.store8", offset),
+    Load32(offset) => with_offset("i32.load", offset),
+    Store32(offset) => with_offset("i32.store", offset),
+    PushI32(value) => format!("i32.const {}", value),
+    AddI32 => "i32.add".to_string(),
+    SubI32 => "i32.sub".to_string(),
+    ShiftLeftI32 => "i32.shl".to_string(),
+    ShiftRightUnsignedI32 => "i32.shr_u".to_string(),
+    AndI32 => "i32.and".to_string(),
+    OrI32 => "i32.or".to_string(),
+    XorI32 => "i32.xor".to_string(),
+    NotEqualsI32 => "i32.ne".to_string(),
+    LessOrEqualSignedI32 => "i32.le_s".to_string(),
+    MemorySize => "memory.size".to_string(),
+    MemoryGrow => "memory.grow".to_string(),
+    Call(function) => format!("call {}", reference(Some(function_names), function)),
+    BranchIf(depth) => format!("br_if {}", depth),
+    BranchTable(ref targets, default) => {
+      let mut text = "br_table".to_string();
+      for target in targets {
+        text.push_str(&format!(" {}", target));
+      }
+      format!("{} {}", text, default)
+    }
+    EqualsZeroI32 => "i32.eqz".to_string(),
+    Loop => "loop".to_string(),
+    Block => "block".to_string(),
+    End => "end".to_string(),
+    Drop => "drop".to_string(),
+    Return => "return".to_string(),
+    Unreachable => "unreachable".to_string(),
+  }
+}
+
+fn write_body(
+  stream: &mut dyn Write,
+  body: &FunctionBody,
+  param_count: usize,
+  function_names: &HashMap<u32, String>,
+  local_names: Option<&HashMap<u32, String>>,
+) -> Result<(), Box<Error>> {
+  let has_names = (0..body.locals.len())
+    .any(|i| reference(local_names, (param_count + i) as u32).starts_with('$'));
+
+  if has_names {
+    // Named locals have to be declared one by one.
+    for (i, local) in body.locals.iter().enumerate() {
+      match local_names.and_then(|names| names.get(&((param_count + i) as u32))) {
+        Some(name) => write!(stream, "\n    (local ${} {})", name, type_name(*local))?,
+        None => write!(stream, "\n    (local {})", type_name(*local))?,
+      }
+    }
+  } else if !body.locals.is_empty() {
+    write!(stream, "\n    (local")?;
+    for local in &body.locals {
+      write!(stream, " {}", type_name(*local))?;
+    }
+    write!(stream, ")")?;
+  }
+
+  let mut depth = 0;
+
+  for instruction in &body.code {
+    match instruction {
+      // The final end closes the function itself, which is implicit in the text format.
+      Instruction::End if depth == 0 => {
+        break;
+      }
+      Instruction::End => {
+        depth -= 1;
+      }
+      _ => {}
+    }
+
+    write!(
+      stream,
+      "\n    {}{}",
+      "  ".repeat(depth),
+      instruction_text(instruction, function_names, local_names)
+    )?;
+
+    match instruction {
+      Instruction::Block | Instruction::Loop => {
+        depth += 1;
+      }
+      _ => {}
+    }
+  }
+
+  Ok(())
+}
+
+fn write_source_info(stream: &mut dyn Write, source_info: &SourceInfo) -> Result<(), Box<Error>> {
+  write!(
+    stream,
+    "\n  ;; source: {} bytes, hash {:016x}",
+    source_info.source_length, source_info.source_hash
+  )?;
+
+  if !source_info.options.is_empty() {
+    write!(stream, "\n  ;; options:")?;
+    for (key, value) in &source_info.options {
+      write!(stream, " {}={}", key, value)?;
+    }
+  }
+
+  if !source_info.source_map.is_empty() {
+    write!(
+      stream,
+      "\n  ;; source map: {} entries for function {}",
+      source_info.source_map.len(),
+      source_info.entry_function
+    )?;
+  }
+
+  Ok(())
+}
+
+pub fn write_wat(module: &Module, stream: &mut dyn Write) -> Result<(), Box<Error>> {
+  let function_names = &module.names.functions;
+
+  write!(stream, "(module")?;
+
+  for (i, function_type) in module.types.iter().enumerate() {
+    write!(stream, "\n  (type (;{};) (func", i)?;
+    write_signature(stream, &function_type.params, &function_type.results, None)?;
+    write!(stream, "))")?;
+  }
+
+  for (i, import) in module.imports.iter().enumerate() {
+    write!(
+      stream,
+      "\n  (import \"{}\" \"{}\" (func {} (type {})))",
+      import.module,
+      import.field,
+      label(Some(function_names), i as u32),
+      import.type_index
+    )?;
+  }
+
+  for (i, (type_index, body)) in module.functions.iter().zip(&module.bodies).enumerate() {
+    let function_type = module
+      .types
+      .get(*type_index as usize)
+      .ok_or("Function refers to a missing type.")?;
+
+    let index = (module.imports.len() + i) as u32;
+    let local_names = module.names.locals.get(&index);
+
+    write!(
+      stream,
+      "\n  (func {} (type {})",
+      label(Some(function_names), index),
+      type_index
+    )?;
+    write_signature(
+      stream,
+      &function_type.params,
+      &function_type.results,
+      local_names,
+    )?;
+    write_body(
+      stream,
+      body,
+      function_type.params.len(),
+      function_names,
+      local_names,
+    )?;
+    write!(stream, ")")?;
+  }
+
+  for (i, memory) in module.memories.iter().enumerate() {
+    write!(stream, "\n  (memory (;{};) {}", i, memory.initial)?;
+    if let Some(maximum) = memory.maximum {
+      write!(stream, " {}", maximum)?;
+    }
+    write!(stream, ")")?;
+  }
+
+  for (i, global) in module.globals.iter().enumerate() {
+    let global_type = if global.mutable {
+      format!("(mut {})", type_name(global.value_type))
+    } else {
+      type_name(global.value_type).to_string()
+    };
+
+    write!(
+      stream,
+      "\n  (global (;{};) {} (i32.const {}))",
+      i, global_type, global.initial
+    )?;
+  }
+
+  for export in &module.exports {
+    let kind = match export.kind {
+      ExternalKind::Function => "func",
+      ExternalKind::Table => "table",
+      ExternalKind::Memory => "memory",
+      ExternalKind::Global => "global",
+    };
+
+    let index = match export.kind {
+      ExternalKind::Function => reference(Some(function_names), export.index),
+      _ => export.index.to_string(),
+    };
+
+    write!(
+      stream,
+      "\n  (export \"{}\" ({} {}))",
+      export.name, kind, index
+    )?;
+  }
+
+  if let Some(ref source_info) = module.source_info {
+    write_source_info(stream, source_info)?;
+  }
+
+  write!(stream, ")\n")?;
+
+  Ok(())
+}
@@ -0,0 +1,280 @@
+use std::env;
+use std::error::Error;
+use std::fs::{remove_file, File};
+use std::io::{ErrorKind, Write};
+use std::path::PathBuf;
+use std::process::{self, Command, Stdio};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use backend::bf::BfBackend;
+use backend::c::CBackend;
+use backend::llvm::LlvmBackend;
+use backend::wasm::executor::{run_wasi, run_wasm};
+use backend::wasm::{WasmOptions, WasmTarget};
+use backend::Backend;
+use interpreter::{run_program_counting, BufferIo};
+use optimizer::{
+  convert_tokens, count_tokens, optimize, optimize_to_level, uses_procedures, OptimizationLevel,
+  OPTIMIZATION_LEVELS,
+};
+use parser::{parse_program_checked, parse_program_extended, Extensions};
+use types::{ProgramToken, State};
+use {compile_tokens, compile_tokens_with_options};
+
+// Keeps the temporary files of engines running in parallel apart.
+static NEXT_BUILD: AtomicUsize = AtomicUsize::new(0);
+
+pub const DEFAULT_SUITE: &[&str] = &["bf/mandelbrot.bf", "bf/sierpinski.bf", "bf/hello.bf"];
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Engine {
+  Interpreter(OptimizationLevel),
+  // The WebAssembly executor in this crate, running modules for either target.
+  Wasm,
+  Wasi,
+  // The optimized program translated back into Brainfuck, then optimized again and interpreted.
+  Brainfuck,
+  // The C backend, built with $CC or cc. Skipped when there is no compiler.
+  C,
+  // The LLVM backend, run with lli. Skipped when it isn't installed.
+  Llvm,
+}
+
+impl Engine {
+  pub fn name(&self) -> String {
+    match self {
+      Engine::Interpreter(level) => format!("interpreter-{}", level.name()),
+      Engine::Wasm => "wasm".to_string(),
+      Engine::Wasi => "wasi".to_string(),
+      Engine::Brainfuck => "bf".to_string(),
+      Engine::C => "c".to_string(),
+      Engine::Llvm => "llvm".to_string(),
+    }
+  }
+}
+
+pub fn all_engines() -> Vec<Engine> {
+  let mut engines = OPTIMIZATION_LEVELS
+    .iter()
+    .map(|level| Engine::Interpreter(*level))
+    .collect::<Vec<_>>();
+
+  engines.extend_from_slice(&[
+    Engine::Wasm,
+    Engine::Wasi,
+    Engine::Brainfuck,
+    Engine::C,
+    Engine::Llvm,
+  ]);
+  engines
+}
+
+pub struct Measurement {
+  pub engine: Engine,
+  // Tokens in the program the engine ran, counting loop bodies.
+  pub ir_size: usize,
+  // Only the interpreter counts ops.
+  pub ops: Option<u64>,
+  pub duration: Duration,
+}
+
+fn temp_path(extension: &str) -> PathBuf {
+  let build = NEXT_BUILD.fetch_add(1, Ordering::SeqCst);
+  env::temp_dir()
+    .join(format!("bfcrs-bench-{}-{}", process::id(), build))
+    .with_extension(extension)
+}
+
+fn write_temp_file(
+  extension: &str,
+  program: &[ProgramToken],
+  backend: &Backend,
+) -> Result<PathBuf, Box<Error>> {
+  let path = temp_path(extension);
+  let mut code = Vec::new();
+  backend.compile(program, &mut code)?;
+  File::create(&path)?.write_all(&code)?;
+  Ok(path)
+}
+
+// Returns None if the program doesn't exist. The time includes starting the process.
+fn run_native(
+  command: &mut Command,
+  input: &[u8],
+) -> Result<Option<(Duration, Vec<u8>)>, Box<Error>> {
+  let start = Instant::now();
+  let mut child = match command
+    .stdin(Stdio::piped())
+    .stdout(Stdio::piped())
+    .stderr(Stdio::null())
+    .spawn()
+  {
+    Err(ref error) if error.kind() == ErrorKind::NotFound => return Ok(None),
+    other => other?,
+  };
+
+  // Feeding the input from another thread keeps a full output pipe from blocking us.
+  let mut stdin = child.stdin.take().unwrap();
+  let input = input.to_vec();
+  let feeder = thread::spawn(move || stdin.write_all(&input));
+
+  let result = child.wait_with_output()?;
+  let duration = start.elapsed();
+  // The program doesn't have to read all of its input.
+  let _ = feeder.join();
+
+  if !result.status.success() {
+    return Err(From::from(format!("{:?} failed.", command)));
+  }
+
+  Ok(Some((duration, result.stdout)))
+}
+
+fn run_c(
+  program: &[ProgramToken],
+  input: &[u8],
+) -> Result<Option<(Duration, Vec<u8>)>, Box<Error>> {
+  let source_path = write_temp_file("c", program, &CBackend)?;
+  let binary_path = temp_path("out");
+
+  let compiler = env::var("CC").unwrap_or_else(|_| "cc".to_string());
+  let compiled = run_native(
+    Command::new(&compiler)
+      .arg("-O2")
+      .arg("-o")
+      .arg(&binary_path)
+      .arg(&source_path),
+    &[],
+  );
+  remove_file(&source_path)?;
+
+  if compiled?.is_none() {
+    return Ok(None);
+  }
+
+  let result = run_native(&mut Command::new(&binary_path), input);
+  remove_file(&binary_path)?;
+  result
+}
+
+fn run_llvm(
+  program: &[ProgramToken],
+  input: &[u8],
+) -> Result<Option<(Duration, Vec<u8>)>, Box<Error>> {
+  let path = write_temp_file("ll", program, &LlvmBackend)?;
+  let result = run_native(Command::new("lli").arg(&path), input);
+  remove_file(&path)?;
+  result
+}
+
+// The Brainfuck backend only emits the commands of extensions for their tokens, and never any
+// comments.
+fn round_trip(program: &[ProgramToken]) -> Vec<ProgramToken> {
+  let mut code = Vec::new();
+  BfBackend.compile_to_stream(program, &mut code);
+  optimize(&convert_tokens(&parse_program_extended(
+    &String::from_utf8(code).unwrap(),
+    Extensions {
+      procedures: true,
+      extended_type_1: true,
+    },
+  )))
+}
+
+// Runs the program on one engine. Returns None if the engine isn't available here, or if it
+// can't run procedures and the program has some.
+pub fn measure(
+  engine: Engine,
+  program: &[ProgramToken],
+  input: &[u8],
+) -> Result<Option<(Measurement, Vec<u8>)>, Box<Error>> {
+  if (engine == Engine::C || engine == Engine::Llvm) && uses_procedures(program) {
+    return Ok(None);
+  }
+
+  let optimized = match engine {
+    Engine::Interpreter(level) => optimize_to_level(program, level),
+    Engine::Brainfuck => round_trip(&optimize(program)),
+    _ => optimize(program),
+  };
+  let mut io = BufferIo::new(input);
+  let mut ops = None;
+
+  let (duration, output) = match engine {
+    Engine::Interpreter(_) | Engine::Brainfuck => {
+      let start = Instant::now();
+      ops = Some(run_program_counting(&optimized, &mut State::new(), &mut io)?);
+      (start.elapsed(), io.output)
+    }
+    Engine::Wasm => {
+      let code = compile_tokens(&optimized, false);
+      let start = Instant::now();
+      run_wasm(&code, &mut io)?;
+      (start.elapsed(), io.output)
+    }
+    Engine::Wasi => {
+      let code = compile_tokens_with_options(&optimized, WasmOptions::new(WasmTarget::Wasi));
+      let start = Instant::now();
+      run_wasi(&code, &mut io)?;
+      (start.elapsed(), io.output)
+    }
+    Engine::C => match run_c(&optimized, input)? {
+      Some(result) => result,
+      None => return Ok(None),
+    },
+    Engine::Llvm => match run_llvm(&optimized, input)? {
+      Some(result) => result,
+      None => return Ok(None),
+    },
+  };
+
+  let measurement = Measurement {
+    engine,
+    ir_size: count_tokens(&optimized),
+    ops,
+    duration,
+  };
+
+  Ok(Some((measurement, output)))
+}
+
+// Runs the source on every engine, checking that they all print the same output.
+pub fn bench_source(
+  source: &str,
+  input: &[u8],
+  engines: &[Engine],
+) -> Result<Vec<Measurement>, Box<Error>> {
+  bench_program(
+    &convert_tokens(&parse_program_checked(source)?),
+    input,
+    engines,
+  )
+}
+
+pub fn bench_program(
+  program: &[ProgramToken],
+  input: &[u8],
+  engines: &[Engine],
+) -> Result<Vec<Measurement>, Box<Error>> {
+  let mut expected_output: Option<Vec<u8>> = None;
+  let mut measurements = Vec::new();
+
+  for engine in engines {
+    if let Some((measurement, output)) = measure(*engine, program, input)? {
+      if expected_output.is_none() {
+        expected_output = Some(output);
+      } else if expected_output.as_ref() != Some(&output) {
+        return Err(From::from(format!(
+          "The {} engine printed different output.",
+          engine.name()
+        )));
+      }
+
+      measurements.push(measurement);
+    }
+  }
+
+  Ok(measurements)
+}
@@ -0,0 +1 @@
+pub mod text_to_bf;
@@ -0,0 +1,185 @@
+use backend::bf::push_repeated;
+use interpreter::{run_program_counting, BfIo};
+use optimizer::convert_tokens;
+use parser::parse_program;
+use types::State;
+
+// Cells the text is printed from, and the most iterations a setup loop runs.
+const MAX_CELLS: usize = 8;
+const MAX_ITERATIONS: usize = 16;
+const CLUSTER_ROUNDS: usize = 8;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Goal {
+  // The fewest characters.
+  Size,
+  // The fewest ops in the unoptimized interpreter.
+  Speed,
+}
+
+struct NullIo;
+
+impl BfIo for NullIo {
+  fn print(&mut self, _ch: u8) {}
+
+  fn read(&mut self) -> u8 {
+    0
+  }
+}
+
+fn push_move(output: &mut String, from: usize, to: usize) {
+  if to > from {
+    push_repeated(output, '>', to - from);
+  } else {
+    push_repeated(output, '<', from - to);
+  }
+}
+
+// Cells wrap around, so going down can be shorter than going up.
+fn change_cost(from: u8, to: u8) -> usize {
+  let up = to.wrapping_sub(from) as usize;
+  up.min(256 - up)
+}
+
+fn push_change(output: &mut String, from: u8, to: u8) {
+  let up = to.wrapping_sub(from) as usize;
+
+  if up <= 128 {
+    push_repeated(output, '+', up);
+  } else {
+    push_repeated(output, '-', 256 - up);
+  }
+}
+
+// Prints every byte from whichever cell is cheapest to reach and change, leaving the new value
+// there for the bytes after it.
+fn print_from_cells(text: &[u8], mut cells: Vec<u8>, mut pointer: usize, output: &mut String) {
+  for &byte in text {
+    let cell = (0..cells.len())
+      .min_by_key(|&i| {
+        let distance = if i > pointer {
+          i - pointer
+        } else {
+          pointer - i
+        };
+        distance + change_cost(cells[i], byte)
+      }).unwrap();
+
+    push_move(output, pointer, cell);
+    push_change(output, cells[cell], byte);
+    output.push('.');
+
+    cells[cell] = byte;
+    pointer = cell;
+  }
+}
+
+// Groups the bytes of the text around `count` values with k-means, starting from groups of
+// neighbouring distinct bytes.
+fn cluster_targets(text: &[u8], count: usize) -> Option<Vec<u8>> {
+  let mut distinct = text.to_vec();
+  distinct.sort();
+  distinct.dedup();
+
+  if distinct.len() < count {
+    return None;
+  }
+
+  let mut targets = (0..count)
+    .map(|i| {
+      let group = &distinct[i * distinct.len() / count..(i + 1) * distinct.len() / count];
+      ((group[0] as usize + group[group.len() - 1] as usize) / 2) as u8
+    })
+    .collect::<Vec<_>>();
+
+  for _ in 0..CLUSTER_ROUNDS {
+    let mut sums = vec![(0, 0); count];
+
+    for &byte in text {
+      let nearest = (0..count)
+        .min_by_key(|&i| change_cost(targets[i], byte)).unwrap();
+      sums[nearest].0 += byte as usize;
+      sums[nearest].1 += 1;
+    }
+
+    for (target, &(sum, bytes)) in targets.iter_mut().zip(&sums) {
+      if bytes > 0 {
+        *target = ((sum + bytes / 2) / bytes) as u8;
+      }
+    }
+  }
+
+  Some(targets)
+}
+
+// Counts down cell 0 while adding a multiple of it to each of the cells to its right, so that
+// they end up near the targets.
+fn with_setup_loop(text: &[u8], targets: &[u8], iterations: usize) -> String {
+  let mut output = String::new();
+  let mut cells = vec![0];
+
+  push_repeated(&mut output, '+', iterations);
+  output.push('[');
+
+  for &target in targets {
+    // Bytes over 127 are closer to 0 going down.
+    let factor = (f64::from(target as i8) / iterations as f64).round() as i8;
+    output.push('>');
+    push_change(&mut output, 0, factor as u8);
+    cells.push((factor as u8).wrapping_mul(iterations as u8));
+  }
+
+  push_repeated(&mut output, '<', targets.len());
+  output.push_str("-]");
+
+  print_from_cells(text, cells, 0, &mut output);
+  output
+}
+
+fn candidates(text: &[u8]) -> Vec<String> {
+  let mut candidates = Vec::new();
+
+  for count in 1..MAX_CELLS + 1 {
+    // Without a loop, the cells start at 0 and every change is spelled out.
+    let mut output = String::new();
+    print_from_cells(text, vec![0; count], 0, &mut output);
+    candidates.push(output);
+
+    if let Some(targets) = cluster_targets(text, count) {
+      for iterations in 2..MAX_ITERATIONS + 1 {
+        candidates.push(with_setup_loop(text, &targets, iterations));
+      }
+    }
+  }
+
+  candidates
+}
+
+// The generated programs always end.
+fn count_ops(code: &str) -> u64 {
+  let program = convert_tokens(&parse_program(code));
+  run_program_counting(&program, &mut State::new(), &mut NullIo).unwrap()
+}
+
+// Generates Brainfuck which prints the text, starting from an empty tape.
+pub fn generate(text: &[u8], goal: Goal) -> String {
+  if text.is_empty() {
+    return String::new();
+  }
+
+  let scored = candidates(text)
+    .into_iter()
+    .map(|code| (count_ops(&code), code))
+    .collect::<Vec<_>>();
+
+  let best = match goal {
+    Goal::Size => scored
+      .into_iter()
+      .min_by_key(|&(ops, ref code)| (code.len(), ops)),
+    Goal::Speed => scored
+      .into_iter()
+      .min_by_key(|&(ops, ref code)| (ops, code.len())),
+  };
+
+  best.unwrap().1
+}
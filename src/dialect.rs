@@ -0,0 +1,317 @@
+use std::error::Error;
+use std::fs::read_to_string;
+use std::path::Path;
+
+use parser::{parse_program_extended, Extensions};
+use types::ParseToken;
+
+// Every command in the order of a substitution table's lines.
+const BRAINFUCK: [&str; 8] = [">", "<", "+", "-", ".", ",", "[", "]"];
+
+// Ook! and Blub spell every command as a pair of three words.
+const WORD_PAIRS: [(usize, usize); 8] = [
+  (0, 1),
+  (1, 0),
+  (0, 0),
+  (2, 2),
+  (2, 0),
+  (0, 2),
+  (2, 1),
+  (1, 2),
+];
+
+// An alternative spelling of Brainfuck's commands. Programs in other dialects are translated to
+// Brainfuck before they're parsed, and Brainfuck can be emitted in any dialect.
+#[derive(Clone, Debug)]
+pub struct Dialect {
+  pub name: String,
+  // How each command in BRAINFUCK is spelled.
+  spellings: Vec<String>,
+  // Commands made of words can be separated by any whitespace, like "Ook.\nOok?".
+  words: bool,
+  pub extensions: Extensions,
+}
+
+impl Dialect {
+  pub fn brainfuck() -> Dialect {
+    Dialect {
+      name: "brainfuck".to_string(),
+      spellings: BRAINFUCK.iter().map(|x| x.to_string()).collect(),
+      words: false,
+      extensions: Extensions::default(),
+    }
+  }
+
+  // Brainfuck with procedures.
+  pub fn pbrain() -> Dialect {
+    Dialect {
+      name: "pbrain".to_string(),
+      extensions: Extensions {
+        procedures: true,
+        ..Extensions::default()
+      },
+      ..Dialect::brainfuck()
+    }
+  }
+
+  // Brainfuck with Extended Brainfuck Type I commands.
+  pub fn extended_brainfuck() -> Dialect {
+    Dialect {
+      name: "ebf".to_string(),
+      extensions: Extensions {
+        extended_type_1: true,
+        ..Extensions::default()
+      },
+      ..Dialect::brainfuck()
+    }
+  }
+
+  fn word_pairs(name: &str, word: &str) -> Dialect {
+    let words = [
+      format!("{}.", word),
+      format!("{}?", word),
+      format!("{}!", word),
+    ];
+
+    Dialect {
+      name: name.to_string(),
+      spellings: WORD_PAIRS
+        .iter()
+        .map(|&(a, b)| format!("{} {}", words[a], words[b]))
+        .collect(),
+      words: true,
+      extensions: Extensions::default(),
+    }
+  }
+
+  pub fn from_name(name: &str) -> Option<Dialect> {
+    match name {
+      "brainfuck" | "bf" => Some(Dialect::brainfuck()),
+      "pbrain" => Some(Dialect::pbrain()),
+      "ebf" => Some(Dialect::extended_brainfuck()),
+      "ook" => Some(Dialect::word_pairs("ook", "Ook")),
+      "blub" => Some(Dialect::word_pairs("blub", "Blub")),
+      _ => None,
+    }
+  }
+
+  // A Trivial Brainfuck Substitution table has a line for each command: the Brainfuck command,
+  // whitespace and its spelling. Empty lines and lines starting with # are skipped.
+  pub fn from_table(name: &str, table: &str) -> Result<Dialect, Box<Error>> {
+    let mut spellings = vec![None; BRAINFUCK.len()];
+
+    for (number, line) in table.lines().enumerate() {
+      let line = line.trim();
+
+      if line.is_empty() || line.starts_with('#') {
+        continue;
+      }
+
+      let mut parts = line.splitn(2, char::is_whitespace);
+      let command = parts.next().unwrap();
+      let spelling = parts.next().map(|x| x.trim()).unwrap_or("");
+
+      let index = match BRAINFUCK.iter().position(|x| *x == command) {
+        Some(index) => index,
+        None => {
+          return Err(From::from(format!(
+            "{}:{}: Unknown command {}",
+            name,
+            number + 1,
+            command
+          )));
+        }
+      };
+
+      if spelling.is_empty() {
+        return Err(From::from(format!(
+          "{}:{}: No spelling for {}",
+          name,
+          number + 1,
+          command
+        )));
+      }
+
+      if spellings[index].is_some() {
+        return Err(From::from(format!(
+          "{}:{}: {} is spelled twice",
+          name,
+          number + 1,
+          command
+        )));
+      }
+
+      spellings[index] = Some(spelling.to_string());
+    }
+
+    if let Some(missing) = spellings.iter().position(|x| x.is_none()) {
+      return Err(From::from(format!(
+        "{}: No spelling for {}",
+        name, BRAINFUCK[missing]
+      )));
+    }
+
+    Ok(Dialect {
+      name: name.to_string(),
+      spellings: spellings.into_iter().map(|x| x.unwrap()).collect(),
+      words: false,
+      extensions: Extensions::default(),
+    })
+  }
+
+  // Built-in dialects are looked up by name, anything else is read as a substitution table.
+  pub fn load(name: &str) -> Result<Dialect, Box<Error>> {
+    if let Some(dialect) = Dialect::from_name(name) {
+      return Ok(dialect);
+    }
+
+    let table =
+      read_to_string(name).map_err(|error| format!("Unknown dialect {}: {}", name, error))?;
+    let stem = Path::new(name)
+      .file_stem()
+      .map_or(name.to_string(), |x| x.to_string_lossy().into_owned());
+
+    Dialect::from_table(&stem, &table)
+  }
+
+  pub fn is_brainfuck(&self) -> bool {
+    self
+      .spellings
+      .iter()
+      .zip(BRAINFUCK.iter())
+      .all(|(a, b)| a == b)
+  }
+
+  fn command(&self, spelling: &str) -> Option<&'static str> {
+    self
+      .spellings
+      .iter()
+      .position(|x| x == spelling)
+      .map(|index| BRAINFUCK[index])
+  }
+
+  fn translate_words(&self, source: &str) -> String {
+    let mut brainfuck = String::new();
+    let mut pending: Option<&str> = None;
+
+    for (number, line) in source.split('\n').enumerate() {
+      if number > 0 {
+        brainfuck.push('\n');
+      }
+
+      // Only the words of the dialect count, so they can be surrounded by anything else.
+      for word in line.split_whitespace().filter(|word| {
+        self
+          .spellings
+          .iter()
+          .any(|x| x.split(' ').any(|y| y == *word))
+      }) {
+        match pending.take() {
+          None => pending = Some(word),
+          // Pairs which aren't commands are skipped.
+          Some(first) => {
+            brainfuck.push_str(self.command(&format!("{} {}", first, word)).unwrap_or(""))
+          }
+        }
+      }
+    }
+
+    brainfuck
+  }
+
+  fn translate_substitutions(&self, source: &str) -> String {
+    let mut brainfuck = String::new();
+    let mut rest = source;
+
+    while let Some(ch) = rest.chars().next() {
+      // The longest spelling wins, so one spelling can start with another.
+      let longest = self
+        .spellings
+        .iter()
+        .enumerate()
+        .filter(|&(_, spelling)| rest.starts_with(&spelling[..]))
+        .max_by_key(|&(_, spelling)| spelling.len());
+
+      match longest {
+        Some((index, spelling)) => {
+          brainfuck.push_str(BRAINFUCK[index]);
+          rest = &rest[spelling.len()..];
+        }
+        None => {
+          if ch == '\n' {
+            brainfuck.push('\n');
+          }
+          rest = &rest[ch.len_utf8()..];
+        }
+      }
+    }
+
+    brainfuck
+  }
+
+  // Translates a program into Brainfuck. Comments are dropped, unless the dialect is Brainfuck,
+  // but line breaks are kept so that errors point at the right line.
+  pub fn translate(&self, source: &str) -> String {
+    if self.is_brainfuck() {
+      source.to_string()
+    } else if self.words {
+      self.translate_words(source)
+    } else {
+      self.translate_substitutions(source)
+    }
+  }
+
+  pub fn parse(&self, source: &str) -> Vec<ParseToken> {
+    parse_program_extended(&self.translate(source), self.extensions)
+  }
+
+  // Spells out the commands of a Brainfuck program, keeping its line breaks and indentation and
+  // wrapping lines longer than the line width. Comments are dropped, since they could contain
+  // words of the dialect.
+  pub fn emit(&self, brainfuck: &str, line_width: usize) -> String {
+    if self.is_brainfuck() {
+      return brainfuck.to_string();
+    }
+
+    let separator = if self.spellings.iter().all(|x| x.len() == 1) {
+      ""
+    } else {
+      " "
+    };
+    let mut output = String::new();
+
+    for line in brainfuck.lines() {
+      let indent = &line[..line.len() - line.trim_start().len()];
+      let mut current = String::new();
+
+      let commands = line
+        .chars()
+        .filter_map(|ch| BRAINFUCK.iter().position(|x| x.starts_with(ch)));
+
+      for index in commands {
+        let spelling = &self.spellings[index];
+
+        if !current.is_empty() {
+          if indent.len() + current.len() + separator.len() + spelling.len() > line_width {
+            output.push_str(indent);
+            output.push_str(&current);
+            output.push('\n');
+            current.clear();
+          } else {
+            current.push_str(separator);
+          }
+        }
+
+        current.push_str(spelling);
+      }
+
+      if !current.is_empty() {
+        output.push_str(indent);
+        output.push_str(&current);
+        output.push('\n');
+      }
+    }
+
+    output
+  }
+}
@@ -0,0 +1,226 @@
+use std::error::Error;
+
+use parser::{parse_program_checked_extended, parse_source_extended, Extensions};
+use types::{ParseToken, SourceItem};
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum FormatStyle {
+  // Only the commands, on a single line.
+  Minify,
+  // One line per loop or procedure bracket, with commands packed into lines up to the line width.
+  Pretty,
+}
+
+#[derive(Clone, Copy)]
+pub struct FormatOptions {
+  pub style: FormatStyle,
+  // Spaces per loop level.
+  pub indent: usize,
+  // Comments are never wrapped, so they can be wider.
+  pub line_width: usize,
+  pub keep_comments: bool,
+  pub extensions: Extensions,
+}
+
+impl FormatOptions {
+  pub fn new(style: FormatStyle) -> FormatOptions {
+    FormatOptions {
+      style,
+      indent: 2,
+      line_width: 80,
+      keep_comments: true,
+      extensions: Extensions::default(),
+    }
+  }
+}
+
+fn command_char(token: ParseToken) -> char {
+  match token {
+    ParseToken::IncrAddr => '>',
+    ParseToken::DecrAddr => '<',
+    ParseToken::IncrValue => '+',
+    ParseToken::DecrValue => '-',
+    ParseToken::LoopStart => '[',
+    ParseToken::LoopEnd => ']',
+    ParseToken::Print => '.',
+    ParseToken::Read => ',',
+    ParseToken::ProcedureStart => '(',
+    ParseToken::ProcedureEnd => ')',
+    ParseToken::Call => ':',
+    ParseToken::End => '@',
+    ParseToken::Store => '$',
+    ParseToken::Retrieve => '!',
+    ParseToken::ShiftLeft => '{',
+    ParseToken::ShiftRight => '}',
+    ParseToken::Not => '~',
+    ParseToken::Xor => '^',
+    ParseToken::And => '&',
+    ParseToken::Or => '|',
+  }
+}
+
+enum Word {
+  Command(char),
+  // The brackets of loops and procedures.
+  BlockStart(char),
+  BlockEnd(char),
+  Comment(String),
+}
+
+// The layout only depends on the commands and the trimmed lines of the comments. That's what
+// makes formatting idempotent: formatting the output again sees exactly the same words.
+fn split_words(items: &[(usize, SourceItem)], keep_comments: bool) -> Vec<Word> {
+  let mut words = Vec::new();
+
+  for &(_, ref item) in items {
+    match item {
+      SourceItem::Token(token) => words.push(match token {
+        ParseToken::LoopStart | ParseToken::ProcedureStart => {
+          Word::BlockStart(command_char(*token))
+        }
+        ParseToken::LoopEnd | ParseToken::ProcedureEnd => Word::BlockEnd(command_char(*token)),
+        _ => Word::Command(command_char(*token)),
+      }),
+      SourceItem::Comment(text) => {
+        if keep_comments {
+          words.extend(
+            text
+              .lines()
+              .map(|line| line.trim())
+              .filter(|line| !line.is_empty())
+              .map(|line| Word::Comment(line.to_string())),
+          );
+        }
+      }
+    }
+  }
+
+  words
+}
+
+// Loops without nested loops or comments, like [->+<], can stay on one line.
+fn inline_block(words: &[Word]) -> Option<String> {
+  let mut text = String::new();
+
+  for word in words {
+    match word {
+      Word::Command(ch) => text.push(*ch),
+      Word::BlockStart(ch) if text.is_empty() => text.push(*ch),
+      Word::BlockEnd(ch) => {
+        text.push(*ch);
+        return Some(text);
+      }
+      Word::BlockStart(_) | Word::Comment(_) => return None,
+    }
+  }
+
+  None
+}
+
+struct Printer {
+  options: FormatOptions,
+  output: String,
+  line: String,
+  depth: usize,
+}
+
+impl Printer {
+  fn indent_width(&self) -> usize {
+    self.depth * self.options.indent
+  }
+
+  fn end_line(&mut self) {
+    if !self.line.is_empty() {
+      for _ in 0..self.indent_width() {
+        self.output.push(' ');
+      }
+
+      self.output.push_str(&self.line);
+      self.output.push('\n');
+      self.line.clear();
+    }
+  }
+
+  // Appends to the current line, unless the text doesn't fit on it anymore.
+  fn push_word(&mut self, text: &str) {
+    if !self.line.is_empty()
+      && self.indent_width() + self.line.len() + text.len() > self.options.line_width
+    {
+      self.end_line();
+    }
+
+    self.line.push_str(text);
+  }
+
+  fn push_line(&mut self, text: &str) {
+    self.end_line();
+    self.line.push_str(text);
+    self.end_line();
+  }
+}
+
+fn pretty_print(words: &[Word], options: FormatOptions) -> String {
+  let mut printer = Printer {
+    options,
+    output: String::new(),
+    line: String::new(),
+    depth: 0,
+  };
+  let mut i = 0;
+
+  while i < words.len() {
+    match words[i] {
+      Word::Command(ch) => printer.push_word(&ch.to_string()),
+      Word::Comment(ref line) => printer.push_line(line),
+      Word::BlockStart(bracket) => match inline_block(&words[i..]) {
+        Some(ref text) if printer.indent_width() + text.len() <= options.line_width => {
+          printer.push_word(text);
+          // Every word of an inline loop is a single character.
+          i += text.len();
+          continue;
+        }
+        _ => {
+          printer.push_line(&bracket.to_string());
+          printer.depth += 1;
+        }
+      },
+      Word::BlockEnd(bracket) => {
+        printer.end_line();
+        printer.depth -= 1;
+        printer.push_line(&bracket.to_string());
+      }
+    }
+
+    i += 1;
+  }
+
+  printer.end_line();
+  printer.output
+}
+
+pub fn format_source(source: &str, options: FormatOptions) -> Result<String, Box<Error>> {
+  parse_program_checked_extended(source, options.extensions)?;
+  let items = parse_source_extended(source, options.extensions);
+
+  match options.style {
+    FormatStyle::Minify => {
+      let mut output = split_words(&items, false)
+        .iter()
+        .map(|word| match word {
+          Word::Command(ch) | Word::BlockStart(ch) | Word::BlockEnd(ch) => *ch,
+          Word::Comment(_) => unreachable!(),
+        })
+        .collect::<String>();
+
+      if !output.is_empty() {
+        output.push('\n');
+      }
+
+      Ok(output)
+    }
+    FormatStyle::Pretty => {
+      let words = split_words(&items, options.keep_comments);
+      Ok(pretty_print(&words, options))
+    }
+  }
+}
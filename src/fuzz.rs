@@ -0,0 +1,352 @@
+use std::any::Any;
+use std::panic::{catch_unwind, AssertUnwindSafe};
+
+use backend::bf::{push_repeated, BfBackend};
+use backend::Backend;
+use interpreter::{run_program_with_budget, BufferIo};
+use optimizer::{convert_tokens, optimize};
+use parser::{parse_program, parse_program_checked};
+use types::{ProgramToken, State};
+
+// The unoptimized program gets this many ops. Programs which don't finish in time are skipped.
+pub const STEP_BUDGET: u64 = 10_000;
+
+// Programs start in the middle of the tape. A single op moves the pointer by at most
+// MAX_MOVE, so they can't walk off either end within the budget.
+const TAPE_START: usize = 32768;
+const MAX_MOVE: usize = 3;
+
+const MAX_DEPTH: usize = 3;
+// Some runs of + and - are long enough to wrap the cell around, more than once.
+const MAX_RUN: usize = 300;
+const MAX_INPUT: usize = 8;
+
+// xorshift64*, so that a seed always generates the same program.
+pub struct Rng {
+  state: u64,
+}
+
+impl Rng {
+  // Scrambles the seed with splitmix64, so that nearby seeds give unrelated programs.
+  pub fn new(seed: u64) -> Rng {
+    let mut z = seed.wrapping_add(0x9E37_79B9_7F4A_7C15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+    z ^= z >> 31;
+
+    // The state must never be zero.
+    Rng {
+      state: if z == 0 { 1 } else { z },
+    }
+  }
+
+  pub fn next(&mut self) -> u64 {
+    let mut x = self.state;
+    x ^= x >> 12;
+    x ^= x << 25;
+    x ^= x >> 27;
+    self.state = x;
+    x.wrapping_mul(0x2545_F491_4F6C_DD1D)
+  }
+
+  pub fn below(&mut self, n: usize) -> usize {
+    (self.next() % n as u64) as usize
+  }
+}
+
+fn run_length(rng: &mut Rng) -> usize {
+  if rng.below(16) == 0 {
+    1 + rng.below(MAX_RUN)
+  } else {
+    1 + rng.below(4)
+  }
+}
+
+// Returns how far the block moves the pointer, or None if it contains a loop which doesn't
+// return to where it started.
+fn generate_block(
+  rng: &mut Rng,
+  length: usize,
+  depth: usize,
+  source: &mut String,
+) -> Option<isize> {
+  let mut moved = Some(0);
+
+  for _ in 0..length {
+    let choices = if depth < MAX_DEPTH { 10 } else { 9 };
+
+    match rng.below(choices) {
+      0 | 1 => {
+        let count = run_length(rng);
+        push_repeated(source, '+', count);
+      }
+      2 => {
+        let count = run_length(rng);
+        push_repeated(source, '-', count);
+      }
+      3 | 4 => {
+        let count = 1 + rng.below(MAX_MOVE);
+        push_repeated(source, '>', count);
+        moved = moved.map(|x| x + count as isize);
+      }
+      5 => {
+        let count = 1 + rng.below(MAX_MOVE);
+        push_repeated(source, '<', count);
+        moved = moved.map(|x| x - count as isize);
+      }
+      6 => source.push('.'),
+      7 => source.push(','),
+      8 => source.push_str(if rng.below(2) == 0 { "[-]" } else { "[+]" }),
+      _ => {
+        source.push('[');
+        let body_length = 1 + rng.below(length);
+        let body_moved = generate_block(rng, body_length, depth + 1, source);
+
+        // Most loops return to where they started and count their cell down, so that they
+        // finish. The rest are left to the step budget.
+        match body_moved {
+          Some(body_moved) if rng.below(4) != 0 => {
+            let back = if body_moved > 0 { '<' } else { '>' };
+            push_repeated(source, back, body_moved.abs() as usize);
+            source.push('-');
+          }
+          _ => moved = None,
+        }
+
+        source.push(']');
+      }
+    }
+  }
+
+  moved
+}
+
+pub fn generate_program(rng: &mut Rng, length: usize) -> String {
+  let mut source = String::new();
+  generate_block(rng, length, 0, &mut source);
+  source
+}
+
+pub fn generate_input(rng: &mut Rng) -> Vec<u8> {
+  let length = rng.below(MAX_INPUT + 1);
+  (0..length).map(|_| rng.next() as u8).collect()
+}
+
+fn panic_message(payload: Box<Any + Send>) -> String {
+  if let Some(message) = payload.downcast_ref::<&str>() {
+    message.to_string()
+  } else if let Some(message) = payload.downcast_ref::<String>() {
+    message.clone()
+  } else {
+    "Unknown panic".to_string()
+  }
+}
+
+struct Run {
+  output: Vec<u8>,
+  state: State,
+}
+
+// Returns None if the program runs out of steps.
+fn run(program: &[ProgramToken], input: &[u8], budget: u64) -> Result<Option<Run>, String> {
+  let mut state = State::new();
+  state.pointer = TAPE_START;
+  let mut io = BufferIo::new(input);
+
+  let finished = catch_unwind(AssertUnwindSafe(|| {
+    run_program_with_budget(program, &mut state, &mut io, budget)
+  }))
+  .map_err(panic_message)?
+  .map_err(|error| error.to_string())?
+  .is_some();
+
+  if finished {
+    Ok(Some(Run {
+      output: io.output,
+      state,
+    }))
+  } else {
+    Ok(None)
+  }
+}
+
+fn compare(name: &str, expected: &Run, actual: Result<Option<Run>, String>) -> Result<(), String> {
+  let actual = match actual {
+    Ok(Some(actual)) => actual,
+    Ok(None) => return Err(format!("The {} program ran out of steps.", name)),
+    Err(message) => return Err(format!("The {} program panicked: {}", name, message)),
+  };
+
+  if actual.output != expected.output {
+    return Err(format!(
+      "The {} program printed {:?} instead of {:?}.",
+      name, actual.output, expected.output
+    ));
+  }
+
+  if actual.state.pointer != expected.state.pointer {
+    return Err(format!(
+      "The {} program ended at cell {} instead of {}.",
+      name, actual.state.pointer, expected.state.pointer
+    ));
+  }
+
+  if let Some(cell) =
+    (0..expected.state.memory.len()).find(|&i| actual.state.memory[i] != expected.state.memory[i])
+  {
+    return Err(format!(
+      "In the {} program cell {} is {} instead of {}.",
+      name, cell, actual.state.memory[cell], expected.state.memory[cell]
+    ));
+  }
+
+  Ok(())
+}
+
+// Runs the program with and without optimizations, and the optimized program translated back
+// into Brainfuck. Programs which don't finish unoptimized within the budget pass, since there's
+// nothing to compare them to.
+pub fn check_program(source: &str, input: &[u8], budget: u64) -> Result<(), String> {
+  let program = convert_tokens(&parse_program(source));
+
+  let expected = match run(&program, input, budget) {
+    Ok(Some(expected)) => expected,
+    _ => return Ok(()),
+  };
+
+  let optimized = catch_unwind(|| optimize(&program))
+    .map_err(|x| format!("The optimizer panicked: {}", panic_message(x)))?;
+
+  // Optimizing should only ever save ops, but a miscompiled loop might never finish.
+  compare("optimized", &expected, run(&optimized, input, budget))?;
+
+  let mut code = Vec::new();
+  BfBackend.compile_to_stream(&optimized, &mut code);
+  let round_trip = String::from_utf8(code).unwrap();
+
+  // Clearing a cell with [-] instead of [+] can take many more steps, but optimizing the
+  // translation turns it back into a SetValue.
+  let round_trip = optimize(&convert_tokens(&parse_program(&round_trip)));
+  compare("round-tripped", &expected, run(&round_trip, input, budget))
+}
+
+fn without(source: &str, ranges: &[(usize, usize)]) -> String {
+  source
+    .char_indices()
+    .filter(|&(i, _)| !ranges.iter().any(|&(start, end)| i >= start && i < end))
+    .map(|(_, x)| x)
+    .collect()
+}
+
+fn matching_bracket(source: &[u8], open: usize) -> usize {
+  let mut depth = 0;
+
+  for (i, &x) in source.iter().enumerate().skip(open) {
+    match x {
+      b'[' => depth += 1,
+      b']' if depth == 1 => return i,
+      b']' => depth -= 1,
+      _ => {}
+    }
+  }
+
+  unreachable!("The source should be balanced.")
+}
+
+// Removes chunks of the source, halving the chunk size whenever nothing can be removed.
+fn remove_chunks<F: Fn(&str) -> bool>(source: &mut String, fails: &F) -> bool {
+  let mut changed = false;
+  let mut chunk = (source.len() / 2).max(1);
+
+  loop {
+    let mut start = 0;
+    let mut removed = false;
+
+    while start < source.len() {
+      let end = (start + chunk).min(source.len());
+      let candidate = without(source, &[(start, end)]);
+
+      if parse_program_checked(&candidate).is_ok() && fails(&candidate) {
+        *source = candidate;
+        removed = true;
+      } else {
+        start += chunk;
+      }
+    }
+
+    changed |= removed;
+
+    if !removed {
+      if chunk == 1 {
+        return changed;
+      }
+      chunk /= 2;
+    }
+  }
+}
+
+// Replaces loops with their bodies.
+fn unwrap_loops<F: Fn(&str) -> bool>(source: &mut String, fails: &F) -> bool {
+  let mut changed = false;
+  let mut open = 0;
+
+  while open < source.len() {
+    if source.as_bytes()[open] == b'[' {
+      let close = matching_bracket(source.as_bytes(), open);
+      let candidate = without(source, &[(open, open + 1), (close, close + 1)]);
+
+      if fails(&candidate) {
+        *source = candidate;
+        changed = true;
+        continue;
+      }
+    }
+
+    open += 1;
+  }
+
+  changed
+}
+
+// Removes as much of the source as possible while it still fails. Comments are dropped first.
+pub fn minimize<F: Fn(&str) -> bool>(source: &str, fails: F) -> String {
+  let mut minimized = source
+    .chars()
+    .filter(|x| "<>+-.,[]".contains(*x))
+    .collect::<String>();
+
+  if parse_program_checked(&minimized).is_err() || !fails(&minimized) {
+    return source.to_string();
+  }
+
+  while remove_chunks(&mut minimized, &fails) || unwrap_loops(&mut minimized, &fails) {}
+
+  minimized
+}
+
+pub struct Failure {
+  pub source: String,
+  pub input: Vec<u8>,
+  pub message: String,
+}
+
+// Generates a program from the seed and checks it, minimizing it if it fails.
+pub fn fuzz_seed(seed: u64) -> Option<Failure> {
+  let mut rng = Rng::new(seed);
+  let length = 1 + rng.below(24);
+  let source = generate_program(&mut rng, length);
+  let input = generate_input(&mut rng);
+
+  check_program(&source, &input, STEP_BUDGET).err()?;
+
+  let source = minimize(&source, |candidate| {
+    check_program(candidate, &input, STEP_BUDGET).is_err()
+  });
+  let message = check_program(&source, &input, STEP_BUDGET).unwrap_err();
+
+  Some(Failure {
+    source,
+    input,
+    message,
+  })
+}
@@ -1,7 +1,10 @@
 use std;
-use std::io::Read;
+use std::error::Error;
+use std::fmt;
+use std::io::{Read, Write};
 
-use types::{ProgramToken, State};
+use tape::Tape;
+use types::ProgramToken;
 
 pub trait BfIo {
   fn print(&mut self, ch: u8);
@@ -15,45 +18,423 @@ impl BfIo for ConsoleIo {
     print!("{}", ch as char);
   }
 
+  // The end of the input reads as 0.
   fn read(&mut self) -> u8 {
+    std::io::stdout().flush().unwrap();
+
     let mut buffer = [0u8];
-    std::io::stdin().read_exact(&mut buffer).unwrap();
-    buffer[0]
+    match std::io::stdin().read(&mut buffer).unwrap() {
+      0 => 0,
+      _ => buffer[0],
+    }
+  }
+}
+
+// Reads from a buffer, then 0 at its end, and collects the output.
+pub struct BufferIo<'a> {
+  pub input: &'a [u8],
+  pub output: Vec<u8>,
+}
+
+impl<'a> BufferIo<'a> {
+  pub fn new(input: &'a [u8]) -> BufferIo<'a> {
+    BufferIo {
+      input,
+      output: Vec::new(),
+    }
+  }
+}
+
+impl<'a> BfIo for BufferIo<'a> {
+  fn print(&mut self, ch: u8) {
+    self.output.push(ch);
+  }
+
+  fn read(&mut self) -> u8 {
+    match self.input.split_first() {
+      Some((&ch, rest)) => {
+        self.input = rest;
+        ch
+      }
+      None => 0,
+    }
+  }
+}
+
+// Why a program couldn't run to its end.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum RunError {
+  // A loop started an iteration in the same state as an earlier one, so it repeats forever.
+  EndlessLoop { cell: isize },
+  // : was run on a cell no procedure has been defined for.
+  UndefinedProcedure { number: u8 },
+  // More than MAX_CALL_DEPTH procedure calls were running at once.
+  RecursionTooDeep,
+  // A cell left of the start of the tape was accessed.
+  PointerUnderflow { cell: isize },
+  // A cell past the end of a tape which can't grow was accessed.
+  PointerOverflow { cell: isize },
+}
+
+impl fmt::Display for RunError {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match *self {
+      RunError::EndlessLoop { cell } => write!(
+        f,
+        "The loop on cell {} never ends, because it keeps repeating the same state.",
+        cell
+      ),
+      RunError::UndefinedProcedure { number } => {
+        write!(f, "Procedure {} isn't defined.", number)
+      }
+      RunError::RecursionTooDeep => write!(f, "Procedures are nested too deeply."),
+      RunError::PointerUnderflow { cell } => {
+        write!(f, "Cell {} is left of the start of the tape.", cell)
+      }
+      RunError::PointerOverflow { cell } => write!(f, "Cell {} is past the end of the tape.", cell),
+    }
+  }
+}
+
+impl Error for RunError {}
+
+pub fn run_program<T: Tape + ?Sized>(
+  program: &[ProgramToken],
+  tape: &mut T,
+  io: &mut impl BfIo,
+) -> Result<(), RunError> {
+  execute(program, tape, io, &mut 0, u64::max_value(), false)?;
+  Ok(())
+}
+
+// Like run_program, but stops loops which never end with RunError::EndlessLoop. Keeping track of
+// the state makes every write slower, so the other runners don't.
+pub fn run_program_detecting_endless_loops<T: Tape + ?Sized>(
+  program: &[ProgramToken],
+  tape: &mut T,
+  io: &mut impl BfIo,
+) -> Result<(), RunError> {
+  execute(program, tape, io, &mut 0, u64::max_value(), true)?;
+  Ok(())
+}
+
+// Returns the number of ops executed, counting every check of a loop condition as one op.
+pub fn run_program_counting<T: Tape + ?Sized>(
+  program: &[ProgramToken],
+  tape: &mut T,
+  io: &mut impl BfIo,
+) -> Result<u64, RunError> {
+  let mut ops = 0;
+  execute(program, tape, io, &mut ops, u64::max_value(), false)?;
+  Ok(ops)
+}
+
+// Stops after the given number of ops. Returns the number of ops executed, or None if the
+// program didn't finish in time.
+pub fn run_program_with_budget<T: Tape + ?Sized>(
+  program: &[ProgramToken],
+  tape: &mut T,
+  io: &mut impl BfIo,
+  budget: u64,
+) -> Result<Option<u64>, RunError> {
+  let mut ops = 0;
+
+  if execute(program, tape, io, &mut ops, budget, false)? {
+    Ok(Some(ops))
+  } else {
+    Ok(None)
   }
 }
 
-pub fn run_program(program: &[ProgramToken], state: &mut State, io: &mut impl BfIo) {
-  let mut instruction_pointer = 0;
+// Procedures are numbered by a cell, so there can be one for every byte.
+const PROCEDURES: usize = 256;
+// Procedures can call themselves, so runaway recursion is stopped. The WebAssembly executor runs
+// calls on the native stack, which can't go much deeper, and both stop at the same depth.
+pub const MAX_CALL_DEPTH: usize = 500;
+
+// Everything the rest of a loop depends on, when it's at the start of an iteration.
+#[derive(Clone, Copy, PartialEq)]
+struct Snapshot {
+  pointer: isize,
+  cells: u64,
+  storage: u8,
+  events: u64,
+}
+
+enum FrameKind {
+  Program,
+  Loop {
+    // The offset of the cell the loop checks.
+    offset: isize,
+    iterations: u64,
+    // The state at the start of an earlier iteration.
+    saved: Snapshot,
+  },
+  Procedure,
+}
+
+struct Frame<'a> {
+  tokens: &'a [ProgramToken],
+  instruction_pointer: usize,
+  kind: FrameKind,
+}
+
+// The hash of the tape is the sum of every cell times the weight of its address, so cells which
+// are 0 don't count, and a write only adds the difference. The weights come from splitmix64 seeded
+// with the address, so that neighbouring cells get unrelated ones. Two different tapes
+// with the same hash would stop a loop which could still end, but that's about as likely as
+// guessing a random 64-bit number.
+fn weight(address: isize) -> u64 {
+  let mut x = (address as u64).wrapping_add(0x9E37_79B9_7F4A_7C15);
+  x = (x ^ (x >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+  x = (x ^ (x >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+  x ^ (x >> 31)
+}
+
+// Without endless loop detection there's no hash to update.
+fn write<T: Tape + ?Sized>(
+  tape: &mut T,
+  cells: &mut Option<u64>,
+  address: isize,
+  old: u8,
+  new: u8,
+) -> Result<(), RunError> {
+  if let Some(ref mut cells) = *cells {
+    let difference = u64::from(new).wrapping_sub(u64::from(old));
+    *cells = cells.wrapping_add(difference.wrapping_mul(weight(address)));
+  }
+
+  tape.set(address, new)
+}
+
+// Loops and calls push a frame instead of recursing, so that deeply recursive procedures don't
+// overflow the stack.
+//
+// A loop which starts an iteration in the same state as an earlier one repeats forever, so with
+// detect_endless_loops the program is stopped with an error instead. Every loop compares the
+// state with the one at its last power of two iterations, which finds a cycle within twice its
+// length (Brent's algorithm). The tape is compared by its hash.
+fn execute<T: Tape + ?Sized>(
+  program: &[ProgramToken],
+  tape: &mut T,
+  io: &mut impl BfIo,
+  ops: &mut u64,
+  budget: u64,
+  detect_endless_loops: bool,
+) -> Result<bool, RunError> {
+  let mut procedures: Vec<Option<&[ProgramToken]>> = vec![None; PROCEDURES];
+  let mut frames = vec![Frame {
+    tokens: program,
+    instruction_pointer: 0,
+    kind: FrameKind::Program,
+  }];
+  let mut call_depth = 0;
+  // The storage byte of Extended Brainfuck Type I.
+  let mut storage = 0u8;
+  // The hash of the tape, relative to how it started.
+  let mut cells = if detect_endless_loops { Some(0u64) } else { None };
+  // Counts I/O and procedure definitions, which the state can't repeat across. Output counts too,
+  // so that loops which keep printing the same thing still run.
+  let mut events = 0u64;
 
   use types::MemoryOp::*;
   use types::ProgramToken::*;
 
-  while let Some(op) = program.get(instruction_pointer) {
-    instruction_pointer += 1;
+  loop {
+    let next = {
+      let frame = frames.last_mut().unwrap();
+      let tokens = frame.tokens;
+      frame.instruction_pointer += 1;
+      tokens.get(frame.instruction_pointer - 1)
+    };
+
+    let op = match next {
+      Some(op) => op,
+      None => {
+        // Loops which run again keep their frame.
+        let finished = {
+          let frame = frames.last_mut().unwrap();
+
+          match frame.kind {
+            FrameKind::Program => return Ok(true),
+            FrameKind::Procedure => {
+              call_depth -= 1;
+              true
+            }
+            FrameKind::Loop {
+              offset,
+              ref mut iterations,
+              ref mut saved,
+            } => {
+              *ops += 1;
+              if *ops > budget {
+                return Ok(false);
+              }
+
+              let cell = tape.pointer() + offset;
+
+              if tape.get(cell)? == 0 {
+                true
+              } else {
+                if let Some(cells) = cells {
+                  let now = Snapshot {
+                    pointer: tape.pointer(),
+                    cells,
+                    storage,
+                    events,
+                  };
+
+                  if now == *saved {
+                    return Err(RunError::EndlessLoop { cell });
+                  }
+
+                  *iterations += 1;
+                  if iterations.is_power_of_two() {
+                    *saved = now;
+                  }
+                }
+
+                frame.instruction_pointer = 0;
+                false
+              }
+            }
+          }
+        };
+
+        if finished {
+          frames.pop();
+        }
+
+        continue;
+      }
+    };
+
+    *ops += 1;
+    if *ops > budget {
+      return Ok(false);
+    }
+
     match op {
       ChangeAddr(by) => {
-        state.pointer = ((state.pointer as isize) + by) as usize;
+        tape.move_pointer(*by);
       }
       Loop(body) => {
-        while state.memory[state.pointer as usize] != 0 {
-          run_program(body, state, io);
+        if tape.get(tape.pointer())? != 0 {
+          frames.push(Frame {
+            tokens: body,
+            instruction_pointer: 0,
+            kind: FrameKind::Loop {
+              offset: 0,
+              iterations: 0,
+              saved: Snapshot {
+                pointer: tape.pointer(),
+                cells: cells.unwrap_or(0),
+                storage,
+                events,
+              },
+            },
+          });
+        }
+      }
+      OffsetLoop(offset, body) => {
+        if tape.get(tape.pointer() + offset)? != 0 {
+          frames.push(Frame {
+            tokens: body,
+            instruction_pointer: 0,
+            kind: FrameKind::Loop {
+              offset: *offset,
+              iterations: 0,
+              saved: Snapshot {
+                pointer: tape.pointer(),
+                cells: cells.unwrap_or(0),
+                storage,
+                events,
+              },
+            },
+          });
         }
       }
+      Procedure(body) => {
+        events += 1;
+        procedures[tape.get(tape.pointer())? as usize] = Some(body);
+      }
+      Call => {
+        let number = tape.get(tape.pointer())?;
+        let body = match procedures[number as usize] {
+          Some(body) => body,
+          None => return Err(RunError::UndefinedProcedure { number }),
+        };
+
+        call_depth += 1;
+        if call_depth > MAX_CALL_DEPTH {
+          return Err(RunError::RecursionTooDeep);
+        }
+
+        frames.push(Frame {
+          tokens: body,
+          instruction_pointer: 0,
+          kind: FrameKind::Procedure,
+        });
+      }
       Offset(offset, op) => {
-        let address = (state.pointer as isize)
-          .checked_add(*offset as isize)
-          .expect("Pointer shouldn't over- or underflow.") as usize;
+        let address = tape
+          .pointer()
+          .checked_add(*offset)
+          .expect("Pointer shouldn't over- or underflow.");
 
         match op {
           ChangeValue(value) => {
-            state.memory[address] =
-              ((state.memory[address] as isize).wrapping_add(*value as isize)) as u8;
+            let old = tape.get(address)?;
+            write(
+              tape,
+              &mut cells,
+              address,
+              old,
+              old.wrapping_add(*value as u8),
+            )?;
           }
           SetValue(value) => {
-            state.memory[address] = *value as u8;
+            let old = tape.get(address)?;
+            write(tape, &mut cells, address, old, *value as u8)?;
           }
           Print => {
-            io.print(state.memory[address]);
+            events += 1;
+            io.print(tape.get(address)?);
+          }
+          Read => {
+            events += 1;
+            let old = tape.get(address)?;
+            let value = io.read();
+            write(tape, &mut cells, address, old, value)?;
+          }
+          Store => storage = tape.get(address)?,
+          Retrieve => {
+            let old = tape.get(address)?;
+            write(tape, &mut cells, address, old, storage)?;
+          }
+          ShiftLeft => {
+            let old = tape.get(address)?;
+            write(tape, &mut cells, address, old, old << 1)?;
+          }
+          ShiftRight => {
+            let old = tape.get(address)?;
+            write(tape, &mut cells, address, old, old >> 1)?;
+          }
+          Not => {
+            let old = tape.get(address)?;
+            write(tape, &mut cells, address, old, !old)?;
+          }
+          Xor => {
+            let old = tape.get(address)?;
+            write(tape, &mut cells, address, old, old ^ storage)?;
+          }
+          And => {
+            let old = tape.get(address)?;
+            write(tape, &mut cells, address, old, old & storage)?;
+          }
+          Or => {
+            let old = tape.get(address)?;
+            write(tape, &mut cells, address, old, old | storage)?;
           }
         }
       }
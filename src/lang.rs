@@ -0,0 +1,539 @@
+use std::collections::HashMap;
+use std::error::Error;
+
+use parser::line_and_column;
+use types::MemoryOp::*;
+use types::ProgramToken;
+use types::ProgramToken::*;
+
+// A small structured language which compiles to the IR:
+//
+// var x = 'A';          Variables are global, and each one gets a cell of its own.
+// var n = 3;
+// while n {             Loops while n isn't 0.
+//   print x;            Prints x as a byte.
+//   x += 1;             = += and -= take a number from 0 to 255, a character or a variable.
+//   n -= 1;
+// }
+// if x { print "yes\n"; } else { print "no\n"; }
+// read x;               // Comments run to the end of the line.
+
+#[derive(Clone, Debug, PartialEq)]
+enum Token {
+  Identifier(String),
+  Number(u8),
+  Text(Vec<u8>),
+  Symbol(&'static str),
+}
+
+const SYMBOLS: &[&str] = &["+=", "-=", "=", ";", "{", "}"];
+const KEYWORDS: &[&str] = &["var", "while", "if", "else", "print", "read"];
+
+fn error_at(source: &str, offset: usize, message: &str) -> Box<Error> {
+  let (line, column) = line_and_column(source, offset);
+  From::from(format!("{} at line {}, column {}", message, line, column))
+}
+
+// Reads the character after a backslash.
+fn escape(x: char) -> Option<char> {
+  match x {
+    'n' => Some('\n'),
+    't' => Some('\t'),
+    '0' => Some('\0'),
+    '\\' | '"' | '\'' => Some(x),
+    _ => None,
+  }
+}
+
+// Reads a string or character literal starting at the quote, and returns its bytes and length.
+fn read_literal(source: &str, start: usize, quote: char) -> Result<(Vec<u8>, usize), Box<Error>> {
+  let mut bytes = Vec::new();
+  let mut chars = source[start + 1..].char_indices();
+
+  while let Some((offset, x)) = chars.next() {
+    let x = match x {
+      '\n' => break,
+      '\\' => match chars.next().and_then(|(_, x)| escape(x)) {
+        Some(x) => x,
+        None => return Err(error_at(source, start + 1 + offset, "Unknown escape")),
+      },
+      _ if x == quote => return Ok((bytes, offset + 2)),
+      _ => x,
+    };
+
+    let mut buffer = [0; 4];
+    bytes.extend_from_slice(x.encode_utf8(&mut buffer).as_bytes());
+  }
+
+  Err(error_at(source, start, "Unterminated literal"))
+}
+
+fn tokenize(source: &str) -> Result<Vec<(usize, Token)>, Box<Error>> {
+  let mut tokens = Vec::new();
+  let mut offset = 0;
+
+  while let Some(x) = source[offset..].chars().next() {
+    let rest = &source[offset..];
+
+    if x.is_whitespace() {
+      offset += x.len_utf8();
+    } else if rest.starts_with("//") {
+      offset += rest.find('\n').unwrap_or(rest.len());
+    } else if x.is_ascii_alphabetic() || x == '_' {
+      let length = rest
+        .find(|x: char| !x.is_ascii_alphanumeric() && x != '_')
+        .unwrap_or(rest.len());
+      tokens.push((offset, Token::Identifier(rest[..length].to_string())));
+      offset += length;
+    } else if x.is_ascii_digit() {
+      let length = rest
+        .find(|x: char| !x.is_ascii_digit())
+        .unwrap_or(rest.len());
+      let value = rest[..length]
+        .parse::<u8>()
+        .map_err(|_| error_at(source, offset, "Numbers go from 0 to 255"))?;
+      tokens.push((offset, Token::Number(value)));
+      offset += length;
+    } else if x == '"' || x == '\'' {
+      let (bytes, length) = read_literal(source, offset, x)?;
+
+      let token = match (x, bytes.len()) {
+        ('"', _) => Token::Text(bytes),
+        (_, 1) => Token::Number(bytes[0]),
+        _ => {
+          return Err(error_at(source, offset, "Characters must be a single byte"));
+        }
+      };
+
+      tokens.push((offset, token));
+      offset += length;
+    } else {
+      match SYMBOLS.iter().find(|symbol| rest.starts_with(*symbol)) {
+        Some(symbol) => {
+          tokens.push((offset, Token::Symbol(symbol)));
+          offset += symbol.len();
+        }
+        None => {
+          return Err(error_at(source, offset, &format!("Unexpected {}", x)));
+        }
+      }
+    }
+  }
+
+  Ok(tokens)
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Value {
+  Variable(String, usize),
+  Constant(u8),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Assignment {
+  Set,
+  Add,
+  Subtract,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Statement {
+  Declare(String, usize, Option<Value>),
+  Assign(String, usize, Assignment, Value),
+  While(String, usize, Vec<Statement>),
+  If(String, usize, Vec<Statement>, Option<Vec<Statement>>),
+  PrintText(Vec<u8>),
+  Print(Value),
+  Read(String, usize),
+}
+
+struct Parser<'a> {
+  source: &'a str,
+  tokens: Vec<(usize, Token)>,
+  position: usize,
+}
+
+impl<'a> Parser<'a> {
+  fn peek(&self) -> Option<&Token> {
+    self.tokens.get(self.position).map(|&(_, ref token)| token)
+  }
+
+  // Where the next token starts, or the end of the source.
+  fn offset(&self) -> usize {
+    self
+      .tokens
+      .get(self.position)
+      .map_or(self.source.len(), |&(offset, _)| offset)
+  }
+
+  fn next(&mut self, expected: &str) -> Result<Token, Box<Error>> {
+    match self.tokens.get(self.position).cloned() {
+      Some((_, token)) => {
+        self.position += 1;
+        Ok(token)
+      }
+      None => Err(self.error(&format!("Expected {}", expected))),
+    }
+  }
+
+  fn error(&self, message: &str) -> Box<Error> {
+    error_at(self.source, self.offset(), message)
+  }
+
+  fn expect(&mut self, symbol: &'static str) -> Result<(), Box<Error>> {
+    if self.peek() == Some(&Token::Symbol(symbol)) {
+      self.position += 1;
+      Ok(())
+    } else {
+      Err(self.error(&format!("Expected {}", symbol)))
+    }
+  }
+
+  fn identifier(&mut self) -> Result<(String, usize), Box<Error>> {
+    let offset = self.offset();
+
+    match self.next("a variable")? {
+      Token::Identifier(ref name) if KEYWORDS.contains(&&name[..]) => {
+        Err(error_at(self.source, offset, "Expected a variable"))
+      }
+      Token::Identifier(name) => Ok((name, offset)),
+      _ => Err(error_at(self.source, offset, "Expected a variable")),
+    }
+  }
+
+  fn value(&mut self) -> Result<Value, Box<Error>> {
+    let offset = self.offset();
+
+    match self.next("a value")? {
+      Token::Identifier(ref name) if KEYWORDS.contains(&&name[..]) => {
+        Err(error_at(self.source, offset, "Expected a value"))
+      }
+      Token::Identifier(name) => Ok(Value::Variable(name, offset)),
+      Token::Number(value) => Ok(Value::Constant(value)),
+      _ => Err(error_at(self.source, offset, "Expected a value")),
+    }
+  }
+
+  fn block(&mut self) -> Result<Vec<Statement>, Box<Error>> {
+    self.expect("{")?;
+    let mut statements = Vec::new();
+
+    while self.peek() != Some(&Token::Symbol("}")) {
+      if self.peek().is_none() {
+        return Err(self.error("Expected }"));
+      }
+
+      statements.push(self.statement()?);
+    }
+
+    self.position += 1;
+    Ok(statements)
+  }
+
+  fn statement(&mut self) -> Result<Statement, Box<Error>> {
+    let offset = self.offset();
+    let keyword = match self.next("a statement")? {
+      Token::Identifier(keyword) => keyword,
+      _ => return Err(error_at(self.source, offset, "Expected a statement")),
+    };
+
+    let statement = match &keyword[..] {
+      "var" => {
+        let (name, offset) = self.identifier()?;
+        let value = if self.peek() == Some(&Token::Symbol("=")) {
+          self.position += 1;
+          Some(self.value()?)
+        } else {
+          None
+        };
+        Statement::Declare(name, offset, value)
+      }
+      "while" => {
+        let (name, offset) = self.identifier()?;
+        return Ok(Statement::While(name, offset, self.block()?));
+      }
+      "if" => {
+        let (name, offset) = self.identifier()?;
+        let then = self.block()?;
+        let otherwise = if self.peek() == Some(&Token::Identifier("else".to_string())) {
+          self.position += 1;
+          Some(self.block()?)
+        } else {
+          None
+        };
+        return Ok(Statement::If(name, offset, then, otherwise));
+      }
+      "print" => match self.peek().cloned() {
+        Some(Token::Text(text)) => {
+          self.position += 1;
+          Statement::PrintText(text)
+        }
+        _ => Statement::Print(self.value()?),
+      },
+      "read" => {
+        let (name, offset) = self.identifier()?;
+        Statement::Read(name, offset)
+      }
+      "else" => return Err(error_at(self.source, offset, "else without if")),
+      _ => {
+        let assignment = match self.peek() {
+          Some(&Token::Symbol("=")) => Assignment::Set,
+          Some(&Token::Symbol("+=")) => Assignment::Add,
+          Some(&Token::Symbol("-=")) => Assignment::Subtract,
+          _ => return Err(self.error("Expected =, += or -=")),
+        };
+        self.position += 1;
+        Statement::Assign(keyword, offset, assignment, self.value()?)
+      }
+    };
+
+    self.expect(";")?;
+    Ok(statement)
+  }
+}
+
+fn count_declarations(statements: &[Statement]) -> usize {
+  statements
+    .iter()
+    .map(|statement| match statement {
+      Statement::Declare(..) => 1,
+      Statement::While(_, _, body) => count_declarations(body),
+      Statement::If(_, _, then, otherwise) => {
+        count_declarations(then) + otherwise.as_ref().map_or(0, |x| count_declarations(x))
+      }
+      _ => 0,
+    })
+    .sum()
+}
+
+// Runs the body while the cell isn't 0. The pointer stays at cell 0, so loops check their cell by
+// offset.
+fn loop_on(cell: isize, body: Vec<ProgramToken>) -> ProgramToken {
+  if cell == 0 {
+    Loop(body)
+  } else {
+    OffsetLoop(cell, body)
+  }
+}
+
+// Adds the source cell to the target, times the factor. The source is moved to the temporary cell
+// and back, so it's unchanged.
+fn add_cell(target: isize, source: isize, factor: i8, temporary: isize) -> Vec<ProgramToken> {
+  vec![
+    loop_on(
+      source,
+      vec![
+        Offset(source, ChangeValue(-1)),
+        Offset(target, ChangeValue(factor)),
+        Offset(temporary, ChangeValue(1)),
+      ],
+    ),
+    loop_on(
+      temporary,
+      vec![
+        Offset(temporary, ChangeValue(-1)),
+        Offset(source, ChangeValue(1)),
+      ],
+    ),
+  ]
+}
+
+// Variables come first on the tape, and temporary cells go after them. Temporary cells are 0
+// whenever they're free.
+struct Lowering<'a> {
+  source: &'a str,
+  variables: HashMap<String, isize>,
+  next_variable: isize,
+  next_temporary: isize,
+}
+
+impl<'a> Lowering<'a> {
+  fn variable(&self, name: &str, offset: usize) -> Result<isize, Box<Error>> {
+    match self.variables.get(name) {
+      Some(&cell) => Ok(cell),
+      None => Err(error_at(
+        self.source,
+        offset,
+        &format!("Undefined variable {}", name),
+      )),
+    }
+  }
+
+  fn allocate(&mut self) -> isize {
+    self.next_temporary += 1;
+    self.next_temporary - 1
+  }
+
+  fn release(&mut self, cell: isize) {
+    assert_eq!(self.next_temporary - 1, cell);
+    self.next_temporary = cell;
+  }
+
+  fn assign(
+    &mut self,
+    cell: isize,
+    assignment: Assignment,
+    value: &Value,
+  ) -> Result<Vec<ProgramToken>, Box<Error>> {
+    let source = match *value {
+      Value::Constant(value) => {
+        let op = match assignment {
+          Assignment::Set => SetValue(value as i8),
+          Assignment::Add => ChangeValue(value as i8),
+          Assignment::Subtract => ChangeValue((value as i8).wrapping_neg()),
+        };
+        return Ok(vec![Offset(cell, op)]);
+      }
+      Value::Variable(ref name, offset) => self.variable(name, offset)?,
+    };
+
+    let temporary = self.allocate();
+
+    let tokens = match assignment {
+      Assignment::Set if source == cell => vec![],
+      Assignment::Set => {
+        let mut tokens = vec![Offset(cell, SetValue(0))];
+        tokens.extend(add_cell(cell, source, 1, temporary));
+        tokens
+      }
+      // Moving x to the temporary cell and adding it back twice doubles it.
+      Assignment::Add if source == cell => vec![
+        loop_on(
+          cell,
+          vec![
+            Offset(cell, ChangeValue(-1)),
+            Offset(temporary, ChangeValue(1)),
+          ],
+        ),
+        loop_on(
+          temporary,
+          vec![
+            Offset(temporary, ChangeValue(-1)),
+            Offset(cell, ChangeValue(2)),
+          ],
+        ),
+      ],
+      Assignment::Subtract if source == cell => vec![Offset(cell, SetValue(0))],
+      Assignment::Add => add_cell(cell, source, 1, temporary),
+      Assignment::Subtract => add_cell(cell, source, -1, temporary),
+    };
+
+    self.release(temporary);
+    Ok(tokens)
+  }
+
+  fn lower(&mut self, statements: &[Statement]) -> Result<Vec<ProgramToken>, Box<Error>> {
+    let mut tokens = Vec::new();
+
+    for statement in statements {
+      match statement {
+        Statement::Declare(name, offset, value) => {
+          if self.variables.contains_key(name) {
+            return Err(error_at(
+              self.source,
+              *offset,
+              &format!("{} is already declared", name),
+            ));
+          }
+
+          let cell = self.next_variable;
+          self.variables.insert(name.clone(), cell);
+          self.next_variable += 1;
+
+          let value = value.clone().unwrap_or(Value::Constant(0));
+          tokens.extend(self.assign(cell, Assignment::Set, &value)?);
+        }
+        Statement::Assign(name, offset, assignment, value) => {
+          let cell = self.variable(name, *offset)?;
+          tokens.extend(self.assign(cell, *assignment, value)?);
+        }
+        Statement::While(name, offset, body) => {
+          let cell = self.variable(name, *offset)?;
+          let body = self.lower(body)?;
+          tokens.push(loop_on(cell, body));
+        }
+        // The condition is copied, so that the branches can change it. The then branch clears
+        // the copy so that it runs once, and the else flag so that the else branch doesn't run.
+        Statement::If(name, offset, then, otherwise) => {
+          let cell = self.variable(name, *offset)?;
+          let condition = self.allocate();
+          let else_flag = self.allocate();
+
+          let scratch = self.allocate();
+          tokens.extend(add_cell(condition, cell, 1, scratch));
+          self.release(scratch);
+
+          let mut then = self.lower(then)?;
+          then.push(Offset(condition, SetValue(0)));
+
+          match otherwise {
+            Some(otherwise) => {
+              tokens.push(Offset(else_flag, SetValue(1)));
+              then.push(Offset(else_flag, SetValue(0)));
+              tokens.push(loop_on(condition, then));
+
+              let mut otherwise = self.lower(otherwise)?;
+              otherwise.push(Offset(else_flag, SetValue(0)));
+              tokens.push(loop_on(else_flag, otherwise));
+            }
+            None => tokens.push(loop_on(condition, then)),
+          }
+
+          self.release(else_flag);
+          self.release(condition);
+        }
+        Statement::PrintText(text) => {
+          let temporary = self.allocate();
+
+          for &x in text {
+            tokens.push(Offset(temporary, SetValue(x as i8)));
+            tokens.push(Offset(temporary, Print));
+          }
+
+          tokens.push(Offset(temporary, SetValue(0)));
+          self.release(temporary);
+        }
+        Statement::Print(Value::Variable(name, offset)) => {
+          let cell = self.variable(name, *offset)?;
+          tokens.push(Offset(cell, Print));
+        }
+        Statement::Print(Value::Constant(x)) => {
+          let temporary = self.allocate();
+          tokens.push(Offset(temporary, SetValue(*x as i8)));
+          tokens.push(Offset(temporary, Print));
+          tokens.push(Offset(temporary, SetValue(0)));
+          self.release(temporary);
+        }
+        Statement::Read(name, offset) => {
+          let cell = self.variable(name, *offset)?;
+          tokens.push(Offset(cell, Read));
+        }
+      }
+    }
+
+    Ok(tokens)
+  }
+}
+
+// Compiles a structured program to the IR. The pointer stays at cell 0 between statements.
+pub fn compile_structured(source: &str) -> Result<Vec<ProgramToken>, Box<Error>> {
+  let mut parser = Parser {
+    source,
+    tokens: tokenize(source)?,
+    position: 0,
+  };
+
+  let mut statements = Vec::new();
+  while parser.peek().is_some() {
+    statements.push(parser.statement()?);
+  }
+
+  let mut lowering = Lowering {
+    source,
+    variables: HashMap::new(),
+    next_variable: 0,
+    next_temporary: count_declarations(&statements) as isize,
+  };
+
+  lowering.lower(&statements)
+}
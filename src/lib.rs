@@ -5,14 +5,27 @@
 extern crate byteorder;
 extern crate leb128;
 
+pub mod analysis;
 pub mod backend;
+pub mod bench;
+pub mod codegen;
+pub mod dialect;
+pub mod formatter;
+pub mod fuzz;
 pub mod interpreter;
+pub mod lang;
+pub mod lint;
 pub mod optimizer;
 pub mod parser;
+pub mod preprocessor;
 pub mod pseudo_c_formatter;
+pub mod repl;
+pub mod tape;
 pub mod types;
 
-use types::ProgramToken;
+use backend::wasm::{DebugInfo, WasmModule, WasmOptions, WasmTarget};
+use preprocessor::Expansion;
+use types::{ParseToken, ProgramToken};
 
 pub fn compile_program(source: &str) -> Vec<u8> {
   let tokens = parser::parse_program(source);
@@ -34,3 +47,92 @@ pub fn compile_tokens(tokens: &[ProgramToken], optimize: bool) -> Vec<u8> {
   backend.compile_to_stream(&optimized, &mut code);
   code
 }
+
+// Source offsets of the tokens convert_tokens produces for this source, in pre-order.
+// Loop ends don't become tokens of their own.
+pub fn program_token_offsets(source: &str) -> Vec<usize> {
+  parser::parse_program_with_offsets(source)
+    .into_iter()
+    .filter(|(_, token)| *token != ParseToken::LoopEnd)
+    .map(|(offset, _)| offset)
+    .collect()
+}
+
+// Compiles a program to WebAssembly with a "bfcrs" debug section. token_offsets are the source
+// offsets of its tokens, from program_token_offsets. Optimized programs can't be mapped back to
+// the source, so they don't have any.
+pub fn compile_program_with_debug_info(
+  program: &[ProgramToken],
+  token_offsets: Option<Vec<usize>>,
+  source: &str,
+  options: WasmOptions,
+  embed_source: bool,
+) -> Vec<u8> {
+  compile_with_debug_info(program, token_offsets, source, options, embed_source)
+}
+
+// Like compile_program_with_debug_info, for a program expanded from macros. token_offsets are
+// offsets in the expanded program. The debug section describes the root file, and maps every
+// token to where it was expanded in it.
+pub fn compile_expansion_with_debug_info(
+  program: &[ProgramToken],
+  token_offsets: Option<Vec<usize>>,
+  expansion: &Expansion,
+  options: WasmOptions,
+  embed_source: bool,
+) -> Vec<u8> {
+  let token_offsets = token_offsets.map(|offsets| {
+    offsets
+      .into_iter()
+      .map(|offset| expansion.root_offset(offset))
+      .collect()
+  });
+
+  compile_with_debug_info(
+    program,
+    token_offsets,
+    expansion.root_source(),
+    options,
+    embed_source,
+  )
+}
+
+fn compile_with_debug_info(
+  program: &[ProgramToken],
+  token_offsets: Option<Vec<usize>>,
+  source: &str,
+  options: WasmOptions,
+  embed_source: bool,
+) -> Vec<u8> {
+  let target_name = match options.target {
+    WasmTarget::Bfcrs => "wasm",
+    WasmTarget::Wasi => "wasi",
+  };
+
+  let optimized = token_offsets.is_none();
+  let mut recorded_options = vec![
+    ("target".to_string(), target_name.to_string()),
+    ("optimize".to_string(), optimized.to_string()),
+  ];
+
+  if let Some(max_pages) = options.max_pages {
+    recorded_options.push(("max-pages".to_string(), max_pages.to_string()));
+  }
+
+  let debug_info = DebugInfo {
+    source,
+    options: recorded_options,
+    embed_source,
+    token_offsets,
+  };
+
+  let mut code = Vec::new();
+  WasmModule::write_with_debug_info(&mut code, program, &options, &debug_info).unwrap();
+  code
+}
+
+pub fn compile_tokens_with_options(tokens: &[ProgramToken], options: WasmOptions) -> Vec<u8> {
+  let mut code = Vec::new();
+  WasmModule::write_with_options(&mut code, tokens, &options).unwrap();
+  code
+}
@@ -0,0 +1,388 @@
+use std::collections::{HashMap, HashSet};
+use std::error::Error;
+
+use parser::{parse_program_checked_extended, parse_program_with_offsets_extended, Extensions};
+use types::ParseToken;
+use types::ParseToken::*;
+
+// A likely mistake in a program. start and end are the byte offsets of the code it's about.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Lint {
+  pub name: &'static str,
+  pub message: String,
+  pub start: usize,
+  pub end: usize,
+}
+
+enum Node {
+  Command(usize, ParseToken),
+  // The offsets of the brackets, and the body between them.
+  Loop(usize, usize, Vec<Node>),
+  Procedure(usize, usize, Vec<Node>),
+}
+
+impl Node {
+  fn start(&self) -> usize {
+    match *self {
+      Node::Command(offset, _) | Node::Loop(offset, _, _) | Node::Procedure(offset, _, _) => offset,
+    }
+  }
+
+  // The offset after the node.
+  fn end(&self) -> usize {
+    match *self {
+      Node::Command(offset, _) | Node::Loop(_, offset, _) | Node::Procedure(_, offset, _) => {
+        offset + 1
+      }
+    }
+  }
+}
+
+// Builds the nodes up to the bracket which closes them, and moves the position past it.
+fn build_tree(tokens: &[(usize, ParseToken)], position: &mut usize) -> Vec<Node> {
+  let mut nodes = Vec::new();
+
+  while *position < tokens.len() {
+    let (offset, token) = tokens[*position];
+    *position += 1;
+
+    let node = match token {
+      LoopStart => {
+        let body = build_tree(tokens, position);
+        Node::Loop(offset, tokens[*position - 1].0, body)
+      }
+      ProcedureStart => {
+        let body = build_tree(tokens, position);
+        Node::Procedure(offset, tokens[*position - 1].0, body)
+      }
+      LoopEnd | ProcedureEnd => return nodes,
+      _ => Node::Command(offset, token),
+    };
+
+    nodes.push(node);
+  }
+
+  nodes
+}
+
+// The cells a loop body writes, relative to the pointer, if it always ends where it started.
+fn balanced_writes(body: &[Node]) -> Option<HashSet<isize>> {
+  let mut pointer = 0;
+  let mut writes = HashSet::new();
+
+  for node in body {
+    match node {
+      Node::Command(_, IncrAddr) => pointer += 1,
+      Node::Command(_, DecrAddr) => pointer -= 1,
+      Node::Command(_, Print) | Node::Command(_, Store) => {}
+      Node::Command(_, Call) | Node::Command(_, End) | Node::Procedure(..) => return None,
+      Node::Command(..) => {
+        writes.insert(pointer);
+      }
+      Node::Loop(_, _, body) => {
+        writes.extend(balanced_writes(body)?.iter().map(|x| x + pointer));
+      }
+    }
+  }
+
+  if pointer == 0 {
+    Some(writes)
+  } else {
+    None
+  }
+}
+
+// How far a loop body moves the pointer, if every loop in it ends where it started.
+fn movement(body: &[Node]) -> Option<isize> {
+  let mut pointer = 0;
+
+  for node in body {
+    match node {
+      Node::Command(_, IncrAddr) => pointer += 1,
+      Node::Command(_, DecrAddr) => pointer -= 1,
+      Node::Command(_, Call) | Node::Procedure(..) => return None,
+      Node::Command(..) => {}
+      Node::Loop(_, _, body) => {
+        if movement(body) != Some(0) {
+          return None;
+        }
+      }
+    }
+  }
+
+  Some(pointer)
+}
+
+// What's known about the tape at some point in the program.
+#[derive(Clone)]
+struct State {
+  // Where the pointer is relative to where it started, if that's known.
+  pointer: Option<isize>,
+  // The cells which have changed, by offset from the pointer, and their values if they're known.
+  cells: HashMap<isize, Option<u8>>,
+  // Whether every other cell is still 0, like at the start.
+  pristine: bool,
+}
+
+impl State {
+  fn start() -> State {
+    State {
+      pointer: Some(0),
+      cells: HashMap::new(),
+      pristine: true,
+    }
+  }
+
+  fn unknown() -> State {
+    State {
+      pointer: None,
+      cells: HashMap::new(),
+      pristine: false,
+    }
+  }
+
+  fn value(&self, offset: isize) -> Option<u8> {
+    match self.cells.get(&offset) {
+      Some(value) => *value,
+      None if self.pristine => Some(0),
+      None => None,
+    }
+  }
+
+  fn move_pointer(&mut self, by: isize) {
+    self.pointer = self.pointer.map(|pointer| pointer + by);
+    self.cells = self
+      .cells
+      .iter()
+      .map(|(x, value)| (x - by, *value))
+      .collect();
+  }
+}
+
+fn inverse(a: ParseToken, b: ParseToken) -> bool {
+  match (a, b) {
+    (IncrValue, DecrValue) | (DecrValue, IncrValue) => true,
+    (IncrAddr, DecrAddr) | (DecrAddr, IncrAddr) => true,
+    _ => false,
+  }
+}
+
+fn command_char(token: ParseToken) -> char {
+  match token {
+    IncrAddr => '>',
+    DecrAddr => '<',
+    IncrValue => '+',
+    _ => '-',
+  }
+}
+
+struct Linter {
+  lints: Vec<Lint>,
+}
+
+impl Linter {
+  fn report(&mut self, name: &'static str, start: usize, end: usize, message: String) {
+    self.lints.push(Lint {
+      name,
+      message,
+      start,
+      end,
+    });
+  }
+
+  fn find_cancelling_pairs(&mut self, nodes: &[Node]) {
+    let mut i = 0;
+
+    while i + 1 < nodes.len() {
+      match (&nodes[i], &nodes[i + 1]) {
+        (&Node::Command(start, a), &Node::Command(end, b)) if inverse(a, b) => {
+          self.report(
+            "cancelling-commands",
+            start,
+            end + 1,
+            format!("{} and {} cancel out", command_char(a), command_char(b)),
+          );
+          i += 2;
+        }
+        _ => i += 1,
+      }
+    }
+  }
+
+  // Follows the nodes from the given state, until they end or can't go on. depth is the number
+  // of loops around them.
+  fn lint_block(&mut self, nodes: &[Node], mut state: State, depth: usize) {
+    self.find_cancelling_pairs(nodes);
+
+    for (i, node) in nodes.iter().enumerate() {
+      match node {
+        Node::Command(offset, DecrAddr) => {
+          if state.pointer == Some(0) {
+            self.report(
+              "negative-pointer",
+              *offset,
+              *offset + 1,
+              "The pointer moves left of cell 0".to_string(),
+            );
+          }
+          state.move_pointer(-1);
+        }
+        Node::Command(_, IncrAddr) => state.move_pointer(1),
+        Node::Command(_, IncrValue) => {
+          let value = state.value(0).map(|x| x.wrapping_add(1));
+          state.cells.insert(0, value);
+        }
+        Node::Command(_, DecrValue) => {
+          let value = state.value(0).map(|x| x.wrapping_sub(1));
+          state.cells.insert(0, value);
+        }
+        Node::Command(_, Print) | Node::Command(_, Store) => {}
+        // Nothing after @ is parsed.
+        Node::Command(_, End) => return,
+        // Procedures can move the pointer and change any cell.
+        Node::Command(_, Call) => state = State::unknown(),
+        Node::Command(..) => {
+          state.cells.insert(0, None);
+        }
+        Node::Procedure(_, _, body) => {
+          self.lint_block(body, State::unknown(), depth);
+        }
+        Node::Loop(start, end, body) => {
+          let (after, ends) = self.lint_loop(*start, *end, body, &state, depth);
+
+          if !ends {
+            if let Some(next) = nodes.get(i + 1) {
+              self.report(
+                "unreachable-code",
+                next.start(),
+                nodes.last().unwrap().end(),
+                "This code never runs, because the loop before it never ends".to_string(),
+              );
+            }
+            return;
+          }
+
+          state = after;
+        }
+      }
+    }
+  }
+
+  // Returns the state after the loop, and whether it can end.
+  fn lint_loop(
+    &mut self,
+    start: usize,
+    end: usize,
+    body: &[Node],
+    state: &State,
+    depth: usize,
+  ) -> (State, bool) {
+    let condition = state.value(0);
+
+    if condition == Some(0) {
+      match body {
+        [Node::Command(_, IncrValue)] | [Node::Command(_, DecrValue)] => self.report(
+          "redundant-clear",
+          start,
+          end + 1,
+          "The cell is already 0, so clearing it does nothing".to_string(),
+        ),
+        _ => self.report(
+          "dead-loop",
+          start,
+          end + 1,
+          "This loop never runs, because the cell is always 0 here".to_string(),
+        ),
+      }
+
+      return (state.clone(), true);
+    }
+
+    if depth > 0 {
+      match movement(body) {
+        Some(by) if by != 0 && body.iter().any(|node| !is_move(node)) => self.report(
+          "unbalanced-loop",
+          start,
+          end + 1,
+          format!(
+            "This loop moves the pointer by {} every time it runs inside another loop, so its \
+             moves can't be turned into offsets",
+            by
+          ),
+        ),
+        _ => {}
+      }
+    }
+
+    let writes = balanced_writes(body);
+
+    // A loop whose body never writes its cell can't end once it starts.
+    let endless = writes.as_ref().map_or(false, |writes| !writes.contains(&0));
+
+    if endless {
+      let message = if condition.is_some() {
+        "This loop never ends, because its cell isn't 0 and the loop never changes it"
+      } else {
+        "This loop never ends once it starts, because it never changes its cell"
+      };
+
+      self.report("endless-loop", start, end + 1, message.to_string());
+    }
+
+    // Cells the body doesn't write keep their values however often it runs.
+    let mut inside = match writes {
+      Some(writes) => {
+        let mut inside = state.clone();
+        for offset in writes {
+          inside.cells.insert(offset, None);
+        }
+        inside
+      }
+      None => State::unknown(),
+    };
+
+    self.lint_block(body, inside.clone(), depth + 1);
+
+    inside.cells.insert(0, Some(0));
+    (inside, !endless || condition.is_none())
+  }
+}
+
+fn is_move(node: &Node) -> bool {
+  match node {
+    Node::Command(_, IncrAddr) | Node::Command(_, DecrAddr) => true,
+    _ => false,
+  }
+}
+
+// Looks for code which is probably a mistake:
+// dead-loop: a loop which never runs, because its cell is always 0.
+// redundant-clear: [-] on a cell which is already 0.
+// endless-loop: a loop which never changes its cell, so it never ends once it starts.
+// unreachable-code: code after a loop which never ends.
+// cancelling-commands: +- -+ <> and >< next to each other.
+// unbalanced-loop: a loop inside another loop which moves the pointer every time it runs.
+// negative-pointer: a < which moves the pointer left of where it started.
+pub fn lint_program(program: &str, extensions: Extensions) -> Result<Vec<Lint>, Box<Error>> {
+  parse_program_checked_extended(program, extensions)?;
+
+  let tokens = parse_program_with_offsets_extended(program, extensions);
+  let tree = build_tree(&tokens, &mut 0);
+
+  let mut linter = Linter { lints: Vec::new() };
+  linter.lint_block(&tree, State::start(), 0);
+
+  let mut lints = linter.lints;
+  lints.sort_by_key(|lint| lint.start);
+  Ok(lints)
+}
+
+// The loops lint_program finds which hang once they start.
+pub fn endless_loops(program: &str, extensions: Extensions) -> Result<Vec<Lint>, Box<Error>> {
+  Ok(
+    lint_program(program, extensions)?
+      .into_iter()
+      .filter(|lint| lint.name == "endless-loop")
+      .collect(),
+  )
+}
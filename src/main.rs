@@ -2,154 +2,1093 @@
 
 extern crate bfcrs;
 
-use std::fs::{create_dir_all, read_to_string, OpenOptions};
+use std::error::Error;
+use std::fs::{canonicalize, create_dir_all, read, File};
+use std::io::{self, BufRead, Read, Write};
 use std::path::Path;
+use std::process::exit;
+use std::time::{Duration, Instant};
 
+use bfcrs::analysis::{pointer_range, TAPE_CELLS};
+use bfcrs::backend::wasm::wat::disassemble;
+use bfcrs::backend::wasm::{WasmModule, WasmOptions, WasmTarget};
 use bfcrs::backend::Backend;
-use bfcrs::interpreter::run_program;
-use bfcrs::interpreter::ConsoleIo;
-use bfcrs::optimizer::optimize_parsed;
-use bfcrs::parser::parse_program;
-use bfcrs::pseudo_c_formatter::print_as_c;
-use bfcrs::types::State;
+use bfcrs::bench::{all_engines, bench_program, DEFAULT_SUITE};
+use bfcrs::codegen::text_to_bf::{self, Goal};
+use bfcrs::dialect::Dialect;
+use bfcrs::formatter::{format_source, FormatOptions, FormatStyle};
+use bfcrs::interpreter::{run_program_detecting_endless_loops, BfIo, ConsoleIo};
+use bfcrs::lang::compile_structured;
+use bfcrs::lint::{endless_loops, lint_program, Lint};
+use bfcrs::optimizer::{convert_tokens, optimize_with_stats, PassStats};
+use bfcrs::parser::{
+  line_and_column, parse_program_checked_extended, parse_program_checked_located, Extensions,
+};
+use bfcrs::preprocessor::{expand_macros_from_files, Expansion};
+use bfcrs::pseudo_c_formatter::write_as_c;
+use bfcrs::repl::Repl;
+use bfcrs::tape::Tape;
+use bfcrs::types::{ParseToken, ProgramToken};
+use bfcrs::{
+  compile_expansion_with_debug_info, compile_program_with_debug_info, program_token_offsets,
+};
+
+const USAGE: &str = "Usage: bfcrsc <command> [options] <input>
+
+Commands:
+  build    Compile a Brainfuck program
+  run      Run a program in the interpreter
+  ir       Print the intermediate representation of a program
+  check    Report which cells a program can reach, and warn if it can leave the tape
+  lint     Warn about code which is probably a mistake
+  fmt      Pretty-print or minify a program
+  bench    Time programs on every engine and optimization level (default: the programs in bf/)
+  disasm   Print a WebAssembly module built by bfcrsc as text
+  repl     Run Brainfuck line by line on a persistent tape, optionally loading a file first
+  text     Generate a Brainfuck program which prints the input
+  help     Show this message
+
+Inputs and outputs named - are stdin and stdout.
+
+Options:
+  -o, --out <path>          Output file. build defaults to the input with the target's
+                            extension (.opt.bf for bf), everything else to stdout.
+  --target <name>           wasm (default), wasi, wat, c, llvm or bf
+  --no-optimize             Skip the optimizer
+  --debug-info              Add a bfcrs debug section to WebAssembly modules
+  --embed-source            Also embed the source in the debug section
+  --max-memory-pages <n>    Grow WebAssembly memory on demand, up to n pages
+  --dialect <name>          Read and write programs in pbrain, ebf, ook, blub or a
+                            substitution table file
+  --macros                  Expand %define, %include and {...}*n before parsing
+  --lang                    Compile the input as a structured program instead of Brainfuck
+  --tape <name>             Interpreter tape: fixed (default), growable, bidirectional or sparse
+  --c                       Print the IR as pseudo-C
+  --minify                  fmt: only print the commands, on one line
+  --indent <n>              fmt: spaces per loop level (default: 2)
+  --width <n>               fmt: line width for commands (default: 80)
+  --strip-comments          fmt: leave out comments
+  --to <dialect>            fmt: write the program in another dialect
+  --fast                    text: generate the fastest program instead of the shortest
+  -v, --verbose             Also report timings and optimizer passes
+  -q, --quiet               Only report errors
+  --message-format <format> human (default) or json, one object per line
+  -h, --help                Show this message
+
+Diagnostics are written to stderr, so they never mix with program output.
+";
+
+const STDIO: &str = "-";
+
+#[derive(Clone, Copy, PartialEq, PartialOrd)]
+enum Verbosity {
+  Quiet,
+  Normal,
+  Verbose,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum MessageFormat {
+  Human,
+  Json,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Command {
+  Build,
+  Run,
+  Ir,
+  Check,
+  Lint,
+  Fmt,
+  Bench,
+  Disasm,
+  Repl,
+  Text,
+  Help,
+}
 
 struct Config {
-  compile: bool,
-  run: bool,
-  print_ir: bool,
+  command: Command,
+  inputs: Vec<String>,
+  output_path: Option<String>,
+  target_name: String,
+  tape_name: String,
+  dialect: Dialect,
+  // fmt writes programs in this dialect instead, if it's given.
+  output_dialect: Option<Dialect>,
+  macros: bool,
+  // The input is a structured program, see lang.rs.
+  lang: bool,
+  optimize: bool,
+  debug_info: bool,
+  embed_source: bool,
+  max_memory_pages: Option<u32>,
   print_c: bool,
-  target_name: &'static str,
-  source_path: String,
-  output_path: String,
+  format_options: FormatOptions,
+  text_goal: Goal,
+  verbosity: Verbosity,
+  message_format: MessageFormat,
 }
 
-impl Default for Config {
-  fn default() -> Config {
+impl Config {
+  fn new(command: Command) -> Config {
     Config {
-      compile: true,
-      run: false,
-      print_ir: false,
+      command,
+      inputs: Vec::new(),
+      output_path: None,
+      target_name: "wasm".to_string(),
+      tape_name: "fixed".to_string(),
+      dialect: Dialect::brainfuck(),
+      output_dialect: None,
+      macros: false,
+      lang: false,
+      optimize: true,
+      debug_info: false,
+      embed_source: false,
+      max_memory_pages: None,
       print_c: false,
-      target_name: "wasm",
-      source_path: "./bf/hello.bf".to_string(),
-      output_path: "./bin/out.wasm".to_string(),
+      format_options: FormatOptions::new(FormatStyle::Pretty),
+      text_goal: Goal::Size,
+      verbosity: Verbosity::Normal,
+      message_format: MessageFormat::Human,
     }
   }
 }
 
-fn parse_args<'a>(args: Vec<String>) -> Config {
-  fn parse_next(args: &[&str], config: Config) -> Config {
+fn parse_args(args: &[String]) -> Result<Config, Box<Error>> {
+  fn parse_options(args: &[&str], config: Config) -> Result<Config, Box<Error>> {
     match args {
-      &["--build-and-run", ref rest..] => parse_next(
+      &["-h", _..] | &["--help", _..] => Ok(Config::new(Command::Help)),
+      &["-o", path, ref rest..] | &["--out", path, ref rest..] => parse_options(
+        rest,
+        Config {
+          output_path: Some(path.to_string()),
+          ..config
+        },
+      ),
+      &["--target", target_name, ref rest..] => {
+        if Backend::from_name(target_name).is_none() {
+          return Err(From::from(format!("Unknown target: {}", target_name)));
+        }
+
+        parse_options(
+          rest,
+          Config {
+            target_name: target_name.to_string(),
+            ..config
+          },
+        )
+      }
+      &["--tape", tape_name, ref rest..] => {
+        if Tape::from_name(tape_name).is_none() {
+          return Err(From::from(format!("Unknown tape: {}", tape_name)));
+        }
+
+        parse_options(
+          rest,
+          Config {
+            tape_name: tape_name.to_string(),
+            ..config
+          },
+        )
+      }
+      &["--dialect", name, ref rest..] => parse_options(
         rest,
         Config {
-          compile: true,
-          run: true,
+          dialect: Dialect::load(name)?,
           ..config
         },
       ),
-      &["--only-run", ref rest..] => parse_next(
+      &["--to", name, ref rest..] => parse_options(
         rest,
         Config {
-          compile: false,
-          run: true,
+          output_dialect: Some(Dialect::load(name)?),
           ..config
         },
       ),
-      &["--print-ir", ref rest..] => parse_next(
+      &["--max-memory-pages", pages, ref rest..] => {
+        let pages = pages
+          .parse()
+          .map_err(|_| format!("Not a page count: {}", pages))?;
+
+        parse_options(
+          rest,
+          Config {
+            max_memory_pages: Some(pages),
+            ..config
+          },
+        )
+      }
+      &["--macros", ref rest..] => parse_options(
         rest,
         Config {
-          print_ir: true,
+          macros: true,
           ..config
         },
       ),
-      &["--print-c", ref rest..] => parse_next(
+      &["--lang", ref rest..] => parse_options(
         rest,
         Config {
-          print_c: true,
+          lang: true,
+          ..config
+        },
+      ),
+      &["--no-optimize", ref rest..] => parse_options(
+        rest,
+        Config {
+          optimize: false,
+          ..config
+        },
+      ),
+      &["--debug-info", ref rest..] => parse_options(
+        rest,
+        Config {
+          debug_info: true,
           ..config
         },
       ),
-      &["--out", file_name, ref rest..] => parse_next(
+      &["--embed-source", ref rest..] => parse_options(
         rest,
         Config {
-          output_path: file_name.to_string(),
+          debug_info: true,
+          embed_source: true,
           ..config
         },
       ),
-      &["--target", "wasm", ref rest..] => parse_next(
+      &["-v", ref rest..] | &["--verbose", ref rest..] => parse_options(
         rest,
         Config {
-          target_name: "wasm",
+          verbosity: Verbosity::Verbose,
           ..config
         },
       ),
-      &["--target", "c", ref rest..] => parse_next(
+      &["-q", ref rest..] | &["--quiet", ref rest..] => parse_options(
         rest,
         Config {
-          target_name: "c",
+          verbosity: Verbosity::Quiet,
           ..config
         },
       ),
-      &["--target", other, _..] => {
-        panic!("Unknown target: {}", other);
+      &["--message-format", format, ref rest..] => {
+        let message_format = match format {
+          "human" => MessageFormat::Human,
+          "json" => MessageFormat::Json,
+          other => {
+            return Err(From::from(format!("Unknown message format: {}", other)));
+          }
+        };
+
+        parse_options(
+          rest,
+          Config {
+            message_format,
+            ..config
+          },
+        )
       }
-      &[source_path] => Config {
-        source_path: source_path.to_string(),
-        ..config
-      },
-      &[x, _..] => {
-        panic!("Unknown parameter: {}", x);
+      &["--c", ref rest..] => parse_options(
+        rest,
+        Config {
+          print_c: true,
+          ..config
+        },
+      ),
+      &["--minify", ref rest..] => {
+        let mut config = config;
+        config.format_options.style = FormatStyle::Minify;
+        parse_options(rest, config)
+      }
+      &["--indent", indent, ref rest..] => {
+        let mut config = config;
+        config.format_options.indent = indent
+          .parse()
+          .map_err(|_| format!("Not an indentation width: {}", indent))?;
+        parse_options(rest, config)
+      }
+      &["--width", width, ref rest..] => {
+        let mut config = config;
+        config.format_options.line_width = width
+          .parse()
+          .map_err(|_| format!("Not a line width: {}", width))?;
+        parse_options(rest, config)
+      }
+      &["--strip-comments", ref rest..] => {
+        let mut config = config;
+        config.format_options.keep_comments = false;
+        parse_options(rest, config)
+      }
+      &["--fast", ref rest..] => parse_options(
+        rest,
+        Config {
+          text_goal: Goal::Speed,
+          ..config
+        },
+      ),
+      &[option]
+        if [
+          "-o",
+          "--out",
+          "--target",
+          "--tape",
+          "--max-memory-pages",
+          "--message-format",
+          "--indent",
+          "--width",
+          "--dialect",
+          "--to",
+        ]
+        .contains(&option) =>
+      {
+        Err(From::from(format!("{} needs a value.", option)))
+      }
+      &[option, _..] if option.starts_with('-') && option != STDIO => {
+        Err(From::from(format!("Unknown option: {}", option)))
+      }
+      &[input, ref rest..] => {
+        let mut config = config;
+        config.inputs.push(input.to_string());
+        parse_options(rest, config)
+      }
+      &[] => Ok(config),
+    }
+  }
+
+  let args = args.iter().map(|x| &x[..]).collect::<Vec<_>>();
+
+  let (command, rest) = match &args[..] {
+    &[] | &["help", _..] | &["-h", _..] | &["--help", _..] => {
+      return Ok(Config::new(Command::Help));
+    }
+    &["build", ref rest..] => (Command::Build, rest),
+    &["run", ref rest..] => (Command::Run, rest),
+    &["ir", ref rest..] => (Command::Ir, rest),
+    &["check", ref rest..] => (Command::Check, rest),
+    &["lint", ref rest..] => (Command::Lint, rest),
+    &["fmt", ref rest..] => (Command::Fmt, rest),
+    &["bench", ref rest..] => (Command::Bench, rest),
+    &["disasm", ref rest..] => (Command::Disasm, rest),
+    &["repl", ref rest..] => (Command::Repl, rest),
+    &["text", ref rest..] => (Command::Text, rest),
+    // A bare source file is built, like before there were subcommands.
+    &[source, _..] if source.ends_with(".bf") => (Command::Build, &args[..]),
+    &[other, _..] => {
+      return Err(From::from(format!(
+        "Unknown command: {}. See bfcrsc --help.",
+        other
+      )));
+    }
+  };
+
+  let config = parse_options(rest, Config::new(command))?;
+
+  match config.command {
+    Command::Help | Command::Bench => {}
+    Command::Repl if config.inputs.len() <= 1 => {}
+    _ if config.inputs.len() != 1 => {
+      return Err(From::from(
+        "Expected exactly one input file, or - for stdin. See bfcrsc --help.",
+      ));
+    }
+    _ => {}
+  }
+
+  Ok(config)
+}
+
+fn json_string(value: &str) -> String {
+  let mut result = String::from("\"");
+
+  for ch in value.chars() {
+    match ch {
+      '"' => result.push_str("\\\""),
+      '\\' => result.push_str("\\\\"),
+      '\n' => result.push_str("\\n"),
+      '\r' => result.push_str("\\r"),
+      '\t' => result.push_str("\\t"),
+      ch if (ch as u32) < 0x20 => result.push_str(&format!("\\u{:04x}", ch as u32)),
+      ch => result.push(ch),
+    }
+  }
+
+  result.push('"');
+  result
+}
+
+fn milliseconds(duration: Duration) -> f64 {
+  duration.as_secs() as f64 * 1000.0 + duration.subsec_nanos() as f64 / 1_000_000.0
+}
+
+// Reports progress on stderr. JSON messages are always written, since consumers can filter them.
+struct Diagnostics {
+  verbosity: Verbosity,
+  format: MessageFormat,
+}
+
+impl Diagnostics {
+  fn new(config: &Config) -> Diagnostics {
+    Diagnostics {
+      verbosity: config.verbosity,
+      format: config.message_format,
+    }
+  }
+
+  fn timing(&self, phase: &str, duration: Duration) {
+    match self.format {
+      MessageFormat::Json => eprintln!(
+        "{{\"type\":\"timing\",\"phase\":{},\"ms\":{:.3}}}",
+        json_string(phase),
+        milliseconds(duration)
+      ),
+      MessageFormat::Human if self.verbosity >= Verbosity::Verbose => {
+        eprintln!("{}: {:.2} ms", phase, milliseconds(duration))
+      }
+      MessageFormat::Human => {}
+    }
+  }
+
+  fn pass(&self, stats: &PassStats) {
+    match self.format {
+      MessageFormat::Json => eprintln!(
+        "{{\"type\":\"pass\",\"round\":{},\"pass\":{},\"tokens_before\":{},\"tokens_after\":{},\"ms\":{:.3}}}",
+        stats.round,
+        json_string(stats.pass),
+        stats.tokens_before,
+        stats.tokens_after,
+        milliseconds(stats.duration)
+      ),
+      MessageFormat::Human if self.verbosity >= Verbosity::Verbose => eprintln!(
+        "round {} {}: {} -> {} tokens, {:.2} ms",
+        stats.round,
+        stats.pass,
+        stats.tokens_before,
+        stats.tokens_after,
+        milliseconds(stats.duration)
+      ),
+      MessageFormat::Human => {}
+    }
+  }
+
+  fn warning(&self, message: &str) {
+    match self.format {
+      MessageFormat::Json => eprintln!(
+        "{{\"type\":\"warning\",\"message\":{}}}",
+        json_string(message)
+      ),
+      MessageFormat::Human if self.verbosity >= Verbosity::Normal => {
+        eprintln!("warning: {}", message)
+      }
+      MessageFormat::Human => {}
+    }
+  }
+
+  // location describes where the code starts, in the source the user wrote.
+  fn lint(&self, lint: &Lint, location: &str) {
+    match self.format {
+      MessageFormat::Json => eprintln!(
+        "{{\"type\":\"lint\",\"name\":{},\"message\":{},\"start\":{},\"end\":{},\"location\":{}}}",
+        json_string(lint.name),
+        json_string(&lint.message),
+        lint.start,
+        lint.end,
+        json_string(location)
+      ),
+      MessageFormat::Human if self.verbosity >= Verbosity::Normal => {
+        eprintln!("warning: {} at {} ({})", lint.message, location, lint.name)
       }
-      &[] => config,
+      MessageFormat::Human => {}
     }
   }
 
-  let borrowed = args.iter().map(|x| &x[..]).collect::<Vec<_>>();
-  parse_next(&borrowed[1..], Config::default())
+  fn artifact(&self, path: &str, bytes: usize) {
+    match self.format {
+      MessageFormat::Json => eprintln!(
+        "{{\"type\":\"artifact\",\"path\":{},\"bytes\":{}}}",
+        json_string(path),
+        bytes
+      ),
+      // Announcing output on stdout would only be noise.
+      MessageFormat::Human if self.verbosity >= Verbosity::Normal && path != STDIO => {
+        eprintln!("Wrote {} ({} bytes)", path, bytes)
+      }
+      MessageFormat::Human => {}
+    }
+  }
 }
 
-fn main() {
-  let config = parse_args(std::env::args().collect());
+fn report_error(error: &Error, format: MessageFormat) {
+  match format {
+    MessageFormat::Json => eprintln!(
+      "{{\"type\":\"error\",\"message\":{}}}",
+      json_string(&error.to_string())
+    ),
+    MessageFormat::Human => eprintln!("error: {}", error),
+  }
+}
+
+fn read_input(path: &str) -> Result<Vec<u8>, Box<Error>> {
+  if path == STDIO {
+    let mut buffer = Vec::new();
+    io::stdin().read_to_end(&mut buffer)?;
+    return Ok(buffer);
+  }
 
-  println!("Reading {}...", config.source_path);
+  Ok(read(path).map_err(|error| format!("Couldn't read {}: {}", path, error))?)
+}
+
+fn read_source(path: &str) -> Result<String, Box<Error>> {
+  Ok(String::from_utf8(read_input(path)?).map_err(|_| format!("{} isn't valid UTF-8.", path))?)
+}
 
-  let src = read_to_string(config.source_path).expect("Source file should exist.");
+// A program as Brainfuck, and the macro source it was expanded from, if any. Structured programs
+// are kept as they are.
+struct Source {
+  text: String,
+  expansion: Option<Expansion>,
+  structured: bool,
+  // Whether the program was translated from another dialect.
+  translated: bool,
+}
 
-  println!("Parsing...");
+// Programs in other dialects are translated to Brainfuck right away.
+fn read_program(config: &Config, path: &str) -> Result<Source, Box<Error>> {
+  let source = read_source(path)?;
 
-  let parsed_program = parse_program(&src);
+  if config.lang {
+    if config.macros {
+      return Err(From::from("Macros can't be used in structured programs."));
+    }
 
-  println!("Optimizing...");
+    return Ok(Source {
+      text: source,
+      expansion: None,
+      structured: true,
+      translated: false,
+    });
+  }
 
-  let optimized_program = optimize_parsed(&parsed_program);
+  if !config.macros {
+    return Ok(Source {
+      text: config.dialect.translate(&source),
+      expansion: None,
+      structured: false,
+      translated: !config.dialect.is_brainfuck(),
+    });
+  }
 
-  if config.print_ir {
-    println!("IR: {:?}", &optimized_program);
+  // Extended Brainfuck Type I uses { and } as commands.
+  if !config.dialect.is_brainfuck() || config.dialect.extensions.extended_type_1 {
+    return Err(From::from(format!(
+      "Macros can't be used in {} programs.",
+      config.dialect.name
+    )));
   }
 
+  let expansion = expand_macros_from_files(&source, path)?;
+  Ok(Source {
+    text: expansion.program.clone(),
+    expansion: Some(expansion),
+    structured: false,
+    translated: false,
+  })
+}
+
+// Errors in expanded programs point at the macro source.
+fn parse_checked(source: &Source, extensions: Extensions) -> Result<Vec<ParseToken>, Box<Error>> {
+  match source.expansion {
+    Some(ref expansion) => {
+      parse_program_checked_located(&source.text, extensions, |offset| expansion.locate(offset))
+    }
+    None => parse_program_checked_extended(&source.text, extensions),
+  }
+}
+
+fn locate(source: &Source, offset: usize) -> String {
+  match source.expansion {
+    Some(ref expansion) => expansion.locate(offset),
+    // Translation keeps the lines of the source, but not the columns.
+    None if source.translated => format!("line {}", line_and_column(&source.text, offset).0),
+    None => {
+      let (line, column) = line_and_column(&source.text, offset);
+      format!("line {}, column {}", line, column)
+    }
+  }
+}
+
+// Structured programs compile straight to the IR.
+fn unoptimized_ir(
+  source: &Source,
+  extensions: Extensions,
+) -> Result<Vec<ProgramToken>, Box<Error>> {
+  if source.structured {
+    compile_structured(&source.text)
+  } else {
+    Ok(convert_tokens(&parse_checked(source, extensions)?))
+  }
+}
+
+fn create_output(path: &str) -> Result<Box<Write>, Box<Error>> {
+  if path == STDIO {
+    return Ok(Box::new(io::stdout()));
+  }
+
+  if let Some(parent) = Path::new(path).parent() {
+    create_dir_all(parent)?;
+  }
+
+  let file = File::create(path).map_err(|error| format!("Couldn't create {}: {}", path, error))?;
+  Ok(Box::new(file))
+}
+
+fn output_path(config: &Config) -> &str {
+  config.output_path.as_ref().map_or(STDIO, |path| &path[..])
+}
+
+// The input with the target's extension. Brainfuck would replace the input, so it gets .opt.bf.
+fn default_output_path(input: &str, extension: &str) -> String {
+  let path = Path::new(input).with_extension(&extension[1..]);
+
+  let path = if path == Path::new(input) {
+    path.with_extension(format!("opt{}", extension))
+  } else {
+    path
+  };
+
+  path.to_string_lossy().into_owned()
+}
+
+fn same_file(a: &str, b: &str) -> bool {
+  match (canonicalize(a), canonicalize(b)) {
+    (Ok(a), Ok(b)) => a == b,
+    _ => false,
+  }
+}
+
+fn compile_ir(
+  source: &Source,
+  extensions: Extensions,
+  optimize_program: bool,
+  diagnostics: &Diagnostics,
+) -> Result<Vec<ProgramToken>, Box<Error>> {
+  let start = Instant::now();
+  let program = unoptimized_ir(source, extensions)?;
+  diagnostics.timing("parse", start.elapsed());
+
+  // They would hang on every target.
+  if !source.structured {
+    for lint in endless_loops(&source.text, extensions)? {
+      diagnostics.lint(&lint, &locate(source, lint.start));
+    }
+  }
+
+  if !optimize_program {
+    return Ok(program);
+  }
+
+  let start = Instant::now();
+  let (optimized, stats) = optimize_with_stats(&program);
+
+  for pass in &stats {
+    diagnostics.pass(pass);
+  }
+  diagnostics.timing("optimize", start.elapsed());
+
+  Ok(optimized)
+}
+
+fn build(config: &Config, diagnostics: &Diagnostics) -> Result<(), Box<Error>> {
+  let input = &config.inputs[0];
+  let source = read_program(config, input)?;
+  let program = compile_ir(
+    &source,
+    config.dialect.extensions,
+    config.optimize,
+    diagnostics,
+  )?;
+  let backend = Backend::from_name(&config.target_name).unwrap();
+
+  let output_path = match config.output_path {
+    Some(ref path) => path.clone(),
+    None if input == STDIO => STDIO.to_string(),
+    None => default_output_path(input, backend.extension()),
+  };
+
+  if same_file(input, &output_path) {
+    return Err(From::from(format!(
+      "Output {} is the input, which would be overwritten.",
+      output_path
+    )));
+  }
+
+  let start = Instant::now();
+
+  let code = if config.debug_info || config.max_memory_pages.is_some() {
+    let target = match &config.target_name[..] {
+      "wasm" => WasmTarget::Bfcrs,
+      "wasi" => WasmTarget::Wasi,
+      other => {
+        return Err(From::from(format!(
+          "Target {} only supports the default WebAssembly options.",
+          other
+        )));
+      }
+    };
+
+    let options = WasmOptions {
+      target,
+      max_pages: config.max_memory_pages,
+    };
+
+    if config.debug_info && config.lang {
+      return Err(From::from(
+        "Debug info isn't supported for structured programs.",
+      ));
+    }
+
+    if config.debug_info && !config.dialect.extensions.names().is_empty() {
+      return Err(From::from(format!(
+        "Debug info isn't supported for {} programs.",
+        config.dialect.name
+      )));
+    }
+
+    if config.debug_info {
+      // Only unoptimized programs still have a token for every command of the source.
+      let token_offsets = if config.optimize {
+        None
+      } else {
+        Some(program_token_offsets(&source.text))
+      };
+
+      match source.expansion {
+        Some(ref expansion) => compile_expansion_with_debug_info(
+          &program,
+          token_offsets,
+          expansion,
+          options,
+          config.embed_source,
+        ),
+        None => compile_program_with_debug_info(
+          &program,
+          token_offsets,
+          &source.text,
+          options,
+          config.embed_source,
+        ),
+      }
+    } else {
+      // Not every combination of options can be compiled, so errors are reported.
+      let mut code = Vec::new();
+      WasmModule::write_with_options(&mut code, &program, &options)?;
+      code
+    }
+  } else {
+    let mut code = Vec::new();
+    backend.compile(&program, &mut code)?;
+
+    if config.target_name == "bf" {
+      let brainfuck = String::from_utf8(code).unwrap();
+      code = config
+        .dialect
+        .emit(&brainfuck, config.format_options.line_width)
+        .into_bytes();
+    }
+
+    code
+  };
+
+  diagnostics.timing("codegen", start.elapsed());
+
+  let mut output = create_output(&output_path)?;
+  output.write_all(&code)?;
+  output.flush()?;
+
+  diagnostics.artifact(&output_path, code.len());
+  Ok(())
+}
+
+fn run(config: &Config, diagnostics: &Diagnostics) -> Result<(), Box<Error>> {
+  let source = read_program(config, &config.inputs[0])?;
+  let program = compile_ir(
+    &source,
+    config.dialect.extensions,
+    config.optimize,
+    diagnostics,
+  )?;
+
+  let start = Instant::now();
+  let mut tape = Tape::from_name(&config.tape_name).unwrap();
+
+  let result = run_program_detecting_endless_loops(&program, &mut *tape, &mut ConsoleIo);
+
+  // Whatever the program printed before it got stuck is still shown.
+  io::stdout().flush()?;
+  result?;
+
+  diagnostics.timing("run", start.elapsed());
+  Ok(())
+}
+
+fn print_ir(config: &Config, diagnostics: &Diagnostics) -> Result<(), Box<Error>> {
+  let source = read_program(config, &config.inputs[0])?;
+  let program = compile_ir(
+    &source,
+    config.dialect.extensions,
+    config.optimize,
+    diagnostics,
+  )?;
+  let mut output = create_output(output_path(config))?;
+
   if config.print_c {
-    print_as_c(&optimized_program, String::new());
+    write_as_c(&program, String::new(), &mut *output)?;
+  } else {
+    writeln!(output, "{:#?}", program)?;
+  }
+
+  output.flush()?;
+  Ok(())
+}
+
+fn check(config: &Config, diagnostics: &Diagnostics) -> Result<(), Box<Error>> {
+  let source = read_program(config, &config.inputs[0])?;
+  let program = compile_ir(
+    &source,
+    config.dialect.extensions,
+    config.optimize,
+    diagnostics,
+  )?;
+  let range = pointer_range(&program);
+  let mut output = create_output(output_path(config))?;
+
+  match (range.min, range.max) {
+    (Some(min), Some(max)) => writeln!(
+      output,
+      "The program stays within cells {} to {} ({} cells).",
+      min,
+      max,
+      range.cells().unwrap()
+    )?,
+    (Some(min), None) => writeln!(
+      output,
+      "The program uses cells from {} to the right, without a bound.",
+      min
+    )?,
+    (None, Some(max)) => writeln!(
+      output,
+      "The program uses cells up to {}, without a bound to the left.",
+      max
+    )?,
+    (None, None) => writeln!(output, "The program can move anywhere on the tape.")?,
+  }
+
+  output.flush()?;
+
+  match range.min {
+    Some(min) if min < 0 => diagnostics.warning(&format!(
+      "The pointer can go left of cell 0, down to cell {}.",
+      min
+    )),
+    None => diagnostics.warning("The pointer can go left of cell 0."),
+    _ => {}
+  }
+
+  match range.max {
+    Some(max) if max as usize >= TAPE_CELLS => diagnostics.warning(&format!(
+      "The pointer can go up to cell {}, past the end of the {}-cell tape.",
+      max, TAPE_CELLS
+    )),
+    _ => {}
+  }
+
+  Ok(())
+}
+
+fn lint(config: &Config, diagnostics: &Diagnostics) -> Result<(), Box<Error>> {
+  if config.lang {
+    return Err(From::from("lint only checks Brainfuck."));
   }
 
-  if config.compile {
-    create_dir_all(Path::new(&config.output_path).parent().unwrap()).unwrap();
+  let source = read_program(config, &config.inputs[0])?;
+  let extensions = config.dialect.extensions;
+  parse_checked(&source, extensions)?;
 
-    let backend = Backend::from_name(config.target_name).unwrap();
+  for lint in lint_program(&source.text, extensions)? {
+    diagnostics.lint(&lint, &locate(&source, lint.start));
+  }
+
+  Ok(())
+}
 
-    let output_path = Path::new("./bin").join(format!("out{}", backend.extension()));
+fn format(config: &Config) -> Result<(), Box<Error>> {
+  if config.lang {
+    return Err(From::from(
+      "fmt only formats Brainfuck. Build structured programs with --target bf instead.",
+    ));
+  }
 
-    let mut output_file = OpenOptions::new()
-      .write(true)
-      .create(true)
-      .truncate(true)
-      .open(output_path)
-      .unwrap();
+  let source = read_program(config, &config.inputs[0])?;
+  let dialect = config.output_dialect.as_ref().unwrap_or(&config.dialect);
 
-    backend.compile_to_stream(&optimized_program, &mut output_file);
+  for extension in config.dialect.extensions.names() {
+    if !dialect.extensions.names().contains(&extension) {
+      return Err(From::from(format!(
+        "{} doesn't have {}.",
+        dialect.name, extension
+      )));
+    }
   }
 
-  if config.run {
-    run_program(&optimized_program, &mut State::new(), &mut ConsoleIo);
+  let mut options = config.format_options;
+  options.extensions = config.dialect.extensions;
+  let formatted = format_source(&source.text, options)?;
+  let formatted = dialect.emit(&formatted, config.format_options.line_width);
+
+  let mut output = create_output(output_path(config))?;
+  output.write_all(formatted.as_bytes())?;
+  output.flush()?;
+  Ok(())
+}
+
+fn generate_text(config: &Config) -> Result<(), Box<Error>> {
+  let text = read_input(&config.inputs[0])?;
+  let mut code = text_to_bf::generate(&text, config.text_goal);
+
+  if !code.is_empty() {
+    code.push('\n');
+  }
+
+  let code = config.dialect.emit(&code, config.format_options.line_width);
+  let mut output = create_output(output_path(config))?;
+  output.write_all(code.as_bytes())?;
+  output.flush()?;
+  Ok(())
+}
+
+fn bench(config: &Config) -> Result<(), Box<Error>> {
+  let inputs = if config.inputs.is_empty() {
+    DEFAULT_SUITE.iter().map(|x| x.to_string()).collect()
+  } else {
+    config.inputs.clone()
+  };
+
+  let mut output = create_output(output_path(config))?;
+  writeln!(
+    output,
+    "{:<24} {:<20} {:>8} {:>14} {:>10}",
+    "program", "engine", "ir size", "ops", "ms"
+  )?;
+
+  for input in &inputs {
+    let source = read_program(config, input)?;
+    // Programs can have their input next to them, like in the tests.
+    let stdin_path = format!("{}.in", input);
+    let stdin = if Path::new(&stdin_path).exists() {
+      read_input(&stdin_path)?
+    } else {
+      Vec::new()
+    };
+
+    let program = unoptimized_ir(&source, config.dialect.extensions)?;
+
+    for measurement in bench_program(&program, &stdin, &all_engines())? {
+      let ops = match measurement.ops {
+        Some(ops) => ops.to_string(),
+        None => "-".to_string(),
+      };
+
+      writeln!(
+        output,
+        "{:<24} {:<20} {:>8} {:>14} {:>10.2}",
+        input,
+        measurement.engine.name(),
+        measurement.ir_size,
+        ops,
+        milliseconds(measurement.duration)
+      )?;
+    }
+  }
+
+  output.flush()?;
+  Ok(())
+}
+
+fn disassemble_module(config: &Config) -> Result<(), Box<Error>> {
+  let module = read_input(&config.inputs[0])?;
+  let mut output = create_output(output_path(config))?;
+  disassemble(&module, &mut *output)?;
+  output.flush()?;
+  Ok(())
+}
+
+fn repl(config: &Config) -> Result<(), Box<Error>> {
+  let mut repl = Repl::new();
+  let stdin = io::stdin();
+  let stdout = io::stdout();
+
+  if let Some(input) = config.inputs.first() {
+    repl.handle_line(
+      &format!(":load {}", input),
+      &mut ConsoleIo,
+      &mut stdout.lock(),
+    )?;
+  } else if config.verbosity > Verbosity::Quiet {
+    println!("Type :help for a list of commands.");
+  }
+
+  loop {
+    print!("{}", repl.prompt());
+    stdout.lock().flush()?;
+
+    let mut line = String::new();
+    if stdin.lock().read_line(&mut line)? == 0 {
+      println!();
+      return Ok(());
+    }
+
+    if !repl.handle_line(line.trim_end(), &mut ConsoleIo, &mut stdout.lock())? {
+      return Ok(());
+    }
+  }
+}
+
+fn execute(config: &Config) -> Result<(), Box<Error>> {
+  let diagnostics = Diagnostics::new(config);
+
+  match config.command {
+    Command::Build => build(config, &diagnostics),
+    Command::Run => run(config, &diagnostics),
+    Command::Ir => print_ir(config, &diagnostics),
+    Command::Check => check(config, &diagnostics),
+    Command::Lint => lint(config, &diagnostics),
+    Command::Fmt => format(config),
+    Command::Bench => bench(config),
+    Command::Disasm => disassemble_module(config),
+    Command::Repl => repl(config),
+    Command::Text => generate_text(config),
+    Command::Help => {
+      print!("{}", USAGE);
+      Ok(())
+    }
+  }
+}
+
+fn main() {
+  let args = std::env::args().skip(1).collect::<Vec<_>>();
+
+  if let Err(error) = parse_args(&args).and_then(|config| execute(&config)) {
+    // Errors can happen before the options have been parsed.
+    let json = args
+      .windows(2)
+      .any(|pair| pair[0] == "--message-format" && pair[1] == "json");
+
+    report_error(
+      &*error,
+      if json {
+        MessageFormat::Json
+      } else {
+        MessageFormat::Human
+      },
+    );
+    exit(1);
   }
 }
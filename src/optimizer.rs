@@ -1,5 +1,6 @@
 use self::MemoryOp::*;
 use self::ProgramToken::*;
+use std::time::{Duration, Instant};
 use types::{MemoryOp, ParseToken, ProgramToken};
 
 fn merge_instructions(all_tokens: &[ProgramToken]) -> Vec<ProgramToken> {
@@ -14,7 +15,7 @@ fn merge_instructions(all_tokens: &[ProgramToken]) -> Vec<ProgramToken> {
       (Some(Offset(offs_a, ChangeValue(a))), [Offset(offs_b, ChangeValue(b)), tail..])
         if offs_a == offs_b =>
       {
-        (Some(ProgramToken::offs_change_value(*offs_a, a + b)), tail)
+        (Some(ProgramToken::offs_change_value(*offs_a, a.wrapping_add(*b))), tail)
       }
       (
         Some(ChangeAddr(addr_offset_a)),
@@ -28,7 +29,7 @@ fn merge_instructions(all_tokens: &[ProgramToken]) -> Vec<ProgramToken> {
         )
       }
       (Some(Loop(body)), rest) => match body.as_slice() {
-        &[Offset(0, ChangeValue(x))] if x.abs() > 0 => (Some(ProgramToken::set_value(0)), rest),
+        &[Offset(0, ChangeValue(x))] if x != 0 => (Some(ProgramToken::set_value(0)), rest),
         _ => {
           results.push(Loop(merge_instructions(body)));
 
@@ -39,6 +40,27 @@ fn merge_instructions(all_tokens: &[ProgramToken]) -> Vec<ProgramToken> {
           }
         }
       },
+      (Some(OffsetLoop(offset, body)), rest) => match body.as_slice() {
+        &[Offset(x_offset, ChangeValue(x))] if x_offset == *offset && x != 0 => {
+          (Some(ProgramToken::offs_set_value(*offset, 0)), rest)
+        }
+        _ => {
+          results.push(OffsetLoop(*offset, merge_instructions(body)));
+
+          match rest {
+            [] => (None, &[]),
+            [head, tail..] => (Some(head.clone()), tail),
+          }
+        }
+      },
+      (Some(Procedure(body)), rest) => {
+        results.push(Procedure(merge_instructions(body)));
+
+        match rest {
+          [] => (None, &[]),
+          [head, tail..] => (Some(head.clone()), tail),
+        }
+      }
       (Some(Offset(offs_a, SetValue(0))), [Offset(offs_b, ChangeValue(a)), tail..])
         if offs_a == offs_b =>
       {
@@ -69,10 +91,59 @@ fn merge_instructions(all_tokens: &[ProgramToken]) -> Vec<ProgramToken> {
   results
 }
 
+// Whether the pointer ends up where it started, however often the loops run.
+fn is_balanced(tokens: &[ProgramToken]) -> bool {
+  let mut movement = 0;
+
+  for token in tokens {
+    match token {
+      ChangeAddr(by) => movement += by,
+      Offset(..) => {}
+      Loop(body) | OffsetLoop(_, body) => {
+        if !is_balanced(body) {
+          return false;
+        }
+      }
+      // Procedures are numbered by the cell under the pointer, and can move it anywhere.
+      Procedure(_) | Call => return false,
+    }
+  }
+
+  movement == 0
+}
+
+// A balanced loop which checks the cell at condition, and whose body is moved by offset.
+fn offset_loop(condition: isize, offset: isize, inner: &[ProgramToken]) -> ProgramToken {
+  let mut body = Vec::with_capacity(inner.len());
+  let end = postpone_moves_from(inner, offset, &mut body);
+  assert_eq!(offset, end);
+
+  if condition == 0 {
+    Loop(body)
+  } else {
+    OffsetLoop(condition, body)
+  }
+}
+
 fn postpone_moves(all_tokens: &[ProgramToken]) -> Vec<ProgramToken> {
   let mut results: Vec<ProgramToken> = Vec::with_capacity(all_tokens.len());
+  let offset = postpone_moves_from(all_tokens, 0, &mut results);
+
+  if offset != 0 {
+    results.push(ChangeAddr(offset));
+  }
+
+  results
+}
+
+// Adds the tokens to the results with the moves left out, starting as if the pointer had already
+// been moved by offset, and returns the moves which are still to be made.
+fn postpone_moves_from(
+  all_tokens: &[ProgramToken],
+  mut offset: isize,
+  results: &mut Vec<ProgramToken>,
+) -> isize {
   let mut i = 0;
-  let mut offset = 0;
 
   while i < all_tokens.len() {
     match all_tokens[i] {
@@ -80,6 +151,11 @@ fn postpone_moves(all_tokens: &[ProgramToken]) -> Vec<ProgramToken> {
         offset += addr;
       }
       Offset(addr_offset, ref op) => results.push(Offset(offset + addr_offset, op.clone())),
+      // Loops which end where they started carry the offset through, instead of moving there.
+      Loop(ref inner) if is_balanced(inner) => results.push(offset_loop(offset, offset, inner)),
+      OffsetLoop(loop_offset, ref inner) if is_balanced(inner) => {
+        results.push(offset_loop(offset + loop_offset, offset, inner))
+      }
       Loop(ref inner) => {
         if offset != 0 {
           results.push(ChangeAddr(offset));
@@ -88,15 +164,36 @@ fn postpone_moves(all_tokens: &[ProgramToken]) -> Vec<ProgramToken> {
 
         results.push(Loop(postpone_moves(&inner)));
       }
+      OffsetLoop(loop_offset, ref inner) => {
+        if offset != 0 {
+          results.push(ChangeAddr(offset));
+          offset = 0;
+        }
+
+        results.push(OffsetLoop(loop_offset, postpone_moves(&inner)));
+      }
+      // Procedures are numbered by the cell under the pointer, and run from wherever it is.
+      Procedure(ref inner) => {
+        if offset != 0 {
+          results.push(ChangeAddr(offset));
+          offset = 0;
+        }
+
+        results.push(Procedure(postpone_moves(&inner)));
+      }
+      Call => {
+        if offset != 0 {
+          results.push(ChangeAddr(offset));
+          offset = 0;
+        }
+
+        results.push(Call);
+      }
     }
     i += 1;
   }
 
-  if offset != 0 {
-    results.push(ChangeAddr(offset));
-  }
-
-  results
+  offset
 }
 
 pub fn convert_tokens(all_tokens: &[ParseToken]) -> Vec<ProgramToken> {
@@ -114,6 +211,7 @@ pub fn convert_tokens(all_tokens: &[ParseToken]) -> Vec<ProgramToken> {
         ParseToken::IncrValue => ProgramToken::change_value(1),
         ParseToken::DecrValue => ProgramToken::change_value(-1),
         ParseToken::Print => Offset(0, Print),
+        ParseToken::Read => Offset(0, Read),
         ParseToken::LoopStart => {
           let mut inner_body = Vec::new();
           *offset += 1;
@@ -121,7 +219,28 @@ pub fn convert_tokens(all_tokens: &[ParseToken]) -> Vec<ProgramToken> {
           convert_tokens_rec(offset, tokens, &mut inner_body);
           Loop(inner_body)
         }
-        ParseToken::LoopEnd => {
+        ParseToken::ProcedureStart => {
+          let mut inner_body = Vec::new();
+          *offset += 1;
+
+          convert_tokens_rec(offset, tokens, &mut inner_body);
+          Procedure(inner_body)
+        }
+        ParseToken::Call => Call,
+        ParseToken::Store => Offset(0, Store),
+        ParseToken::Retrieve => Offset(0, Retrieve),
+        ParseToken::ShiftLeft => Offset(0, ShiftLeft),
+        ParseToken::ShiftRight => Offset(0, ShiftRight),
+        ParseToken::Not => Offset(0, Not),
+        ParseToken::Xor => Offset(0, Xor),
+        ParseToken::And => Offset(0, And),
+        ParseToken::Or => Offset(0, Or),
+        // Nothing after @ is parsed, so it's always the last token.
+        ParseToken::End => {
+          *offset += 1;
+          break;
+        }
+        ParseToken::LoopEnd | ParseToken::ProcedureEnd => {
           break;
         }
       };
@@ -142,25 +261,125 @@ pub fn convert_tokens(all_tokens: &[ParseToken]) -> Vec<ProgramToken> {
   program
 }
 
-pub fn optimize(program: &[ProgramToken]) -> Vec<ProgramToken> {
+pub struct PassStats {
+  pub round: usize,
+  pub pass: &'static str,
+  pub tokens_before: usize,
+  pub tokens_after: usize,
+  pub duration: Duration,
+}
+
+// Counts tokens inside loops and procedures too.
+pub fn count_tokens(program: &[ProgramToken]) -> usize {
+  program
+    .iter()
+    .map(|token| match token {
+      Loop(body) | OffsetLoop(_, body) | Procedure(body) => 1 + count_tokens(body),
+      _ => 1,
+    })
+    .sum()
+}
+
+pub fn uses_storage(program: &[ProgramToken]) -> bool {
+  program.iter().any(|token| match token {
+    Loop(body) | OffsetLoop(_, body) | Procedure(body) => uses_storage(body),
+    Offset(_, op) => op.uses_storage(),
+    _ => false,
+  })
+}
+
+pub fn uses_procedures(program: &[ProgramToken]) -> bool {
+  program.iter().any(|token| match token {
+    Loop(body) | OffsetLoop(_, body) => uses_procedures(body),
+    Procedure(_) | Call => true,
+    _ => false,
+  })
+}
+
+fn run_pass(
+  stats: &mut Vec<PassStats>,
+  round: usize,
+  pass: &'static str,
+  tokens: &[ProgramToken],
+  pass_fn: fn(&[ProgramToken]) -> Vec<ProgramToken>,
+) -> Vec<ProgramToken> {
+  let start = Instant::now();
+  let result = pass_fn(tokens);
+
+  stats.push(PassStats {
+    round,
+    pass,
+    tokens_before: count_tokens(tokens),
+    tokens_after: count_tokens(&result),
+    duration: start.elapsed(),
+  });
+
+  result
+}
+
+pub fn optimize_with_stats(program: &[ProgramToken]) -> (Vec<ProgramToken>, Vec<PassStats>) {
   let mut tokens = program.to_vec();
-  let mut iterations = 0;
+  let mut stats = Vec::new();
+  let mut round = 1;
 
   loop {
-    println!("Optimization round: {}", iterations + 1);
-    let optimized = postpone_moves(&merge_instructions(&tokens));
+    let merged = run_pass(
+      &mut stats,
+      round,
+      "merge_instructions",
+      &tokens,
+      merge_instructions,
+    );
+    let optimized = run_pass(&mut stats, round, "postpone_moves", &merged, postpone_moves);
+
     if optimized == tokens {
-      return optimized;
+      return (optimized, stats);
     }
     tokens = optimized;
-    iterations += 1;
+    round += 1;
   }
 }
 
+pub fn optimize(program: &[ProgramToken]) -> Vec<ProgramToken> {
+  optimize_with_stats(program).0
+}
+
 pub fn optimize_parsed(tokens: &[ParseToken]) -> Vec<ProgramToken> {
   optimize(&convert_tokens(tokens))
 }
 
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum OptimizationLevel {
+  None,
+  // A single merge_instructions pass.
+  Merge,
+  Full,
+}
+
+pub const OPTIMIZATION_LEVELS: &[OptimizationLevel] = &[
+  OptimizationLevel::None,
+  OptimizationLevel::Merge,
+  OptimizationLevel::Full,
+];
+
+impl OptimizationLevel {
+  pub fn name(&self) -> &'static str {
+    match self {
+      OptimizationLevel::None => "none",
+      OptimizationLevel::Merge => "merge",
+      OptimizationLevel::Full => "full",
+    }
+  }
+}
+
+pub fn optimize_to_level(program: &[ProgramToken], level: OptimizationLevel) -> Vec<ProgramToken> {
+  match level {
+    OptimizationLevel::None => program.to_vec(),
+    OptimizationLevel::Merge => merge_instructions(program),
+    OptimizationLevel::Full => optimize(program),
+  }
+}
+
 #[test]
 fn single_ops_are_maintained() {
   let before = vec![
@@ -189,3 +408,79 @@ fn same_ops_are_merged() {
 
   assert_eq!(&expected, &after);
 }
+
+#[test]
+fn balanced_loops_keep_the_offset() {
+  let before = vec![
+    ChangeAddr(2),
+    Loop(vec![
+      ProgramToken::change_value(-1),
+      ChangeAddr(1),
+      Loop(vec![
+        ProgramToken::change_value(-1),
+        ChangeAddr(-3),
+        ProgramToken::change_value(1),
+        ChangeAddr(3),
+      ]),
+      ChangeAddr(-1),
+    ]),
+    ChangeAddr(1),
+    Offset(0, Print),
+  ];
+  let expected = vec![
+    OffsetLoop(
+      2,
+      vec![
+        ProgramToken::offs_change_value(2, -1),
+        OffsetLoop(
+          3,
+          vec![
+            ProgramToken::offs_change_value(3, -1),
+            ProgramToken::offs_change_value(0, 1),
+          ],
+        ),
+      ],
+    ),
+    Offset(3, Print),
+    ChangeAddr(3),
+  ];
+
+  assert_eq!(expected, optimize(&before));
+}
+
+#[test]
+fn unbalanced_loops_move_to_their_cell() {
+  let before = vec![ChangeAddr(2), Loop(vec![ChangeAddr(1)]), Offset(0, Print)];
+  assert_eq!(before, optimize(&before));
+
+  // A loop around one which moves can end anywhere too.
+  let before = vec![
+    ChangeAddr(2),
+    Loop(vec![Loop(vec![ChangeAddr(1)]), ChangeAddr(-1)]),
+  ];
+  assert_eq!(before, optimize(&before));
+}
+
+#[test]
+fn stats_cover_every_round() {
+  let before = vec![
+    ProgramToken::change_value(1),
+    ProgramToken::change_value(1),
+    Loop(vec![ChangeAddr(1), ChangeAddr(1)]),
+  ];
+  let (after, stats) = optimize_with_stats(&before);
+
+  assert_eq!(count_tokens(&after), stats.last().unwrap().tokens_after);
+  assert_eq!("merge_instructions", stats[0].pass);
+  assert_eq!(5, stats[0].tokens_before);
+  assert_eq!(3, stats[0].tokens_after);
+
+  // The optimizer stops after a round which changes nothing.
+  let last_round = stats.last().unwrap().round;
+  assert!(
+    stats
+      .iter()
+      .filter(|x| x.round == last_round)
+      .all(|x| x.tokens_before == x.tokens_after)
+  );
+}
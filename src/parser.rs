@@ -1,17 +1,215 @@
-use types::ParseToken;
+use std::error::Error;
+
+use types::{ParseToken, SourceItem};
+
+// Commands beyond Brainfuck's eight, which only some dialects have.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Extensions {
+  // pbrain's procedures: ( and ) define one, : calls one.
+  pub procedures: bool,
+  // Extended Brainfuck Type I: @ ends the program, $ and ! copy to and from a storage byte, and
+  // { } ~ ^ & | are bitwise ops.
+  pub extended_type_1: bool,
+}
+
+impl Extensions {
+  pub fn names(&self) -> Vec<&'static str> {
+    let mut names = Vec::new();
+
+    if self.procedures {
+      names.push("procedures");
+    }
+
+    if self.extended_type_1 {
+      names.push("Extended Brainfuck Type I");
+    }
+
+    names
+  }
+}
 
 pub fn parse_program(program: &str) -> Vec<ParseToken> {
-  program
-    .chars()
-    .filter_map(|x| match x {
-      '>' => Some(ParseToken::IncrAddr),
-      '<' => Some(ParseToken::DecrAddr),
-      '+' => Some(ParseToken::IncrValue),
-      '-' => Some(ParseToken::DecrValue),
-      '[' => Some(ParseToken::LoopStart),
-      ']' => Some(ParseToken::LoopEnd),
-      '.' => Some(ParseToken::Print),
-      _ => None,
-    })
+  parse_program_extended(program, Extensions::default())
+}
+
+pub fn parse_program_extended(program: &str, extensions: Extensions) -> Vec<ParseToken> {
+  parse_with_offsets(program, extensions)
+    .into_iter()
+    .map(|(_, token)| token)
+    .collect()
+}
+
+fn parse_token(x: char, extensions: Extensions) -> Option<ParseToken> {
+  match x {
+    '>' => Some(ParseToken::IncrAddr),
+    '<' => Some(ParseToken::DecrAddr),
+    '+' => Some(ParseToken::IncrValue),
+    '-' => Some(ParseToken::DecrValue),
+    '[' => Some(ParseToken::LoopStart),
+    ']' => Some(ParseToken::LoopEnd),
+    '.' => Some(ParseToken::Print),
+    ',' => Some(ParseToken::Read),
+    '(' if extensions.procedures => Some(ParseToken::ProcedureStart),
+    ')' if extensions.procedures => Some(ParseToken::ProcedureEnd),
+    ':' if extensions.procedures => Some(ParseToken::Call),
+    '@' if extensions.extended_type_1 => Some(ParseToken::End),
+    '$' if extensions.extended_type_1 => Some(ParseToken::Store),
+    '!' if extensions.extended_type_1 => Some(ParseToken::Retrieve),
+    '{' if extensions.extended_type_1 => Some(ParseToken::ShiftLeft),
+    '}' if extensions.extended_type_1 => Some(ParseToken::ShiftRight),
+    '~' if extensions.extended_type_1 => Some(ParseToken::Not),
+    '^' if extensions.extended_type_1 => Some(ParseToken::Xor),
+    '&' if extensions.extended_type_1 => Some(ParseToken::And),
+    '|' if extensions.extended_type_1 => Some(ParseToken::Or),
+    _ => None,
+  }
+}
+
+// Also returns the byte offset of every token in the source.
+pub fn parse_program_with_offsets(program: &str) -> Vec<(usize, ParseToken)> {
+  parse_with_offsets(program, Extensions::default())
+}
+
+pub fn parse_program_with_offsets_extended(
+  program: &str,
+  extensions: Extensions,
+) -> Vec<(usize, ParseToken)> {
+  parse_with_offsets(program, extensions)
+}
+
+// Extended Brainfuck Type I programs end at the first @, and anything after it is ignored.
+fn code_length(program: &str, extensions: Extensions) -> usize {
+  if extensions.extended_type_1 {
+    program.find('@').map_or(program.len(), |end| end + 1)
+  } else {
+    program.len()
+  }
+}
+
+fn parse_with_offsets(program: &str, extensions: Extensions) -> Vec<(usize, ParseToken)> {
+  program[..code_length(program, extensions)]
+    .char_indices()
+    .filter_map(|(offset, x)| parse_token(x, extensions).map(|token| (offset, token)))
     .collect()
 }
+
+// Keeps the comments between tokens too, with the byte offset of every item.
+pub fn parse_source(program: &str) -> Vec<(usize, SourceItem)> {
+  parse_source_extended(program, Extensions::default())
+}
+
+pub fn parse_source_extended(program: &str, extensions: Extensions) -> Vec<(usize, SourceItem)> {
+  let mut items = Vec::new();
+  let end = code_length(program, extensions);
+  let mut comment: Option<(usize, String)> = None;
+
+  for (offset, x) in program[..end].char_indices() {
+    match parse_token(x, extensions) {
+      Some(token) => {
+        if let Some((start, text)) = comment.take() {
+          items.push((start, SourceItem::Comment(text)));
+        }
+        items.push((offset, SourceItem::Token(token)));
+      }
+      None => {
+        if comment.is_none() {
+          comment = Some((offset, String::new()));
+        }
+        if let Some((_, ref mut text)) = comment {
+          text.push(x);
+        }
+      }
+    }
+  }
+
+  if let Some((start, text)) = comment {
+    items.push((start, SourceItem::Comment(text)));
+  }
+
+  if end < program.len() {
+    items.push((end, SourceItem::Comment(program[end..].to_string())));
+  }
+
+  items
+}
+
+pub fn line_and_column(program: &str, offset: usize) -> (usize, usize) {
+  let before = &program[..offset];
+  let line = before.matches('\n').count() + 1;
+  let column = before.chars().rev().take_while(|&x| x != '\n').count() + 1;
+  (line, column)
+}
+
+// Like parse_program, but rejects programs with unbalanced brackets.
+pub fn parse_program_checked(program: &str) -> Result<Vec<ParseToken>, Box<Error>> {
+  parse_program_checked_extended(program, Extensions::default())
+}
+
+pub fn parse_program_checked_extended(
+  program: &str,
+  extensions: Extensions,
+) -> Result<Vec<ParseToken>, Box<Error>> {
+  parse_program_checked_located(program, extensions, |offset| {
+    let (line, column) = line_and_column(program, offset);
+    format!("line {}, column {}", line, column)
+  })
+}
+
+// Describes the positions in errors with locate, e.g. to point into the source of a program which
+// was generated from it.
+pub fn parse_program_checked_located<F: Fn(usize) -> String>(
+  program: &str,
+  extensions: Extensions,
+  locate: F,
+) -> Result<Vec<ParseToken>, Box<Error>> {
+  let tokens = parse_with_offsets(program, extensions);
+  // Loops and procedures nest inside each other, so they share a stack.
+  let mut open_brackets = Vec::new();
+
+  for &(offset, ref token) in &tokens {
+    let (close, open) = match token {
+      ParseToken::LoopStart => {
+        open_brackets.push((offset, '['));
+        continue;
+      }
+      ParseToken::ProcedureStart => {
+        open_brackets.push((offset, '('));
+        continue;
+      }
+      ParseToken::LoopEnd => (']', '['),
+      ParseToken::ProcedureEnd => (')', '('),
+      _ => continue,
+    };
+
+    match open_brackets.pop() {
+      None => {
+        return Err(From::from(format!(
+          "Unmatched {} at {}",
+          close,
+          locate(offset)
+        )));
+      }
+      Some((start, bracket)) => {
+        if bracket != open {
+          return Err(From::from(format!(
+            "{} at {} closes the {} at {}",
+            close,
+            locate(offset),
+            bracket,
+            locate(start)
+          )));
+        }
+      }
+    }
+  }
+
+  if let Some((offset, bracket)) = open_brackets.pop() {
+    return Err(From::from(format!(
+      "Unclosed {} at {}",
+      bracket,
+      locate(offset)
+    )));
+  }
+
+  Ok(tokens.into_iter().map(|(_, token)| token).collect())
+}
@@ -0,0 +1,398 @@
+use std::collections::HashMap;
+use std::error::Error;
+use std::fs::read;
+use std::path::Path;
+use std::rc::Rc;
+
+use parser::line_and_column;
+
+// Nested repetitions and macros make programs grow exponentially, so expansions are capped.
+const MAX_LENGTH: usize = 1 << 26;
+const MAX_INCLUDE_DEPTH: usize = 64;
+
+pub struct SourceFile {
+  pub path: String,
+  pub text: String,
+}
+
+// Where a byte of the expanded program came from. Bytes from macro bodies and included files
+// also remember where they were expanded in the root file.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Origin {
+  pub file: usize,
+  pub offset: usize,
+  pub root_offset: usize,
+}
+
+// A program with its macros expanded. The root file, which was expanded, comes first.
+pub struct Expansion {
+  pub program: String,
+  pub files: Vec<SourceFile>,
+  // The origin of every byte of the program.
+  pub origins: Vec<Origin>,
+}
+
+impl Expansion {
+  pub fn root_source(&self) -> &str {
+    &self.files[0].text
+  }
+
+  pub fn root_offset(&self, offset: usize) -> usize {
+    self.origins[offset].root_offset
+  }
+
+  // Describes where a byte of the program came from, e.g. "line 3, column 2" in the root file or
+  // "line 1, column 5 of lib.bfm" in an included one.
+  pub fn locate(&self, offset: usize) -> String {
+    let origin = self.origins[offset];
+    let file = &self.files[origin.file];
+    let path = if origin.file == 0 {
+      None
+    } else {
+      Some(&file.path[..])
+    };
+
+    describe(&file.text, origin.offset, path)
+  }
+}
+
+fn describe(text: &str, offset: usize, path: Option<&str>) -> String {
+  let (line, column) = line_and_column(text, offset);
+
+  match path {
+    Some(path) => format!("line {}, column {} of {}", line, column, path),
+    None => format!("line {}, column {}", line, column),
+  }
+}
+
+fn identifier_length(text: &str) -> usize {
+  text
+    .chars()
+    .take_while(|&x| x.is_ascii_alphanumeric() || x == '_')
+    .count()
+}
+
+#[derive(Clone, Copy)]
+struct Macro {
+  file: usize,
+  start: usize,
+  end: usize,
+}
+
+struct Preprocessor<L> {
+  load: L,
+  paths: Vec<String>,
+  // Shared, so that a file can be read while the program grows.
+  sources: Vec<Rc<String>>,
+  macros: HashMap<String, Macro>,
+  program: String,
+  origins: Vec<Origin>,
+  // The macros being expanded and the files being included, innermost last.
+  expanding: Vec<String>,
+  including: Vec<usize>,
+}
+
+impl<L: FnMut(&str) -> Result<String, Box<Error>>> Preprocessor<L> {
+  fn error(&self, file: usize, offset: usize, message: &str) -> Box<Error> {
+    let path = if file == 0 {
+      None
+    } else {
+      Some(&self.paths[file][..])
+    };
+
+    From::from(format!(
+      "{} at {}",
+      message,
+      describe(&self.sources[file], offset, path)
+    ))
+  }
+
+  fn check_length(
+    &self,
+    file: usize,
+    offset: usize,
+    length: Option<usize>,
+  ) -> Result<(), Box<Error>> {
+    match length {
+      Some(length) if length <= MAX_LENGTH => Ok(()),
+      _ => Err(self.error(
+        file,
+        offset,
+        &format!("The program grows past {} bytes", MAX_LENGTH),
+      )),
+    }
+  }
+
+  fn add_file(&mut self, path: String, source: String) -> usize {
+    self.paths.push(path);
+    self.sources.push(Rc::new(source));
+    self.sources.len() - 1
+  }
+
+  // Expands text[start..end] of a file. root_offset is where it's being expanded in the root file,
+  // or None if it's part of the root file itself.
+  fn expand(
+    &mut self,
+    file: usize,
+    start: usize,
+    end: usize,
+    root_offset: Option<usize>,
+  ) -> Result<(), Box<Error>> {
+    let text = self.sources[file].clone();
+    let mut position = start;
+    // Where every group that's still open starts in the program and in the file.
+    let mut groups: Vec<(usize, usize)> = Vec::new();
+
+    while position < end {
+      let x = text[position..].chars().next().unwrap();
+
+      match x {
+        // Comments can contain commands, and the line break after them is kept.
+        '#' => {
+          position = text[position..end]
+            .find('\n')
+            .map_or(end, |length| position + length);
+        }
+        '{' => {
+          groups.push((self.program.len(), position));
+          position += 1;
+        }
+        '}' => {
+          let (group_start, _) = match groups.pop() {
+            Some(group) => group,
+            None => return Err(self.error(file, position, "Unmatched }")),
+          };
+
+          let count_start = position + 2;
+          let digits = text[count_start.min(end)..end]
+            .chars()
+            .take_while(|x| x.is_ascii_digit())
+            .count();
+
+          if !text[position + 1..end].starts_with('*') || digits == 0 {
+            return Err(self.error(file, position, "Expected *<count> after }"));
+          }
+
+          let count = text[count_start..count_start + digits]
+            .parse::<usize>()
+            .ok();
+          let length = self.program.len() - group_start;
+          let total = count.and_then(|count| length.checked_mul(count));
+          self.check_length(
+            file,
+            position,
+            total.and_then(|x| x.checked_add(group_start)),
+          )?;
+
+          let group = self.program[group_start..].to_string();
+          let origins = self.origins[group_start..].to_vec();
+          self.program.truncate(group_start);
+          self.origins.truncate(group_start);
+
+          for _ in 0..count.unwrap() {
+            self.program.push_str(&group);
+            self.origins.extend_from_slice(&origins);
+          }
+
+          position = count_start + digits;
+        }
+        '%' => {
+          position = self.expand_directive(file, position, end, root_offset)?;
+        }
+        _ => {
+          self.program.push(x);
+
+          for i in 0..x.len_utf8() {
+            self.origins.push(Origin {
+              file,
+              offset: position + i,
+              root_offset: root_offset.unwrap_or(position),
+            });
+          }
+
+          position += x.len_utf8();
+        }
+      }
+    }
+
+    match groups.pop() {
+      Some((_, offset)) => Err(self.error(file, offset, "Unclosed {")),
+      None => Ok(()),
+    }
+  }
+
+  // Handles the % at the given position, and returns the position after it.
+  fn expand_directive(
+    &mut self,
+    file: usize,
+    position: usize,
+    end: usize,
+    root_offset: Option<usize>,
+  ) -> Result<usize, Box<Error>> {
+    let text = self.sources[file].clone();
+    let name_start = position + 1;
+    let name_end = name_start + identifier_length(&text[name_start..end]);
+    let name = &text[name_start..name_end];
+    let root_offset = Some(root_offset.unwrap_or(position));
+
+    match name {
+      "" => Err(self.error(file, position, "Expected a macro name after %")),
+      // %define name body, where the body is the rest of the line after any whitespace.
+      "define" => {
+        let line_end = text[name_end..end]
+          .find('\n')
+          .map_or(end, |length| name_end + length);
+        let line = &text[name_end..line_end];
+        let macro_start = line_end - line.trim_start().len();
+        let macro_end = macro_start + identifier_length(&text[macro_start..line_end]);
+        let macro_name = &text[macro_start..macro_end];
+
+        if macro_name.is_empty() {
+          return Err(self.error(file, position, "Expected a macro name after %define"));
+        }
+
+        if macro_name == "define" || macro_name == "include" {
+          return Err(self.error(
+            file,
+            macro_start,
+            &format!("%{} can't be redefined", macro_name),
+          ));
+        }
+
+        if self.macros.contains_key(macro_name) {
+          return Err(self.error(
+            file,
+            macro_start,
+            &format!("Macro {} is already defined", macro_name),
+          ));
+        }
+
+        let body = &text[macro_end..line_end];
+        self.macros.insert(
+          macro_name.to_string(),
+          Macro {
+            file,
+            start: line_end - body.trim_start().len(),
+            end: line_end,
+          },
+        );
+        Ok(line_end)
+      }
+      // %include "path", where the path is relative to the including file.
+      "include" => {
+        let line_end = text[name_end..end]
+          .find('\n')
+          .map_or(end, |length| name_end + length);
+        let line = text[name_end..line_end].trim_start();
+        let path_start = line_end - line.len() + 1;
+
+        let path_end = match line.chars().next() {
+          Some('"') => line[1..].find('"').map(|length| path_start + length),
+          _ => None,
+        };
+        let path_end = match path_end {
+          Some(path_end) => path_end,
+          None => {
+            return Err(self.error(file, position, "Expected a quoted path after %include"));
+          }
+        };
+
+        if self.including.len() >= MAX_INCLUDE_DEPTH {
+          return Err(self.error(file, position, "Includes are nested too deeply"));
+        }
+
+        let path = match Path::new(&self.paths[file]).parent() {
+          Some(directory) => directory.join(&text[path_start..path_end]),
+          None => Path::new(&text[path_start..path_end]).to_path_buf(),
+        };
+        let path = path.to_string_lossy().into_owned();
+
+        if self.including.iter().any(|&x| self.paths[x] == path) {
+          return Err(self.error(file, position, &format!("{} includes itself", path)));
+        }
+
+        let source = (self.load)(&path)?;
+        let length = source.len();
+        let included = self.add_file(path, source);
+
+        self.including.push(included);
+        self.expand(included, 0, length, root_offset)?;
+        self.including.pop();
+        Ok(path_end + 1)
+      }
+      _ => {
+        let definition = match self.macros.get(name) {
+          Some(definition) => *definition,
+          None => {
+            return Err(self.error(file, position, &format!("Undefined macro {}", name)));
+          }
+        };
+
+        if self.expanding.iter().any(|x| x == name) {
+          return Err(self.error(file, position, &format!("Macro {} expands itself", name)));
+        }
+
+        self.expanding.push(name.to_string());
+        self.expand(
+          definition.file,
+          definition.start,
+          definition.end,
+          root_offset,
+        )?;
+        self.expanding.pop();
+
+        self.check_length(file, position, Some(self.program.len()))?;
+        Ok(name_end)
+      }
+    }
+  }
+}
+
+// Expands the macros of a program, which was read from path:
+// # starts a comment which runs to the end of the line.
+// %define name body defines a macro, whose body is the rest of the line, and %name expands it.
+// %include "file" expands another file, relative to the one it's in. load reads it.
+// {code}*n repeats code n times.
+pub fn expand_macros<L>(source: &str, path: &str, load: L) -> Result<Expansion, Box<Error>>
+where
+  L: FnMut(&str) -> Result<String, Box<Error>>,
+{
+  let mut preprocessor = Preprocessor {
+    load,
+    paths: Vec::new(),
+    sources: Vec::new(),
+    macros: HashMap::new(),
+    program: String::new(),
+    origins: Vec::new(),
+    expanding: Vec::new(),
+    including: Vec::new(),
+  };
+
+  let root = preprocessor.add_file(path.to_string(), source.to_string());
+  preprocessor.including.push(root);
+  preprocessor.expand(root, 0, source.len(), None)?;
+
+  let files = preprocessor
+    .paths
+    .into_iter()
+    .zip(preprocessor.sources)
+    .map(|(path, text)| SourceFile {
+      path,
+      text: (*text).clone(),
+    })
+    .collect();
+
+  Ok(Expansion {
+    program: preprocessor.program,
+    files,
+    origins: preprocessor.origins,
+  })
+}
+
+// Like expand_macros, but included files are read from disk.
+pub fn expand_macros_from_files(source: &str, path: &str) -> Result<Expansion, Box<Error>> {
+  expand_macros(source, path, |path| {
+    let bytes = read(path).map_err(|error| format!("Couldn't read {}: {}", path, error))?;
+    Ok(String::from_utf8(bytes).map_err(|_| format!("{} isn't valid UTF-8.", path))?)
+  })
+}
@@ -1,25 +1,81 @@
+use std::io::{self, Write};
+
 use types::{MemoryOp, ProgramToken};
 
 pub fn print_as_c(program: &[ProgramToken], indent: String) {
+  write_as_c(program, indent, &mut io::stdout()).unwrap();
+}
+
+pub fn write_as_c(
+  program: &[ProgramToken],
+  indent: String,
+  stream: &mut dyn Write,
+) -> io::Result<()> {
   for token in program {
     match token {
       ProgramToken::ChangeAddr(offset) => {
-        println!("{}pointer += {}", indent, offset);
+        writeln!(stream, "{}pointer += {}", indent, offset)?;
       }
       ProgramToken::Offset(offset, MemoryOp::ChangeValue(value)) => {
-        println!("{}memory[pointer + {}] += {}", indent, offset, value);
+        writeln!(stream, "{}memory[pointer + {}] += {}", indent, offset, value)?;
       }
       ProgramToken::Offset(offset, MemoryOp::SetValue(value)) => {
-        println!("{}memory[pointer + {}] = {}", indent, offset, value);
+        writeln!(stream, "{}memory[pointer + {}] = {}", indent, offset, value)?;
       }
       ProgramToken::Offset(offset, MemoryOp::Print) => {
-        println!("{}print(memory[pointer + {}])", indent, offset);
+        writeln!(stream, "{}print(memory[pointer + {}])", indent, offset)?;
+      }
+      ProgramToken::Offset(offset, MemoryOp::Read) => {
+        writeln!(stream, "{}memory[pointer + {}] = read()", indent, offset)?;
+      }
+      ProgramToken::Offset(offset, MemoryOp::Store) => {
+        writeln!(stream, "{}storage = memory[pointer + {}]", indent, offset)?;
+      }
+      ProgramToken::Offset(offset, MemoryOp::Retrieve) => {
+        writeln!(stream, "{}memory[pointer + {}] = storage", indent, offset)?;
+      }
+      ProgramToken::Offset(offset, MemoryOp::ShiftLeft) => {
+        writeln!(stream, "{}memory[pointer + {}] <<= 1", indent, offset)?;
+      }
+      ProgramToken::Offset(offset, MemoryOp::ShiftRight) => {
+        writeln!(stream, "{}memory[pointer + {}] >>= 1", indent, offset)?;
+      }
+      ProgramToken::Offset(offset, MemoryOp::Not) => {
+        writeln!(
+          stream,
+          "{}memory[pointer + {}] = ~memory[pointer + {}]",
+          indent, offset, offset
+        )?;
+      }
+      ProgramToken::Offset(offset, MemoryOp::Xor) => {
+        writeln!(stream, "{}memory[pointer + {}] ^= storage", indent, offset)?;
+      }
+      ProgramToken::Offset(offset, MemoryOp::And) => {
+        writeln!(stream, "{}memory[pointer + {}] &= storage", indent, offset)?;
+      }
+      ProgramToken::Offset(offset, MemoryOp::Or) => {
+        writeln!(stream, "{}memory[pointer + {}] |= storage", indent, offset)?;
       }
       ProgramToken::Loop(inner) => {
-        println!("{}while (memory[pointer]) {{", indent);
-        print_as_c(inner, indent.clone() + "  ");
-        println!("{}}}", indent);
+        writeln!(stream, "{}while (memory[pointer]) {{", indent)?;
+        write_as_c(inner, indent.clone() + "  ", stream)?;
+        writeln!(stream, "{}}}", indent)?;
+      }
+      ProgramToken::OffsetLoop(offset, inner) => {
+        writeln!(stream, "{}while (memory[pointer + {}]) {{", indent, offset)?;
+        write_as_c(inner, indent.clone() + "  ", stream)?;
+        writeln!(stream, "{}}}", indent)?;
+      }
+      ProgramToken::Procedure(inner) => {
+        writeln!(stream, "{}procedure[memory[pointer]] = {{", indent)?;
+        write_as_c(inner, indent.clone() + "  ", stream)?;
+        writeln!(stream, "{}}}", indent)?;
+      }
+      ProgramToken::Call => {
+        writeln!(stream, "{}procedure[memory[pointer]]()", indent)?;
       }
     }
   }
+
+  Ok(())
 }
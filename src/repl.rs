@@ -0,0 +1,221 @@
+use std::fs::read_to_string;
+use std::io::{self, Write};
+
+use interpreter::{run_program_detecting_endless_loops, BfIo};
+use optimizer::optimize_parsed;
+use parser::parse_program_checked;
+use types::{ProgramToken, State};
+
+const HELP: &str = "Enter Brainfuck to run it. Lines with an unclosed [ continue on the next line.
+  :reset          Clear the tape
+  :dump [a..b]    Show cells a to b (default 0..32)
+  :ir             Show the IR of the last program
+  :load <file>    Run a file
+  :quit           Exit";
+
+const DUMP_ROW: usize = 16;
+
+// Keeps track of whether the program left the cursor at the start of a line.
+struct LineTracker<'a, T: BfIo + 'a> {
+  io: &'a mut T,
+  at_line_start: bool,
+}
+
+impl<'a, T: BfIo + 'a> BfIo for LineTracker<'a, T> {
+  fn print(&mut self, ch: u8) {
+    self.io.print(ch);
+    self.at_line_start = ch == b'\n';
+  }
+
+  fn read(&mut self) -> u8 {
+    self.io.read()
+  }
+}
+
+fn parse_range(range: &str) -> Option<(usize, usize)> {
+  let mut parts = range.splitn(2, "..");
+  let start = parts.next()?.trim().parse().ok()?;
+  let end = parts.next()?.trim().parse().ok()?;
+
+  if start < end {
+    Some((start, end))
+  } else {
+    None
+  }
+}
+
+pub struct Repl {
+  pub state: State,
+  last_program: Vec<ProgramToken>,
+  // Lines of a loop which hasn't been closed yet.
+  pending: String,
+}
+
+impl Repl {
+  pub fn new() -> Repl {
+    Repl {
+      state: State::new(),
+      last_program: Vec::new(),
+      pending: String::new(),
+    }
+  }
+
+  pub fn prompt(&self) -> &'static str {
+    if self.pending.is_empty() {
+      "bf> "
+    } else {
+      "..> "
+    }
+  }
+
+  // Returns false when the user wants to quit.
+  pub fn handle_line(
+    &mut self,
+    line: &str,
+    io: &mut impl BfIo,
+    out: &mut dyn Write,
+  ) -> io::Result<bool> {
+    if self.pending.is_empty() && line.trim_start().starts_with(':') {
+      return self.handle_command(line.trim(), io, out);
+    }
+
+    self.pending.push_str(line);
+    self.pending.push('\n');
+
+    let depth = self.pending.chars().fold(0, |depth, x| match x {
+      '[' => depth + 1,
+      ']' => depth - 1,
+      _ => depth,
+    });
+
+    if depth > 0 {
+      return Ok(true);
+    }
+
+    let source = self.pending.split_off(0);
+    self.execute(&source, io, out)?;
+    Ok(true)
+  }
+
+  fn handle_command(
+    &mut self,
+    line: &str,
+    io: &mut impl BfIo,
+    out: &mut dyn Write,
+  ) -> io::Result<bool> {
+    let mut parts = line.splitn(2, char::is_whitespace);
+    let command = parts.next().unwrap_or("");
+    let argument = parts.next().map(|x| x.trim()).unwrap_or("");
+
+    match command {
+      ":reset" => {
+        self.state = State::new();
+        writeln!(out, "Tape cleared.")?;
+      }
+      ":dump" => {
+        let range = if argument.is_empty() {
+          Some((0, 32))
+        } else {
+          parse_range(argument)
+        };
+
+        match range {
+          Some((start, end)) => self.write_dump(start, end, out)?,
+          None => writeln!(out, "error: Expected a range like 0..32")?,
+        }
+      }
+      ":ir" => {
+        writeln!(out, "{:?}", self.last_program)?;
+      }
+      ":load" => match read_to_string(argument) {
+        Ok(source) => self.execute(&source, io, out)?,
+        Err(error) => writeln!(out, "error: Couldn't read {}: {}", argument, error)?,
+      },
+      ":help" => {
+        writeln!(out, "{}", HELP)?;
+      }
+      ":quit" | ":q" => {
+        return Ok(false);
+      }
+      other => {
+        writeln!(out, "error: Unknown command {}. Try :help", other)?;
+      }
+    }
+
+    Ok(true)
+  }
+
+  fn execute(&mut self, source: &str, io: &mut impl BfIo, out: &mut dyn Write) -> io::Result<()> {
+    let tokens = match parse_program_checked(source) {
+      Ok(tokens) => tokens,
+      Err(error) => {
+        return writeln!(out, "error: {}", error);
+      }
+    };
+
+    let program = optimize_parsed(&tokens);
+
+    let mut tracker = LineTracker {
+      io,
+      at_line_start: true,
+    };
+
+    let result = run_program_detecting_endless_loops(&program, &mut self.state, &mut tracker);
+
+    if !tracker.at_line_start {
+      writeln!(out)?;
+    }
+
+    self.last_program = program;
+
+    if let Err(error) = result {
+      writeln!(out, "error: {}", error)?;
+      writeln!(
+        out,
+        "The tape may be in an inconsistent state, use :reset to clear it."
+      )?;
+      return Ok(());
+    }
+
+    self.write_status(out)
+  }
+
+  // The pointer and a few cells around it, e.g. "pointer 2 | cells 0..7: 0 1 [2] 0 0 0 0"
+  fn write_status(&self, out: &mut dyn Write) -> io::Result<()> {
+    let pointer = self.state.pointer;
+    let start = pointer.saturating_sub(4);
+    let end = pointer.saturating_add(5).min(self.state.memory.len());
+
+    if start >= end {
+      return writeln!(out, "pointer {} is outside the tape", pointer);
+    }
+
+    write!(out, "pointer {} | cells {}..{}:", pointer, start, end)?;
+
+    for index in start..end {
+      if index == pointer {
+        write!(out, " [{}]", self.state.memory[index])?;
+      } else {
+        write!(out, " {}", self.state.memory[index])?;
+      }
+    }
+
+    writeln!(out)
+  }
+
+  fn write_dump(&self, start: usize, end: usize, out: &mut dyn Write) -> io::Result<()> {
+    let end = end.min(self.state.memory.len());
+
+    for row_start in (start..end).step_by(DUMP_ROW) {
+      write!(out, "{:>5}:", row_start)?;
+
+      for index in row_start..(row_start + DUMP_ROW).min(end) {
+        write!(out, " {:>3}", self.state.memory[index])?;
+      }
+
+      writeln!(out)?;
+    }
+
+    Ok(())
+  }
+}
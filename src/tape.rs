@@ -0,0 +1,198 @@
+use std::collections::HashMap;
+
+use interpreter::RunError;
+use types::State;
+
+// The memory the interpreter operates on. Cells are addressed by absolute index; which indices
+// are valid depends on the implementation, and accessing any other cell is an error.
+pub trait Tape {
+  fn pointer(&self) -> isize;
+  fn move_pointer(&mut self, by: isize);
+  fn get(&self, index: isize) -> Result<u8, RunError>;
+  fn set(&mut self, index: isize, value: u8) -> Result<(), RunError>;
+}
+
+impl Tape {
+  pub fn from_name(name: &str) -> Option<Box<Tape>> {
+    match name {
+      "fixed" => Some(Box::new(State::new())),
+      "growable" => Some(Box::new(GrowableTape::new())),
+      "bidirectional" => Some(Box::new(BidirectionalTape::new())),
+      "sparse" => Some(Box::new(SparseTape::new())),
+      _ => None,
+    }
+  }
+}
+
+fn non_negative(index: isize) -> Result<usize, RunError> {
+  if index < 0 {
+    return Err(RunError::PointerUnderflow { cell: index });
+  }
+
+  Ok(index as usize)
+}
+
+// The default tape: a fixed 64 KiB, just like the WebAssembly backend's memory.
+impl Tape for State {
+  fn pointer(&self) -> isize {
+    self.pointer as isize
+  }
+
+  fn move_pointer(&mut self, by: isize) {
+    self.pointer = ((self.pointer as isize) + by) as usize;
+  }
+
+  fn get(&self, index: isize) -> Result<u8, RunError> {
+    match self.memory.get(non_negative(index)?) {
+      Some(value) => Ok(*value),
+      None => Err(RunError::PointerOverflow { cell: index }),
+    }
+  }
+
+  fn set(&mut self, index: isize, value: u8) -> Result<(), RunError> {
+    match self.memory.get_mut(non_negative(index)?) {
+      Some(cell) => {
+        *cell = value;
+        Ok(())
+      }
+      None => Err(RunError::PointerOverflow { cell: index }),
+    }
+  }
+}
+
+// Starts empty and grows to the right whenever a cell past the end is written.
+pub struct GrowableTape {
+  pub pointer: isize,
+  pub memory: Vec<u8>,
+}
+
+impl GrowableTape {
+  pub fn new() -> GrowableTape {
+    GrowableTape {
+      pointer: 0,
+      memory: Vec::new(),
+    }
+  }
+}
+
+impl Tape for GrowableTape {
+  fn pointer(&self) -> isize {
+    self.pointer
+  }
+
+  fn move_pointer(&mut self, by: isize) {
+    self.pointer += by;
+  }
+
+  fn get(&self, index: isize) -> Result<u8, RunError> {
+    Ok(self.memory.get(non_negative(index)?).cloned().unwrap_or(0))
+  }
+
+  fn set(&mut self, index: isize, value: u8) -> Result<(), RunError> {
+    let index = non_negative(index)?;
+
+    if index >= self.memory.len() {
+      self.memory.resize(index + 1, 0);
+    }
+
+    self.memory[index] = value;
+    Ok(())
+  }
+}
+
+// Grows in both directions, so the pointer may go left of where it started.
+pub struct BidirectionalTape {
+  pub pointer: isize,
+  // Cells 0, 1, 2...
+  pub right: Vec<u8>,
+  // Cells -1, -2, -3...
+  pub left: Vec<u8>,
+}
+
+impl BidirectionalTape {
+  pub fn new() -> BidirectionalTape {
+    BidirectionalTape {
+      pointer: 0,
+      right: Vec::new(),
+      left: Vec::new(),
+    }
+  }
+
+  fn half(&mut self, index: isize) -> (&mut Vec<u8>, usize) {
+    if index >= 0 {
+      (&mut self.right, index as usize)
+    } else {
+      (&mut self.left, (-1 - index) as usize)
+    }
+  }
+}
+
+impl Tape for BidirectionalTape {
+  fn pointer(&self) -> isize {
+    self.pointer
+  }
+
+  fn move_pointer(&mut self, by: isize) {
+    self.pointer += by;
+  }
+
+  fn get(&self, index: isize) -> Result<u8, RunError> {
+    let cell = if index >= 0 {
+      self.right.get(index as usize)
+    } else {
+      self.left.get((-1 - index) as usize)
+    };
+
+    Ok(cell.cloned().unwrap_or(0))
+  }
+
+  fn set(&mut self, index: isize, value: u8) -> Result<(), RunError> {
+    let (cells, index) = self.half(index);
+
+    if index >= cells.len() {
+      cells.resize(index + 1, 0);
+    }
+
+    cells[index] = value;
+    Ok(())
+  }
+}
+
+// Only stores cells which aren't zero, for programs which touch a few cells far apart.
+pub struct SparseTape {
+  pub pointer: isize,
+  pub cells: HashMap<isize, u8>,
+}
+
+impl SparseTape {
+  pub fn new() -> SparseTape {
+    SparseTape {
+      pointer: 0,
+      cells: HashMap::new(),
+    }
+  }
+}
+
+impl Tape for SparseTape {
+  fn pointer(&self) -> isize {
+    self.pointer
+  }
+
+  fn move_pointer(&mut self, by: isize) {
+    self.pointer += by;
+  }
+
+  fn get(&self, index: isize) -> Result<u8, RunError> {
+    Ok(self.cells.get(&index).cloned().unwrap_or(0))
+  }
+
+  fn set(&mut self, index: isize, value: u8) -> Result<(), RunError> {
+    if value == 0 {
+      self.cells.remove(&index);
+    } else {
+      self.cells.insert(index, value);
+    }
+
+    Ok(())
+  }
+}
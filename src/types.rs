@@ -1,4 +1,4 @@
-#[derive(Debug, PartialEq)]
+#[derive(Debug, PartialEq, Clone, Copy)]
 pub enum ParseToken {
   IncrAddr,
   DecrAddr,
@@ -7,6 +7,29 @@ pub enum ParseToken {
   LoopStart,
   LoopEnd,
   Print,
+  Read,
+  // pbrain's ( ) and :.
+  ProcedureStart,
+  ProcedureEnd,
+  Call,
+  // Extended Brainfuck Type I's @, $, !, {, }, ~, ^, & and |.
+  End,
+  Store,
+  Retrieve,
+  ShiftLeft,
+  ShiftRight,
+  Not,
+  Xor,
+  And,
+  Or,
+}
+
+// Everything in a source file, so that it can be printed back out.
+#[derive(Debug, PartialEq)]
+pub enum SourceItem {
+  Token(ParseToken),
+  // A run of characters which aren't commands, including whitespace.
+  Comment(String),
 }
 
 #[derive(Debug, PartialEq, Clone)]
@@ -14,6 +37,28 @@ pub enum MemoryOp {
   ChangeValue(i8),
   SetValue(i8),
   Print,
+  // Reads a byte from the input, or 0 at the end of it.
+  Read,
+  // Copy the cell to the storage byte, or the storage byte to the cell.
+  Store,
+  Retrieve,
+  // Logical shifts by one bit.
+  ShiftLeft,
+  ShiftRight,
+  Not,
+  // Combine the cell with the storage byte.
+  Xor,
+  And,
+  Or,
+}
+
+impl MemoryOp {
+  pub fn uses_storage(&self) -> bool {
+    match self {
+      MemoryOp::Store | MemoryOp::Retrieve | MemoryOp::Xor | MemoryOp::And | MemoryOp::Or => true,
+      _ => false,
+    }
+  }
 }
 
 #[derive(Debug, PartialEq, Clone)]
@@ -21,6 +66,13 @@ pub enum ProgramToken {
   ChangeAddr(isize),
   Offset(isize, MemoryOp),
   Loop(Vec<ProgramToken>),
+  // Runs the body while the cell at the offset from the pointer isn't 0. The optimizer uses it for
+  // loops which end where they started, so that the pointer doesn't have to move around them.
+  OffsetLoop(isize, Vec<ProgramToken>),
+  // Defines the procedure numbered by the current cell, replacing any earlier definition.
+  Procedure(Vec<ProgramToken>),
+  // Calls the procedure numbered by the current cell.
+  Call,
 }
 
 impl ProgramToken {
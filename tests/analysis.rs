@@ -0,0 +1,89 @@
+extern crate bfcrs;
+
+use bfcrs::analysis::{pointer_range, PointerRange, TAPE_CELLS};
+use bfcrs::dialect::Dialect;
+use bfcrs::lang::compile_structured;
+use bfcrs::optimizer::{convert_tokens, optimize, optimize_parsed};
+use bfcrs::parser::parse_program;
+
+fn range(source: &str) -> PointerRange {
+  pointer_range(&optimize_parsed(&parse_program(source)))
+}
+
+fn bounded(min: isize, max: isize) -> PointerRange {
+  PointerRange {
+    min: Some(min),
+    max: Some(max),
+  }
+}
+
+#[test]
+pub fn straight_code_is_followed_exactly() {
+  assert_eq!(bounded(0, 0), range(""));
+  assert_eq!(bounded(0, 0), range(">>>"));
+  assert_eq!(bounded(-1, 2), range(">>+<<<-"));
+  assert_eq!(Some(4), range(">>+<<<-").cells());
+  assert!(range("<+>").can_go_left());
+  assert!(!range(">+<").can_go_left());
+}
+
+#[test]
+pub fn balanced_loops_keep_the_range_bounded() {
+  let copy = range("+[->>+<<]>>[-<<+>>]");
+  assert_eq!(bounded(0, 2), copy);
+  assert!(copy.stays_within(3));
+  assert!(!copy.stays_within(2));
+
+  assert_eq!(bounded(0, 5), range(">>[->[->+<]<[->>>+<<<]]"));
+}
+
+#[test]
+pub fn moving_loops_are_unbounded_in_their_direction() {
+  assert_eq!(
+    PointerRange {
+      min: Some(0),
+      max: None,
+    },
+    range("+[>+]")
+  );
+  assert_eq!(
+    PointerRange {
+      min: None,
+      max: Some(2),
+    },
+    range(">>+[<]")
+  );
+  // A loop which contains a moving loop can move either way.
+  assert_eq!(
+    PointerRange {
+      min: None,
+      max: None,
+    },
+    range("+[>[<]>]")
+  );
+  assert!(!range("+[>+]").stays_within(TAPE_CELLS));
+  assert!(range(">>+[<]").can_go_left());
+}
+
+#[test]
+pub fn calls_can_go_anywhere() {
+  let program = optimize(&convert_tokens(&Dialect::pbrain().parse("(>+<)>")));
+  assert_eq!(bounded(0, 0), pointer_range(&program));
+
+  let program = optimize(&convert_tokens(&Dialect::pbrain().parse("(>+<):")));
+  assert_eq!(
+    PointerRange {
+      min: None,
+      max: None,
+    },
+    pointer_range(&program)
+  );
+}
+
+#[test]
+pub fn structured_programs_stay_within_their_cells() {
+  // Two variables, and the temporary cells of the copy, the if and the print.
+  let program =
+    compile_structured("var x = 3; var y = x; while x { x -= 1; if y { print \"!\"; } }").unwrap();
+  assert_eq!(bounded(0, 4), pointer_range(&optimize(&program)));
+}
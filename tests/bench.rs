@@ -0,0 +1,58 @@
+extern crate bfcrs;
+
+use bfcrs::bench::{all_engines, bench_source, Engine};
+use bfcrs::interpreter::run_program_counting;
+use bfcrs::optimizer::{convert_tokens, optimize_parsed, OptimizationLevel, OPTIMIZATION_LEVELS};
+use bfcrs::parser::parse_program;
+use bfcrs::types::State;
+
+mod common;
+use common::types::MockIo;
+
+#[test]
+pub fn ops_count_loop_checks() {
+  // Two ops, then the loop is checked three times and its body runs twice.
+  let program = convert_tokens(&parse_program("++[-]"));
+  let ops = run_program_counting(&program, &mut State::new(), &mut MockIo::new());
+  assert_eq!(Ok(2 + 3 + 2), ops);
+
+  // The optimizer turns it all into a single SetValue.
+  let program = optimize_parsed(&parse_program("++[-]"));
+  let ops = run_program_counting(&program, &mut State::new(), &mut MockIo::new());
+  assert_eq!(Ok(1), ops);
+}
+
+#[test]
+pub fn optimization_levels_do_less_work() {
+  let measurements = bench_source(
+    include_str!("../bf/hello.bf"),
+    &[],
+    &[
+      Engine::Interpreter(OptimizationLevel::None),
+      Engine::Interpreter(OptimizationLevel::Merge),
+      Engine::Interpreter(OptimizationLevel::Full),
+    ],
+  ).unwrap();
+
+  assert_eq!(3, measurements.len());
+  for pair in measurements.windows(2) {
+    assert!(pair[1].ir_size < pair[0].ir_size);
+    assert!(pair[1].ops.unwrap() < pair[0].ops.unwrap());
+  }
+}
+
+#[test]
+pub fn every_engine_agrees() {
+  let measurements = bench_source(include_str!("../bf/hello.bf"), &[], &all_engines()).unwrap();
+
+  let engines = measurements.iter().map(|x| x.engine).collect::<Vec<_>>();
+
+  // Native engines are skipped when their tools aren't installed.
+  let built_in = OPTIMIZATION_LEVELS.len() + 3;
+  assert_eq!(&all_engines()[..built_in], &engines[..built_in]);
+}
+
+#[test]
+pub fn invalid_programs_are_rejected() {
+  assert!(bench_source("+]", &[], &all_engines()).is_err());
+}
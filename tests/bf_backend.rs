@@ -0,0 +1,92 @@
+extern crate bfcrs;
+
+use bfcrs::backend::bf::BfBackend;
+use bfcrs::backend::Backend;
+use bfcrs::formatter::{format_source, FormatOptions, FormatStyle};
+use bfcrs::optimizer::{convert_tokens, optimize_parsed};
+use bfcrs::parser::parse_program;
+use bfcrs::types::MemoryOp::*;
+use bfcrs::types::ProgramToken;
+use bfcrs::types::ProgramToken::*;
+
+mod common;
+use common::interpreter_util::{run_bf_in_interpreter, run_tokens_in_interpreter};
+
+fn to_bf(program: &[ProgramToken]) -> String {
+  let mut code = Vec::new();
+  BfBackend.compile_to_stream(program, &mut code);
+  String::from_utf8(code).unwrap()
+}
+
+fn optimize_source(source: &str) -> String {
+  to_bf(&optimize_parsed(&parse_program(source)))
+}
+
+#[test]
+pub fn offsets_are_reached_without_walking_back() {
+  let program = vec![
+    Offset(2, ChangeValue(3)),
+    Offset(5, Print),
+    ChangeAddr(4),
+    Loop(vec![Offset(0, ChangeValue(-1)), Offset(1, SetValue(2))]),
+  ];
+
+  assert_eq!(">>+++>>>.<[->[-]++<]\n", to_bf(&program));
+}
+
+#[test]
+pub fn offset_loops_keep_the_head_at_their_cell() {
+  let program = vec![
+    OffsetLoop(
+      2,
+      vec![Offset(2, ChangeValue(-1)), Offset(3, ChangeValue(1))],
+    ),
+    Offset(0, Print),
+  ];
+
+  assert_eq!(">>[->+<]<<.\n", to_bf(&program));
+  assert_eq!(program, optimize_parsed(&parse_program(&to_bf(&program))));
+}
+
+#[test]
+pub fn changes_wrap_the_shorter_way() {
+  assert_eq!("---\n", to_bf(&[Offset(0, ChangeValue(-3))]));
+  assert_eq!(
+    128,
+    to_bf(&[Offset(0, ChangeValue(-128))]).matches('+').count()
+  );
+  assert_eq!("[-]-\n", to_bf(&[Offset(0, SetValue(-1))]));
+  assert_eq!("", to_bf(&[]));
+}
+
+#[test]
+pub fn round_trips_run_the_same() {
+  for source in &[
+    include_str!("../bf/hello.bf"),
+    include_str!("../bf/sierpinski.bf"),
+  ] {
+    let expected = run_bf_in_interpreter(source);
+    // The translation runs unoptimized, so it's checked on its own.
+    let actual =
+      run_tokens_in_interpreter(&convert_tokens(&parse_program(&optimize_source(source))));
+
+    assert_eq!(expected.output, actual.output);
+    assert_eq!(expected.state.pointer, actual.state.pointer);
+    assert!(expected.state.memory[..] == actual.state.memory[..]);
+  }
+}
+
+#[test]
+pub fn round_trips_are_stable_and_small() {
+  for source in &[
+    include_str!("../bf/hello.bf"),
+    include_str!("../bf/sierpinski.bf"),
+    include_str!("../bf/mandelbrot.bf"),
+  ] {
+    let optimized = optimize_source(source);
+    let minified = format_source(source, FormatOptions::new(FormatStyle::Minify)).unwrap();
+
+    assert_eq!(optimized, optimize_source(&optimized));
+    assert!(optimized.len() <= minified.len());
+  }
+}
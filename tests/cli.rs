@@ -0,0 +1,82 @@
+use std::env::{current_exe, temp_dir};
+use std::fs::{create_dir_all, read_to_string, remove_dir_all, write};
+use std::path::PathBuf;
+use std::process::{Command, Output};
+
+// Cargo builds bfcrsc next to the directory the test binaries are in.
+fn bfcrsc(args: &[&str]) -> Output {
+  let mut path = current_exe().unwrap();
+  path.pop();
+
+  if path.ends_with("deps") {
+    path.pop();
+  }
+
+  Command::new(path.join("bfcrsc"))
+    .args(args)
+    .output()
+    .unwrap()
+}
+
+fn scratch_dir(name: &str) -> PathBuf {
+  let dir = temp_dir().join(format!("bfcrs-cli-test-{}", name));
+  let _ = remove_dir_all(&dir);
+  create_dir_all(&dir).unwrap();
+  dir
+}
+
+#[test]
+pub fn bf_target_does_not_overwrite_the_input() {
+  let dir = scratch_dir("bf-default-output");
+  let input = dir.join("program.bf");
+  let source = "++ +++ [>++<-] >.\n";
+  write(&input, source).unwrap();
+
+  let result = bfcrsc(&["build", input.to_str().unwrap(), "--target", "bf"]);
+
+  assert!(result.status.success());
+  assert_eq!(source, read_to_string(&input).unwrap());
+  assert_eq!(
+    "+++++[>++<-]>.",
+    read_to_string(dir.join("program.opt.bf"))
+      .unwrap()
+      .trim_end()
+  );
+
+  remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+pub fn output_must_not_be_the_input() {
+  let dir = scratch_dir("output-is-input");
+  let input = dir.join("program.bf");
+  let input = input.to_str().unwrap();
+  write(input, "+.").unwrap();
+
+  let result = bfcrsc(&["build", input, "--target", "bf", "-o", input]);
+
+  assert!(!result.status.success());
+  assert!(String::from_utf8(result.stderr)
+    .unwrap()
+    .contains("would be overwritten"));
+  assert_eq!("+.", read_to_string(input).unwrap());
+
+  remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+pub fn translated_programs_are_located_by_line() {
+  let dir = scratch_dir("translated-lint");
+  let input = dir.join("program.ook");
+  let input = input.to_str().unwrap();
+  // +[] with the loop indented on the second line.
+  write(input, "Ook. Ook.\n  Ook! Ook? Ook? Ook!\n").unwrap();
+
+  let result = bfcrsc(&["build", input, "--dialect", "ook", "-o", "-"]);
+  let stderr = String::from_utf8(result.stderr).unwrap();
+
+  assert!(result.status.success());
+  assert!(stderr.contains(" at line 2 (endless-loop)"), "{}", stderr);
+
+  remove_dir_all(&dir).unwrap();
+}
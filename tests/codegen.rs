@@ -0,0 +1,85 @@
+extern crate bfcrs;
+
+use bfcrs::codegen::text_to_bf::{generate, Goal};
+use bfcrs::interpreter::{run_program, run_program_counting};
+use bfcrs::optimizer::convert_tokens;
+use bfcrs::parser::parse_program;
+use bfcrs::types::State;
+
+mod common;
+use common::types::MockIo;
+
+const BANNER: &[u8] =
+  b"bfcrs - an optimizing Brainfuck compiler\n========================================\n";
+
+fn run(code: &str) -> Vec<u8> {
+  let mut io = MockIo::new();
+  run_program(
+    &convert_tokens(&parse_program(code)),
+    &mut State::new(),
+    &mut io,
+  )
+  .unwrap();
+  io.output.chars().map(|x| x as u8).collect()
+}
+
+fn count_ops(code: &str) -> u64 {
+  let program = convert_tokens(&parse_program(code));
+  run_program_counting(&program, &mut State::new(), &mut MockIo::new()).unwrap()
+}
+
+#[test]
+pub fn generated_programs_print_the_text() {
+  let every_byte = (0..256).map(|x| x as u8).collect::<Vec<_>>();
+
+  for text in &[
+    &b"Hello World!\n"[..],
+    BANNER,
+    &every_byte,
+    b"\xff\x80\x00",
+    b"a",
+  ] {
+    for goal in &[Goal::Size, Goal::Speed] {
+      assert_eq!(text.to_vec(), run(&generate(text, *goal)), "{:?}", goal);
+    }
+  }
+}
+
+#[test]
+pub fn empty_text_needs_no_code() {
+  assert_eq!("", generate(b"", Goal::Size));
+  assert_eq!("", generate(b"", Goal::Speed));
+}
+
+#[test]
+pub fn goals_trade_size_for_speed() {
+  let small = generate(BANNER, Goal::Size);
+  let fast = generate(BANNER, Goal::Speed);
+
+  assert!(small.len() <= fast.len());
+  assert!(count_ops(&fast) <= count_ops(&small));
+}
+
+// Printing every byte from a single cell, changing it the shorter way around.
+fn single_cell_length(text: &[u8]) -> usize {
+  let mut cell = 0u8;
+  let mut length = 0;
+
+  for &byte in text {
+    let up = byte.wrapping_sub(cell) as usize;
+    length += up.min(256 - up) + 1;
+    cell = byte;
+  }
+
+  length
+}
+
+#[test]
+pub fn loops_and_cells_beat_a_single_cell() {
+  let small = generate(BANNER, Goal::Size);
+  assert!(small.contains('['));
+  assert!(small.len() * 2 < single_cell_length(BANNER));
+
+  // Every = after the first is printed without changing the cell.
+  assert!(small.contains(&".".repeat(40)));
+}
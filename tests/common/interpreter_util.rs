@@ -1,30 +1,14 @@
-use bfcrs::interpreter::{run_program, BfIo};
+use bfcrs::interpreter::run_program;
 use bfcrs::optimizer::optimize_parsed;
 use bfcrs::parser::parse_program;
 use bfcrs::types::{ProgramToken, State};
-use common::types::RunResult;
-
-struct MockIo {
-  output: String,
-}
-
-impl BfIo for MockIo {
-  fn print(&mut self, ch: u8) {
-    self.output.push(ch.into());
-  }
-
-  fn read(&mut self) -> u8 {
-    0
-  }
-}
+use common::types::{MockIo, RunResult};
 
 pub fn run_tokens_in_interpreter(program: &[ProgramToken]) -> RunResult {
   let mut state = State::new();
-  let mut io = MockIo {
-    output: String::new(),
-  };
+  let mut io = MockIo::new();
 
-  run_program(program, &mut state, &mut io);
+  run_program(program, &mut state, &mut io).unwrap();
 
   RunResult {
     output: io.output,
@@ -1,4 +1,4 @@
 pub mod interpreter_util;
-pub mod node_bridge;
 pub mod test_util;
 pub mod types;
+pub mod wasm_util;
@@ -2,8 +2,8 @@ use bfcrs::optimizer::optimize_parsed;
 use bfcrs::parser::parse_program;
 use bfcrs::types::ProgramToken;
 use common::interpreter_util::run_tokens_in_interpreter;
-use common::node_bridge::run_tokens_in_node;
 use common::types::RunResult;
+use common::wasm_util::run_tokens_in_wasm;
 
 pub fn chunked_assert_eq(a: &[u8], b: &[u8]) {
   assert_eq!(a.len(), b.len());
@@ -23,8 +23,8 @@ pub fn compare_results(a: &RunResult, b: &RunResult) {
 
 pub fn run_and_expect_same_tokens(program: &[ProgramToken]) {
   let interpreter_result = run_tokens_in_interpreter(&program);
-  let node_result = run_tokens_in_node(&program);
-  compare_results(&interpreter_result, &node_result);
+  let wasm_result = run_tokens_in_wasm(&program);
+  compare_results(&interpreter_result, &wasm_result);
 }
 
 pub fn run_and_expect_same(source: &str) {
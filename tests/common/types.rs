@@ -1,6 +1,36 @@
+use bfcrs::interpreter::BfIo;
 use bfcrs::types::State;
 
 pub struct RunResult {
   pub output: String,
   pub state: State,
 }
+
+pub struct MockIo {
+  pub output: String,
+  pub input: Vec<u8>,
+}
+
+impl MockIo {
+  pub fn new() -> MockIo {
+    MockIo::with_input(&[])
+  }
+
+  pub fn with_input(input: &[u8]) -> MockIo {
+    MockIo {
+      output: String::new(),
+      input: input.iter().rev().cloned().collect(),
+    }
+  }
+}
+
+impl BfIo for MockIo {
+  fn print(&mut self, ch: u8) {
+    self.output.push(ch.into());
+  }
+
+  // The end of the input reads as 0.
+  fn read(&mut self) -> u8 {
+    self.input.pop().unwrap_or(0)
+  }
+}
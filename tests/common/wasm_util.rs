@@ -0,0 +1,25 @@
+use bfcrs::backend::wasm::executor::run_wasm;
+use bfcrs::types::ProgramToken;
+use bfcrs::{compile_program, compile_tokens};
+
+use common::types::{MockIo, RunResult};
+
+pub fn run_wasm_in_executor(code: &[u8]) -> RunResult {
+  let mut io = MockIo::new();
+  let state = run_wasm(code, &mut io).expect("Module should run without trapping.");
+
+  RunResult {
+    output: io.output,
+    state,
+  }
+}
+
+pub fn run_bf_in_wasm(source: &str) -> RunResult {
+  let code = compile_program(source);
+  run_wasm_in_executor(&code)
+}
+
+pub fn run_tokens_in_wasm(program: &[ProgramToken]) -> RunResult {
+  let code = compile_tokens(program, false);
+  run_wasm_in_executor(&code)
+}
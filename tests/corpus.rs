@@ -0,0 +1,90 @@
+extern crate bfcrs;
+
+use std::fs::{read, read_dir, read_to_string};
+use std::path::{Path, PathBuf};
+use std::thread::{self, JoinHandle};
+
+use bfcrs::bench::{all_engines, measure};
+use bfcrs::optimizer::convert_tokens;
+use bfcrs::parser::parse_program_checked;
+
+// Every foo.bf in here is run with foo.bf.in as its input, if there is one, and has to print
+// exactly foo.bf.out on every engine.
+const CORPUS: &str = "bf";
+
+fn find_programs(directory: &Path, programs: &mut Vec<PathBuf>) {
+  for entry in read_dir(directory).unwrap() {
+    let path = entry.unwrap().path();
+
+    if path.is_dir() {
+      find_programs(&path, programs);
+    } else if path.extension().map_or(false, |x| x == "bf") {
+      programs.push(path);
+    }
+  }
+}
+
+fn with_suffix(path: &Path, suffix: &str) -> PathBuf {
+  let mut name = path.as_os_str().to_owned();
+  name.push(suffix);
+  PathBuf::from(name)
+}
+
+// Every engine runs on its own thread, mandelbrot takes a while on the slower ones.
+fn check_program(path: &Path) -> Vec<JoinHandle<Option<String>>> {
+  let source = read_to_string(path).unwrap();
+  let input = read(with_suffix(path, ".in")).unwrap_or_default();
+  let expected = match read(with_suffix(path, ".out")) {
+    Ok(expected) => expected,
+    Err(_) => {
+      let failure = format!("{}: No .out file", path.display());
+      return vec![thread::spawn(move || Some(failure))];
+    }
+  };
+
+  let program = match parse_program_checked(&source) {
+    Ok(tokens) => convert_tokens(&tokens),
+    Err(error) => {
+      let failure = format!("{}: {}", path.display(), error);
+      return vec![thread::spawn(move || Some(failure))];
+    }
+  };
+
+  all_engines()
+    .into_iter()
+    .map(|engine| {
+      let (path, program) = (path.display().to_string(), program.clone());
+      let (input, expected) = (input.clone(), expected.clone());
+
+      thread::spawn(move || match measure(engine, &program, &input) {
+        Ok(Some((_, ref output))) if *output != expected => Some(format!(
+          "{} on {}: Printed {:?}",
+          path,
+          engine.name(),
+          String::from_utf8_lossy(output)
+        )),
+        Ok(_) => None,
+        Err(error) => Some(format!("{} on {}: {}", path, engine.name(), error)),
+      })
+    })
+    .collect()
+}
+
+#[test]
+pub fn corpus_programs_print_their_output() {
+  let mut programs = Vec::new();
+  find_programs(Path::new(CORPUS), &mut programs);
+  programs.sort();
+  assert!(!programs.is_empty());
+
+  let runs = programs
+    .iter()
+    .flat_map(|path| check_program(path))
+    .collect::<Vec<_>>();
+  let failures = runs
+    .into_iter()
+    .filter_map(|run| run.join().unwrap())
+    .collect::<Vec<_>>();
+
+  assert!(failures.is_empty(), "\n{}", failures.join("\n"));
+}
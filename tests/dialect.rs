@@ -0,0 +1,102 @@
+extern crate bfcrs;
+
+use bfcrs::dialect::Dialect;
+use bfcrs::parser::{parse_program, parse_program_checked};
+use bfcrs::types::ParseToken::*;
+
+const TABLE: &str = "# Commands as words
+> right
+< left
++ up
+- down
+. out
+, in
+[ begin
+] end
+";
+
+#[test]
+pub fn ook_commands() {
+  let ook = Dialect::from_name("ook").unwrap();
+
+  assert_eq!(
+    vec![IncrAddr, DecrAddr, IncrValue, DecrValue, Print, Read, LoopStart, LoopEnd],
+    ook.parse("Ook. Ook? Ook? Ook. Ook. Ook. Ook! Ook! Ook! Ook. Ook. Ook! Ook! Ook? Ook? Ook!")
+  );
+}
+
+#[test]
+pub fn words_are_paired_across_lines_and_comments() {
+  let blub = Dialect::from_name("blub").unwrap();
+
+  // The line break stays, so that errors point at the right line.
+  assert_eq!("\n>+", blub.translate("Blub.\nBlub? fish Blub. Blub."));
+  assert_eq!(
+    "Unmatched ] at line 2, column 1",
+    parse_program_checked(&blub.translate("Blub. Blub.\nBlub? Blub!"))
+      .unwrap_err()
+      .to_string()
+  );
+}
+
+#[test]
+pub fn substitutions_prefer_the_longest_spelling() {
+  let dialect = Dialect::from_table("a", "> a\n< aa\n+ b\n- c\n. d\n, e\n[ f\n] g").unwrap();
+  assert_eq!(vec![DecrAddr, IncrAddr, IncrValue], dialect.parse("aaa xb"));
+}
+
+#[test]
+pub fn every_dialect_round_trips() {
+  let dialects = vec![
+    Dialect::from_name("ook").unwrap(),
+    Dialect::from_name("blub").unwrap(),
+    Dialect::from_table("words", TABLE).unwrap(),
+  ];
+
+  for source in &[
+    include_str!("../bf/hello.bf"),
+    include_str!("../bf/sierpinski.bf"),
+    include_str!("../bf/mandelbrot.bf"),
+  ] {
+    for dialect in &dialects {
+      let emitted = dialect.emit(source, 80);
+
+      assert!(emitted.lines().all(|line| line.len() <= 80));
+      assert_eq!(
+        parse_program(source),
+        dialect.parse(&emitted),
+        "{}",
+        dialect.name
+      );
+    }
+  }
+}
+
+#[test]
+pub fn brainfuck_is_left_alone() {
+  let brainfuck = Dialect::from_name("brainfuck").unwrap();
+  let source = include_str!("../bf/hello.bf");
+
+  assert!(brainfuck.is_brainfuck());
+  assert_eq!(source, brainfuck.translate(source));
+  assert_eq!(source, brainfuck.emit(source, 80));
+}
+
+#[test]
+pub fn invalid_tables_are_rejected() {
+  let errors = [
+    ("x up", "t:1: Unknown command x"),
+    ("+", "t:1: No spelling for +"),
+    ("+ up\n\n+ plus", "t:3: + is spelled twice"),
+    ("+ up", "t: No spelling for >"),
+  ];
+
+  for &(table, message) in &errors {
+    assert_eq!(
+      message,
+      Dialect::from_table("t", table).unwrap_err().to_string()
+    );
+  }
+
+  assert!(Dialect::load("no/such/dialect.tbs").is_err());
+}
@@ -0,0 +1,112 @@
+extern crate bfcrs;
+
+use bfcrs::dialect::Dialect;
+use bfcrs::interpreter::{
+  run_program_detecting_endless_loops, run_program_with_budget, RunError,
+};
+use bfcrs::lint::endless_loops;
+use bfcrs::optimizer::{convert_tokens, optimize};
+use bfcrs::parser::{parse_program, Extensions};
+use bfcrs::tape::Tape;
+use bfcrs::types::State;
+
+mod common;
+use common::types::MockIo;
+
+// Runs the optimized program, and returns its output and the state it ended in.
+fn run(source: &str) -> Result<(String, State), RunError> {
+  let program = optimize(&convert_tokens(&parse_program(source)));
+  let mut state = State::new();
+  let mut io = MockIo::new();
+
+  run_program_detecting_endless_loops(&program, &mut state, &mut io)?;
+  Ok((io.output, state))
+}
+
+// The code of the loops which never end.
+fn endless(source: &str) -> Vec<&str> {
+  endless_loops(source, Extensions::default())
+    .unwrap()
+    .iter()
+    .map(|lint| &source[lint.start..lint.end])
+    .collect()
+}
+
+#[test]
+pub fn loops_which_never_change_their_cell_are_found() {
+  assert_eq!(vec!["[]"], endless("+[]"));
+  assert_eq!(vec!["[>+<]"], endless("+[>+<]"));
+  // The cell could be 0, but if it isn't the loop hangs.
+  assert_eq!(vec!["[>.<]"], endless(",[>.<]"));
+  assert_eq!(vec!["[>[-]<]"], endless(",[>[-]<]"));
+  // Nested loops count too.
+  assert_eq!(vec!["[]"], endless("+[->,[]<]"));
+}
+
+#[test]
+pub fn loops_which_can_end_are_not_reported() {
+  // The loop never runs.
+  assert!(endless("[]").is_empty());
+  assert!(endless("+[-]").is_empty());
+  assert!(endless(",[,]").is_empty());
+  // A moving loop checks a different cell every time.
+  assert!(endless("+[>]").is_empty());
+  // The condition is changed by a nested loop, or a procedure could change it.
+  assert!(endless("+[>+[-<->]<]").is_empty());
+  assert!(endless_loops("+[:]", Dialect::pbrain().extensions)
+    .unwrap()
+    .is_empty());
+}
+
+#[test]
+pub fn interpreter_stops_empty_loops() {
+  assert_eq!(Err(RunError::EndlessLoop { cell: 0 }), run("+[]").map(|_| ()));
+}
+
+#[test]
+pub fn interpreter_stops_loops_which_cycle() {
+  // Cell 3 goes through all 256 values and starts over.
+  assert_eq!(Err(RunError::EndlessLoop { cell: 2 }), run(">>+[>+<]").map(|_| ()));
+}
+
+#[test]
+pub fn interpreter_stops_unoptimized_loops() {
+  let result = run_program_detecting_endless_loops(
+    &convert_tokens(&parse_program("+[>+>-<<]")),
+    &mut State::new(),
+    &mut MockIo::new(),
+  );
+  assert_eq!(Err(RunError::EndlessLoop { cell: 0 }), result);
+}
+
+#[test]
+pub fn loops_which_make_progress_keep_running() {
+  // Loops which only end after hundreds of iterations, and nested loops which run back and forth.
+  let (_, state) = run("-[>+<-]>[>+>+<<-]").unwrap();
+  assert_eq!(&[0, 0, 255, 255], &state.memory[0..4]);
+
+  let (_, state) = run("++[>+++[>+<-]<-]").unwrap();
+  assert_eq!(&[0, 0, 6], &state.memory[0..3]);
+
+  // Output is progress too.
+  let (output, _) = run("++[>+++[>.<-]<-]").unwrap();
+  assert_eq!(6, output.len());
+}
+
+#[test]
+pub fn the_detector_works_on_every_tape() {
+  let program = optimize(&convert_tokens(&parse_program(">+[<+>]")));
+
+  for name in &["fixed", "growable", "bidirectional", "sparse"] {
+    let mut tape = Tape::from_name(name).unwrap();
+    let result = run_program_detecting_endless_loops(&program, &mut *tape, &mut MockIo::new());
+    assert_eq!(Err(RunError::EndlessLoop { cell: 1 }), result, "{}", name);
+  }
+}
+
+#[test]
+pub fn other_runners_dont_detect_endless_loops() {
+  let program = optimize(&convert_tokens(&parse_program("+[]")));
+  let result = run_program_with_budget(&program, &mut State::new(), &mut MockIo::new(), 1000);
+  assert_eq!(Ok(None), result);
+}
@@ -0,0 +1,41 @@
+extern crate bfcrs;
+
+use bfcrs::backend::wasm::executor::run_wasm;
+use bfcrs::compile_program;
+use bfcrs::types::ProgramToken;
+
+mod common;
+use common::types::MockIo;
+use common::wasm_util::run_tokens_in_wasm;
+
+#[test]
+pub fn out_of_bounds_access_traps() {
+  let result = run_wasm(&compile_program("<+"), &mut MockIo::new());
+  assert!(result.is_err());
+}
+
+#[test]
+pub fn fused_ops_keep_semantics() {
+  let result = run_tokens_in_wasm(&[
+    ProgramToken::change_value(2),
+    ProgramToken::Loop(vec![
+      ProgramToken::offs_change_value(1, 3),
+      ProgramToken::change_value(-1),
+    ]),
+    ProgramToken::ChangeAddr(2),
+    ProgramToken::offs_change_value(-1, 1),
+  ]);
+
+  assert_eq!(&[0, 7, 0], &result.state.memory[0..3]);
+  assert_eq!(2, result.state.pointer);
+}
+
+#[test]
+pub fn reads_input() {
+  let mut io = MockIo::with_input(b"abc");
+  let result = run_wasm(&compile_program(",[.>,]<<,"), &mut io).unwrap();
+
+  assert_eq!("abc", io.output);
+  // Reading past the end of the input stores 0.
+  assert_eq!(&[b'a', 0, b'c', 0], &result.memory[0..4]);
+}
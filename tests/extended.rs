@@ -0,0 +1,124 @@
+extern crate bfcrs;
+
+use bfcrs::backend::bf::BfBackend;
+use bfcrs::backend::wasm::executor::run_wasi;
+use bfcrs::backend::wasm::wat::WatBackend;
+use bfcrs::backend::wasm::{WasmOptions, WasmTarget};
+use bfcrs::backend::Backend;
+use bfcrs::dialect::Dialect;
+use bfcrs::optimizer::{convert_tokens, optimize};
+use bfcrs::parser::{parse_program, parse_program_extended, Extensions};
+use bfcrs::types::MemoryOp::*;
+use bfcrs::types::ProgramToken;
+use bfcrs::types::ProgramToken::*;
+use bfcrs::{compile_tokens, compile_tokens_with_options};
+
+mod common;
+use common::interpreter_util::run_tokens_in_interpreter;
+use common::types::MockIo;
+use common::wasm_util::run_wasm_in_executor;
+
+// Cell 1 starts at 'A', and every command but @ leaves it there or prints it. The last . is
+// never reached.
+const BITS: &str = "++++++++[>++++++++<-]>+ $}.!{}.~~^|.&+. @ .";
+
+// Shifts wrap around, and the storage byte survives ops on other cells.
+const WRAPPING: &str = "-{> -}> +++$ >!< ^ >~ >---$<<<<|";
+
+fn ebf(source: &str) -> Vec<ProgramToken> {
+  optimize(&convert_tokens(
+    &Dialect::extended_brainfuck().parse(source),
+  ))
+}
+
+fn run_in_wasi(program: &[ProgramToken]) -> String {
+  let code = compile_tokens_with_options(program, WasmOptions::new(WasmTarget::Wasi));
+  let mut io = MockIo::new();
+  run_wasi(&code, &mut io).unwrap();
+  io.output
+}
+
+#[test]
+pub fn extended_commands_are_only_parsed_in_ebf() {
+  assert_eq!(
+    vec![Offset(0, ChangeValue(1))],
+    convert_tokens(&parse_program("$!{}~^&|@+"))
+  );
+  assert_eq!(
+    vec![Offset(0, Store), Offset(0, Not)],
+    convert_tokens(&Dialect::extended_brainfuck().parse("$~@+"))
+  );
+}
+
+#[test]
+pub fn interpreter_runs_extended_commands() {
+  assert_eq!(" AAB", run_tokens_in_interpreter(&ebf(BITS)).output);
+
+  let state = run_tokens_in_interpreter(&ebf(WRAPPING)).state;
+  assert_eq!(&[255, 127, 0, 0xFC, 253][..], &state.memory[..5]);
+}
+
+#[test]
+pub fn wasm_runs_extended_commands_like_the_interpreter() {
+  for source in &[BITS, WRAPPING] {
+    let program = ebf(source);
+    let expected = run_tokens_in_interpreter(&program);
+    let actual = run_wasm_in_executor(&compile_tokens(&program, false));
+
+    assert_eq!(expected.output, actual.output);
+    assert_eq!(expected.state.pointer, actual.state.pointer);
+    assert!(expected.state.memory[..] == actual.state.memory[..expected.state.memory.len()]);
+
+    assert_eq!(expected.output, run_in_wasi(&program));
+  }
+}
+
+#[test]
+pub fn procedures_share_the_storage_byte() {
+  let extensions = Extensions {
+    procedures: true,
+    extended_type_1: true,
+  };
+  // Procedure 0 stores cell 1, which is 'A', and the program retrieves it into cell 2.
+  let source = "(>$<) >>++++++++[<++++++++>-]<+< : >[-]>!.";
+  let program = optimize(&convert_tokens(&parse_program_extended(source, extensions)));
+
+  assert_eq!("A", run_tokens_in_interpreter(&program).output);
+  assert_eq!(
+    "A",
+    run_wasm_in_executor(&compile_tokens(&program, false)).output
+  );
+  assert_eq!("A", run_in_wasi(&program));
+}
+
+#[test]
+pub fn wasm_only_declares_the_storage_global_when_needed() {
+  let wat = |program: &[ProgramToken]| {
+    let mut wat = Vec::new();
+    WatBackend.compile_to_stream(program, &mut wat);
+    String::from_utf8(wat).unwrap()
+  };
+
+  let with_storage = wat(&ebf("$^"));
+  assert!(with_storage.contains("(global (;0;) (mut i32) (i32.const 0))"));
+  assert!(with_storage.contains("global.set 0"));
+  assert!(with_storage.contains("i32.xor"));
+
+  assert!(!wat(&ebf("{}~")).contains("(global"));
+}
+
+#[test]
+pub fn round_trips_keep_extended_commands() {
+  for source in &[BITS, WRAPPING] {
+    let program = ebf(source);
+    let mut code = Vec::new();
+    BfBackend.compile_to_stream(&program, &mut code);
+
+    assert_eq!(
+      program,
+      ebf(&String::from_utf8(code).unwrap()),
+      "{}",
+      source
+    );
+  }
+}
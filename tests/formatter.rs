@@ -0,0 +1,127 @@
+extern crate bfcrs;
+
+use bfcrs::formatter::{format_source, FormatOptions, FormatStyle};
+use bfcrs::fuzz::{generate_program, Rng};
+use bfcrs::parser::parse_program;
+
+const PROGRAMS: &[&str] = &[
+  include_str!("../bf/hello.bf"),
+  include_str!("../bf/sierpinski.bf"),
+  include_str!("../bf/mandelbrot.bf"),
+  include_str!("../bf/reverse.bf"),
+];
+
+fn pretty() -> FormatOptions {
+  FormatOptions::new(FormatStyle::Pretty)
+}
+
+fn minify() -> FormatOptions {
+  FormatOptions::new(FormatStyle::Minify)
+}
+
+fn every_style() -> Vec<FormatOptions> {
+  let mut narrow = pretty();
+  narrow.line_width = 8;
+  narrow.indent = 4;
+
+  let mut stripped = pretty();
+  stripped.keep_comments = false;
+
+  vec![
+    FormatOptions::new(FormatStyle::Minify),
+    pretty(),
+    narrow,
+    stripped,
+  ]
+}
+
+#[test]
+pub fn minify_strips_everything_else() {
+  assert_eq!(
+    "+[->+<].\n",
+    format_source("add one\n+ [ - > + < ] print it .", minify()).unwrap()
+  );
+  assert_eq!("", format_source("just a comment", minify()).unwrap());
+}
+
+#[test]
+pub fn pretty_prints_loops_on_their_own_lines() {
+  let source = "  set up\n++++[>++[->+<]<-]   done\n>>.";
+  let expected = "set up
+++++
+[
+  >++[->+<]<-
+]
+done
+>>.
+";
+
+  assert_eq!(expected, format_source(source, pretty()).unwrap());
+}
+
+#[test]
+pub fn pretty_respects_the_options() {
+  let mut options = pretty();
+  // The width includes the indentation.
+  options.line_width = 10;
+  options.indent = 4;
+  options.keep_comments = false;
+
+  let expected = "+++
+[
+    >+++++
+    ++<-
+]
+";
+
+  assert_eq!(
+    expected,
+    format_source("comment +++[>+++++++<-]", options).unwrap()
+  );
+}
+
+#[test]
+pub fn unbalanced_programs_are_rejected() {
+  for options in every_style() {
+    assert!(format_source("+[", options).is_err());
+  }
+}
+
+#[test]
+pub fn formatting_keeps_the_program() {
+  for source in PROGRAMS {
+    for options in every_style() {
+      let formatted = format_source(source, options).unwrap();
+      assert_eq!(parse_program(source), parse_program(&formatted));
+    }
+  }
+}
+
+#[test]
+pub fn formatting_is_idempotent() {
+  // Half of the generated programs get comments in awkward places.
+  let generated = (0..200).map(|seed| {
+    let source = generate_program(&mut Rng::new(seed), 16);
+
+    if seed % 2 == 0 {
+      source
+    } else {
+      source
+        .replace("[", " a\n\n  loop [ ")
+        .replace(".", ".done\n")
+    }
+  });
+  let programs = PROGRAMS
+    .iter()
+    .map(|x| x.to_string())
+    .chain(generated)
+    .collect::<Vec<_>>();
+
+  for source in &programs {
+    for options in every_style() {
+      let once = format_source(source, options).unwrap();
+      let twice = format_source(&once, options).unwrap();
+      assert_eq!(once, twice);
+    }
+  }
+}
@@ -0,0 +1,74 @@
+extern crate bfcrs;
+
+use bfcrs::fuzz::{check_program, fuzz_seed, generate_program, minimize, Rng, STEP_BUDGET};
+use bfcrs::interpreter::run_program_with_budget;
+use bfcrs::optimizer::convert_tokens;
+use bfcrs::parser::{parse_program, parse_program_checked};
+use bfcrs::types::State;
+
+mod common;
+use common::types::MockIo;
+
+const SEEDS: u64 = 20_000;
+
+#[test]
+pub fn budget_stops_infinite_loops() {
+  let run = |source| {
+    let program = convert_tokens(&parse_program(source));
+    run_program_with_budget(&program, &mut State::new(), &mut MockIo::new(), 100)
+  };
+
+  assert_eq!(Ok(Some(3)), run("+++"));
+  assert_eq!(Ok(None), run("+[]"));
+}
+
+#[test]
+pub fn generated_programs_are_balanced_and_repeatable() {
+  for seed in 0..100 {
+    let source = generate_program(&mut Rng::new(seed), 20);
+
+    assert!(parse_program_checked(&source).is_ok(), "{}", source);
+    assert_eq!(source, generate_program(&mut Rng::new(seed), 20));
+    assert_ne!(source, generate_program(&mut Rng::new(seed + 1), 20));
+  }
+}
+
+#[test]
+pub fn minimize_keeps_the_failure() {
+  // Comments are stripped before anything else.
+  let minimized = minimize("Prints +++[>+<-]>. the value", |source| {
+    source.contains('+') && source.contains('.')
+  });
+  assert_eq!("+.", minimized);
+
+  // Loops can be replaced with their bodies.
+  let minimized = minimize("[[+]>]>", |source| source.contains('+'));
+  assert_eq!("+", minimized);
+}
+
+#[test]
+pub fn long_runs_wrap_around() {
+  let plus = |count| "+".repeat(count);
+  let minus = |count| "-".repeat(count);
+
+  for source in &[
+    plus(130) + ".",
+    minus(300) + ".",
+    plus(128) + "[" + &minus(128) + "].",
+    plus(200) + ">" + &plus(100) + "<[->+<]>.",
+  ] {
+    assert_eq!(Ok(()), check_program(source, &[], STEP_BUDGET), "{}", source);
+  }
+}
+
+#[test]
+pub fn optimizer_matches_the_interpreter() {
+  for seed in 0..SEEDS {
+    if let Some(failure) = fuzz_seed(seed) {
+      panic!(
+        "Seed {}: {}\nProgram: {}\nInput: {:?}",
+        seed, failure.message, failure.source, failure.input
+      );
+    }
+  }
+}
@@ -4,12 +4,12 @@ use bfcrs::types::ProgramToken;
 use bfcrs::types::ProgramToken::*;
 
 mod common;
-use common::node_bridge::run_bf_in_node;
+use common::wasm_util::run_bf_in_wasm;
 use common::test_util::{run_and_expect_same, run_and_expect_same_tokens};
 
 #[test]
-pub fn inc_dec_smoke_node() {
-  let result = run_bf_in_node("++-- >+ >++");
+pub fn inc_dec_smoke_wasm() {
+  let result = run_bf_in_wasm("++-- >+ >++");
 
   assert_eq!("", result.output);
   assert_eq!(&[0, 1, 2], &result.state.memory[0..3]);
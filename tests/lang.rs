@@ -0,0 +1,142 @@
+extern crate bfcrs;
+
+use bfcrs::backend::bf::BfBackend;
+use bfcrs::backend::Backend;
+use bfcrs::interpreter::run_program;
+use bfcrs::lang::compile_structured;
+use bfcrs::optimizer::{convert_tokens, optimize};
+use bfcrs::parser::parse_program;
+use bfcrs::types::MemoryOp::*;
+use bfcrs::types::ProgramToken::*;
+use bfcrs::types::State;
+
+mod common;
+use common::interpreter_util::run_tokens_in_interpreter;
+use common::test_util::run_and_expect_same_tokens;
+use common::types::MockIo;
+
+const ALPHABET: &str = "
+var letter = 'a';
+var n = 26;
+while n {
+  print letter;
+  letter += 1;
+  n -= 1;
+}
+print \"\\n\";
+";
+
+// Both branches change the condition, which was copied first.
+const BRANCHES: &str = "
+var x = 2;
+var y = x;
+y += y;
+y -= x;
+if x { x = 0; print \"then \"; } else { print \"else \"; }
+if x { print \"then\"; } else { x = 'x'; print \"else \"; }
+if y { print y; }
+";
+
+#[test]
+pub fn assignments_use_constants_or_cells() {
+  assert_eq!(
+    vec![
+      Offset(0, SetValue(65)),
+      Offset(1, SetValue(0)),
+      Offset(1, ChangeValue(-1)),
+    ],
+    compile_structured("var x = 'A'; var y; y -= 1;").unwrap()
+  );
+
+  // y is moved to a temporary cell and back while it's added to x.
+  let program = optimize(&compile_structured("var x; var y = 3; x += y;").unwrap());
+  let result = run_tokens_in_interpreter(&program);
+  assert_eq!(&[3, 3, 0][..], &result.state.memory[..3]);
+}
+
+#[test]
+pub fn runs_loops_and_branches() {
+  let alphabet = compile_structured(ALPHABET).unwrap();
+  assert_eq!(
+    "abcdefghijklmnopqrstuvwxyz\n",
+    run_tokens_in_interpreter(&alphabet).output
+  );
+
+  let branches = compile_structured(BRANCHES).unwrap();
+  let result = run_tokens_in_interpreter(&branches);
+  assert_eq!("then else \u{2}", result.output);
+  // Temporary cells are cleared afterwards, and the pointer is back at cell 0.
+  assert_eq!(&[b'x', 2, 0, 0, 0][..], &result.state.memory[..5]);
+  assert_eq!(0, result.state.pointer);
+}
+
+#[test]
+pub fn optimized_programs_run_the_same_everywhere() {
+  for source in &[ALPHABET, BRANCHES] {
+    let program = compile_structured(source).unwrap();
+    let optimized = optimize(&program);
+
+    assert_eq!(
+      run_tokens_in_interpreter(&program).output,
+      run_tokens_in_interpreter(&optimized).output
+    );
+    run_and_expect_same_tokens(&optimized);
+
+    // The Brainfuck backend writes an equivalent program.
+    let mut code = Vec::new();
+    BfBackend.compile_to_stream(&optimized, &mut code);
+    let brainfuck = optimize(&convert_tokens(&parse_program(
+      &String::from_utf8(code).unwrap(),
+    )));
+    assert_eq!(
+      run_tokens_in_interpreter(&program).output,
+      run_tokens_in_interpreter(&brainfuck).output
+    );
+  }
+}
+
+#[test]
+pub fn reads_into_variables() {
+  let program = compile_structured("var x; read x; while x { print x; read x; }").unwrap();
+  let mut io = MockIo::with_input(b"echo");
+  run_program(&optimize(&program), &mut State::new(), &mut io).unwrap();
+  assert_eq!("echo", io.output);
+}
+
+#[test]
+pub fn errors_have_locations() {
+  let errors = [
+    (
+      "var x = 256;",
+      "Numbers go from 0 to 255 at line 1, column 9",
+    ),
+    (
+      "var x;\nx += y;",
+      "Undefined variable y at line 2, column 6",
+    ),
+    ("y = 1; var y;", "Undefined variable y at line 1, column 1"),
+    (
+      "var x; var x;",
+      "x is already declared at line 1, column 12",
+    ),
+    (
+      "var x = 'ab';",
+      "Characters must be a single byte at line 1, column 9",
+    ),
+    ("print \"a\\q\";", "Unknown escape at line 1, column 9"),
+    ("print \"a", "Unterminated literal at line 1, column 7"),
+    ("var x\nprint x;", "Expected ; at line 2, column 1"),
+    ("var x; while x print x;", "Expected { at line 1, column 16"),
+    ("var x; if x { print x;", "Expected } at line 1, column 23"),
+    ("var x; x * 2;", "Unexpected * at line 1, column 10"),
+    ("var x; x 2;", "Expected =, += or -= at line 1, column 10"),
+    ("print var;", "Expected a value at line 1, column 7"),
+    ("var while;", "Expected a variable at line 1, column 5"),
+    ("var x; ;", "Expected a statement at line 1, column 8"),
+    ("else { }", "else without if at line 1, column 1"),
+  ];
+
+  for &(source, message) in &errors {
+    assert_eq!(message, compile_structured(source).unwrap_err().to_string());
+  }
+}
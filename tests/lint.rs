@@ -0,0 +1,108 @@
+extern crate bfcrs;
+
+use bfcrs::lint::{lint_program, Lint};
+use bfcrs::parser::Extensions;
+
+fn lint(source: &str) -> Vec<Lint> {
+  lint_program(source, Extensions::default()).unwrap()
+}
+
+fn names(source: &str) -> Vec<&'static str> {
+  lint(source).iter().map(|lint| lint.name).collect()
+}
+
+// The code each lint is about.
+fn spans(source: &str) -> Vec<&str> {
+  lint(source)
+    .iter()
+    .map(|lint| &source[lint.start..lint.end])
+    .collect()
+}
+
+#[test]
+pub fn clean_programs_have_no_lints() {
+  assert!(lint("").is_empty());
+  assert!(lint("++[->+<]>.").is_empty());
+  assert!(lint(",[.,]").is_empty());
+  assert!(lint("+[>+<-]>[-]").is_empty());
+}
+
+#[test]
+pub fn loops_on_zero_cells_never_run() {
+  assert_eq!(vec!["dead-loop"], names("[.]+"));
+  assert_eq!(vec!["[.]"], spans("[.]+"));
+  // The cell a loop leaves is always 0.
+  assert_eq!(vec!["[>]"], spans("+[-]>+<[>]"));
+  // Other cells keep their values.
+  assert!(lint("+>+[-]<[-]").is_empty());
+  assert!(lint(",[-.]").is_empty());
+}
+
+#[test]
+pub fn clearing_a_zero_cell_is_redundant() {
+  assert_eq!(vec!["redundant-clear"], names("[-]+."));
+  assert_eq!(vec!["redundant-clear"], names("+[-][-]"));
+  assert_eq!(vec!["[+]"], spans("+[-]>[+]"));
+}
+
+#[test]
+pub fn code_after_endless_loops_is_unreachable() {
+  let lints = lint("+[]>.<");
+  assert_eq!(vec!["endless-loop", "unreachable-code"], names("+[]>.<"));
+  assert_eq!((3, 6), (lints[1].start, lints[1].end));
+  // The body doesn't change the cell it checks.
+  assert_eq!(vec!["[>+.<]", ">.<"], spans("+[>+.<]>.<"));
+  // Inside a loop only the rest of its body is unreachable.
+  assert_eq!(vec!["[>[-]+[]<]", "[]", "<"], spans(",[>[-]+[]<]."));
+  // A read can change the cell.
+  assert!(lint("+[,]>.").is_empty());
+}
+
+#[test]
+pub fn cancelling_pairs_are_found() {
+  assert_eq!(vec!["+-", "><"], spans("+-.><>"));
+  assert_eq!(vec!["cancelling-commands"; 2], names("+-.><>"));
+  // Comments between the commands don't hide the pair.
+  assert_eq!(vec!["+ -"], spans("+ -"));
+  assert_eq!(1, lint("++--").len());
+}
+
+#[test]
+pub fn moving_loops_inside_loops_are_reported() {
+  assert_eq!(vec!["unbalanced-loop"], names(",[[->+>]<]"));
+  assert_eq!(vec!["[->+>]"], spans(",[[->+>]<]"));
+  // Scans only move, and balanced loops can use offsets.
+  assert!(lint(",[[>]<]").is_empty());
+  assert!(lint(",[>[->+<]<-]").is_empty());
+  // Outside loops it only runs once.
+  assert!(lint(",[->+>]").is_empty());
+}
+
+#[test]
+pub fn moving_left_of_cell_0_is_reported() {
+  assert_eq!(vec!["negative-pointer"], names("+<+"));
+  assert_eq!(
+    vec![3],
+    lint(">+<<").iter().map(|x| x.start).collect::<Vec<_>>()
+  );
+  assert!(lint(">+<").is_empty());
+  // After a scan the pointer could be anywhere.
+  assert!(lint(">+[>]<<").is_empty());
+}
+
+#[test]
+pub fn lints_are_sorted_by_position() {
+  let lints = lint("<+-[-]");
+  let starts = lints.iter().map(|lint| lint.start).collect::<Vec<_>>();
+  assert_eq!(vec![0, 1, 3], starts);
+  assert_eq!(
+    vec!["negative-pointer", "cancelling-commands", "redundant-clear"],
+    names("<+-[-]")
+  );
+}
+
+#[test]
+pub fn unbalanced_brackets_are_errors() {
+  assert!(lint_program("[", Extensions::default()).is_err());
+  assert!(lint_program("]", Extensions::default()).is_err());
+}
@@ -0,0 +1,69 @@
+extern crate bfcrs;
+
+use std::env::temp_dir;
+use std::fs::{read, read_to_string, remove_file, write};
+use std::io::{ErrorKind, Write};
+use std::process::{Command, Stdio};
+
+use bfcrs::backend::llvm::LlvmBackend;
+use bfcrs::backend::Backend;
+use bfcrs::optimizer::optimize_parsed;
+use bfcrs::parser::parse_program;
+
+fn compile(source: &str) -> String {
+  let mut code = Vec::new();
+  LlvmBackend.compile_to_stream(&optimize_parsed(&parse_program(source)), &mut code);
+  String::from_utf8(code).unwrap()
+}
+
+// Runs the program with lli, or returns None when it isn't installed.
+fn run_with_lli(name: &str, source: &str, input: &[u8]) -> Option<Vec<u8>> {
+  let path = temp_dir().join(format!("bfcrs-llvm-test-{}.ll", name));
+  write(&path, compile(source)).unwrap();
+
+  let mut child = match Command::new("lli")
+    .arg(&path)
+    .stdin(Stdio::piped())
+    .stdout(Stdio::piped())
+    .spawn()
+  {
+    Err(ref error) if error.kind() == ErrorKind::NotFound => return None,
+    other => other.unwrap(),
+  };
+
+  child.stdin.take().unwrap().write_all(input).unwrap();
+  let result = child.wait_with_output().unwrap();
+  remove_file(&path).unwrap();
+
+  assert!(result.status.success(), "lli failed on {}", name);
+  Some(result.stdout)
+}
+
+#[test]
+pub fn module_defines_main() {
+  let code = compile("+.");
+
+  assert!(code.starts_with("@tape = internal global [65536 x i8] zeroinitializer\n"));
+  assert!(code.contains("declare i32 @putchar(i32)\n"));
+  assert!(code.contains("define i32 @main() {\n"));
+  assert!(code.ends_with("  ret i32 0\n}\n"));
+}
+
+#[test]
+pub fn hello_world_output() {
+  let source = read_to_string("bf/hello.bf").unwrap();
+
+  if let Some(output) = run_with_lli("hello", &source, &[]) {
+    assert_eq!(read("bf/hello.bf.out").unwrap(), output);
+  }
+}
+
+#[test]
+pub fn input_is_read() {
+  let source = read_to_string("bf/reverse.bf").unwrap();
+  let input = read("bf/reverse.bf.in").unwrap();
+
+  if let Some(output) = run_with_lli("reverse", &source, &input) {
+    assert_eq!(read("bf/reverse.bf.out").unwrap(), output);
+  }
+}
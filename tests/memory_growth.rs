@@ -0,0 +1,96 @@
+extern crate bfcrs;
+
+use bfcrs::backend::wasm::decoder::decode_module;
+use bfcrs::backend::wasm::executor::{run_wasi, run_wasm};
+use bfcrs::backend::wasm::{WasmModule, WasmOptions, WasmTarget};
+use bfcrs::compile_tokens_with_options;
+use bfcrs::types::{MemoryOp, ProgramToken};
+
+mod common;
+use common::types::MockIo;
+
+fn growable(target: WasmTarget, max_pages: u32) -> WasmOptions {
+  WasmOptions {
+    target,
+    max_pages: Some(max_pages),
+  }
+}
+
+// Prints "A" from a cell on the third page.
+fn far_right_program() -> Vec<ProgramToken> {
+  vec![
+    ProgramToken::ChangeAddr(140000),
+    ProgramToken::set_value(65),
+    ProgramToken::Offset(0, MemoryOp::Print),
+  ]
+}
+
+#[test]
+pub fn declares_maximum() {
+  let module = decode_module(&compile_tokens_with_options(
+    &[],
+    growable(WasmTarget::Bfcrs, 16),
+  )).unwrap();
+
+  assert_eq!(1, module.memories[0].initial);
+  assert_eq!(Some(16), module.memories[0].maximum);
+}
+
+#[test]
+pub fn fixed_memory_traps() {
+  let code = compile_tokens_with_options(&far_right_program(), WasmOptions::new(WasmTarget::Bfcrs));
+  assert!(run_wasm(&code, &mut MockIo::new()).is_err());
+}
+
+#[test]
+pub fn grows_when_moving_right() {
+  let code = compile_tokens_with_options(&far_right_program(), growable(WasmTarget::Bfcrs, 16));
+
+  let mut io = MockIo::new();
+  let state = run_wasm(&code, &mut io).unwrap();
+
+  assert_eq!("A", io.output);
+  assert_eq!(140000, state.pointer);
+  assert_eq!(3 * 65536, state.memory.len());
+}
+
+#[test]
+pub fn grows_for_offsets() {
+  // The pointer never moves, but the offset is past the initial page.
+  let code = compile_tokens_with_options(
+    &[
+      ProgramToken::offs_set_value(70000, 66),
+      ProgramToken::Offset(70000, MemoryOp::Print),
+    ],
+    growable(WasmTarget::Bfcrs, 16),
+  );
+
+  let mut io = MockIo::new();
+  run_wasm(&code, &mut io).unwrap();
+  assert_eq!("B", io.output);
+}
+
+#[test]
+pub fn respects_maximum() {
+  let code = compile_tokens_with_options(&far_right_program(), growable(WasmTarget::Bfcrs, 2));
+  assert!(run_wasm(&code, &mut MockIo::new()).is_err());
+}
+
+#[test]
+pub fn grows_on_wasi() {
+  let code = compile_tokens_with_options(&far_right_program(), growable(WasmTarget::Wasi, 16));
+
+  let mut io = MockIo::new();
+  let memory = run_wasi(&code, &mut io).unwrap();
+
+  assert_eq!("A", io.output);
+  // The WASI tape starts on the second page.
+  assert_eq!(4 * 65536, memory.len());
+}
+
+#[test]
+pub fn maximum_below_initial_size_is_rejected() {
+  let mut code = Vec::new();
+  let result = WasmModule::write_with_options(&mut code, &[], &growable(WasmTarget::Wasi, 1));
+  assert!(result.is_err());
+}
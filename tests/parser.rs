@@ -0,0 +1,54 @@
+extern crate bfcrs;
+
+use bfcrs::parser::{
+  parse_program, parse_program_checked, parse_program_with_offsets, parse_source,
+};
+use bfcrs::types::{ParseToken, SourceItem};
+
+#[test]
+pub fn offsets_skip_comments() {
+  let offsets = parse_program_with_offsets("a+ [b]")
+    .into_iter()
+    .map(|(offset, _)| offset)
+    .collect::<Vec<_>>();
+
+  assert_eq!(vec![1, 3, 5], offsets);
+}
+
+#[test]
+pub fn source_keeps_comments() {
+  assert_eq!(
+    vec![
+      (0, SourceItem::Comment("a".to_string())),
+      (1, SourceItem::Token(ParseToken::IncrValue)),
+      (2, SourceItem::Comment(" é ".to_string())),
+      (6, SourceItem::Token(ParseToken::Read)),
+    ],
+    parse_source("a+ é ,")
+  );
+}
+
+#[test]
+pub fn checked_parse_accepts_balanced_programs() {
+  let source = "+[->+<]comment.";
+  assert_eq!(
+    parse_program(source),
+    parse_program_checked(source).unwrap()
+  );
+  assert_eq!(
+    ParseToken::Print,
+    *parse_program_checked(source).unwrap().last().unwrap()
+  );
+}
+
+#[test]
+pub fn unmatched_loop_end() {
+  let error = parse_program_checked("+\n-]").unwrap_err();
+  assert_eq!("Unmatched ] at line 2, column 2", error.to_string());
+}
+
+#[test]
+pub fn unclosed_loop_start() {
+  let error = parse_program_checked("[[-]\n").unwrap_err();
+  assert_eq!("Unclosed [ at line 1, column 1", error.to_string());
+}
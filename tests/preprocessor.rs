@@ -0,0 +1,171 @@
+extern crate bfcrs;
+
+use std::collections::HashMap;
+use std::error::Error;
+
+use bfcrs::backend::wasm::decoder::decode_module;
+use bfcrs::backend::wasm::{WasmOptions, WasmTarget};
+use bfcrs::optimizer::convert_tokens;
+use bfcrs::parser::{parse_program, parse_program_checked_located, Extensions};
+use bfcrs::preprocessor::{expand_macros, Expansion};
+use bfcrs::{compile_expansion_with_debug_info, program_token_offsets};
+
+fn expand_with_files(source: &str, files: &[(&str, &str)]) -> Result<Expansion, Box<Error>> {
+  let files = files
+    .iter()
+    .map(|&(path, text)| (path.to_string(), text.to_string()))
+    .collect::<HashMap<_, _>>();
+
+  expand_macros(source, "main.bfm", |path| {
+    Ok(
+      files
+        .get(path)
+        .cloned()
+        .ok_or_else(|| format!("No file called {}", path))?,
+    )
+  })
+}
+
+fn expand(source: &str) -> Result<Expansion, Box<Error>> {
+  expand_with_files(source, &[])
+}
+
+#[test]
+pub fn expands_macros_and_repetitions() {
+  assert_eq!(
+    "\n+->+-> \n",
+    expand("%define x +-\n{%x>}*2 # a comment with .\n")
+      .unwrap()
+      .program
+  );
+  assert_eq!("[-]", expand("{{-}*0[-]}*1").unwrap().program);
+  // Macros are expanded where they're used, so they can use macros defined after them.
+  assert_eq!(
+    "\n\n>>",
+    expand("%define b %a%a\n%define a >\n%b").unwrap().program
+  );
+}
+
+#[test]
+pub fn origins_point_at_the_macro_source() {
+  let expansion = expand("%define a >.\n{+}*2%a").unwrap();
+  assert_eq!("\n++>.", expansion.program);
+
+  // Both copies of the + come from the same place.
+  assert_eq!(14, expansion.origins[1].offset);
+  assert_eq!(14, expansion.origins[2].offset);
+  assert_eq!("line 2, column 2", expansion.locate(2));
+
+  // The . comes from the definition, and was expanded at the %a.
+  assert_eq!("line 1, column 12", expansion.locate(4));
+  assert_eq!(18, expansion.root_offset(4));
+}
+
+#[test]
+pub fn includes_are_relative_to_the_including_file() {
+  let expansion = expand_with_files(
+    "%include \"lib/a.bfm\" %b",
+    &[
+      ("lib/a.bfm", "%include \"b.bfm\"\n%define b ."),
+      ("lib/b.bfm", "+"),
+    ],
+  ).unwrap();
+
+  assert_eq!("+\n .", expansion.program);
+  assert_eq!("line 1, column 1 of lib/b.bfm", expansion.locate(0));
+  assert_eq!(0, expansion.root_offset(0));
+  assert_eq!("line 2, column 11 of lib/a.bfm", expansion.locate(3));
+  assert_eq!(21, expansion.root_offset(3));
+}
+
+#[test]
+pub fn errors_point_at_the_macro_source() {
+  let errors = [
+    ("+%", "Expected a macro name after % at line 1, column 2"),
+    ("%a", "Undefined macro a at line 1, column 1"),
+    (
+      "%define\n",
+      "Expected a macro name after %define at line 1, column 1",
+    ),
+    (
+      "%define a +\n%define a -",
+      "Macro a is already defined at line 2, column 9",
+    ),
+    (
+      "%define include +",
+      "%include can't be redefined at line 1, column 9",
+    ),
+    (
+      "%define a %a\n%a",
+      "Macro a expands itself at line 1, column 11",
+    ),
+    ("{+", "Unclosed { at line 1, column 1"),
+    ("+}*2", "Unmatched } at line 1, column 2"),
+    ("{+}", "Expected *<count> after } at line 1, column 3"),
+    ("{+}*", "Expected *<count> after } at line 1, column 3"),
+    (
+      "{{+}*100000}*100000",
+      "The program grows past 67108864 bytes at line 1, column 12",
+    ),
+    (
+      "%include lib.bfm",
+      "Expected a quoted path after %include at line 1, column 1",
+    ),
+    ("%include \"lib.bfm\"", "No file called lib.bfm"),
+    (
+      "%include \"main.bfm\"",
+      "main.bfm includes itself at line 1, column 1",
+    ),
+  ];
+
+  for &(source, message) in &errors {
+    assert_eq!(message, expand(source).err().unwrap().to_string());
+  }
+
+  let error = expand_with_files("\n%include \"a.bfm\"", &[("a.bfm", "{")])
+    .err().unwrap();
+  assert_eq!("Unclosed { at line 1, column 1 of a.bfm", error.to_string());
+}
+
+#[test]
+pub fn parse_errors_point_at_the_macro_source() {
+  let expansion = expand_with_files(
+    "%include \"loops.bfm\"\n%close %close",
+    &[("loops.bfm", "%define close ]")],
+  ).unwrap();
+
+  let error = parse_program_checked_located(&expansion.program, Extensions::default(), |offset| {
+    expansion.locate(offset)
+  })
+  .unwrap_err();
+  assert_eq!(
+    "Unmatched ] at line 1, column 15 of loops.bfm",
+    error.to_string()
+  );
+}
+
+#[test]
+pub fn debug_info_maps_to_the_root_file() {
+  let source = "%define clear [-]\n+%clear.";
+  let expansion = expand(source).unwrap();
+  let program = convert_tokens(&parse_program(&expansion.program));
+  let module = decode_module(&compile_expansion_with_debug_info(
+    &program,
+    Some(program_token_offsets(&expansion.program)),
+    &expansion,
+    WasmOptions::new(WasmTarget::Bfcrs),
+    true,
+  )).unwrap();
+
+  let source_info = module.source_info.unwrap();
+  assert_eq!(source.len() as u32, source_info.source_length);
+  assert_eq!(Some(source.to_string()), source_info.source);
+
+  // Every token of [-] was expanded at the %clear.
+  let source_offsets = source_info
+    .source_map
+    .iter()
+    .map(|(_, source_offset)| *source_offset)
+    .collect::<Vec<_>>();
+  assert_eq!(vec![18, 19, 19, 25], source_offsets);
+}
@@ -0,0 +1,241 @@
+extern crate bfcrs;
+
+use bfcrs::backend::bf::BfBackend;
+use bfcrs::backend::c::CBackend;
+use bfcrs::backend::llvm::LlvmBackend;
+use bfcrs::backend::wasm::executor::{run_wasi, run_wasm};
+use bfcrs::backend::wasm::wat::WatBackend;
+use bfcrs::backend::wasm::{WasmOptions, WasmTarget};
+use bfcrs::backend::Backend;
+use bfcrs::dialect::Dialect;
+use bfcrs::formatter::{format_source, FormatOptions, FormatStyle};
+use bfcrs::interpreter::{run_program, RunError, MAX_CALL_DEPTH};
+use bfcrs::optimizer::{convert_tokens, optimize};
+use bfcrs::parser::{parse_program, parse_program_checked_extended};
+use bfcrs::types::{MemoryOp, ProgramToken, State};
+use bfcrs::types::ProgramToken::*;
+use bfcrs::{compile_tokens, compile_tokens_with_options};
+
+mod common;
+use common::interpreter_util::run_tokens_in_interpreter;
+use common::types::MockIo;
+use common::wasm_util::run_wasm_in_executor;
+
+// Procedure 1 prints cell 1 and increments it, and cell 1 starts at 'A'.
+const ABC: &str = "+(>.+<)>>++++++++[<++++++++>-]<+<:::";
+
+// Calling procedure 1 defines procedure 2, which is then redefined.
+const NESTED: &str = ">>+++++++++++[<+++>-]<< +(+(>.<)-) : +: (>+.-<):";
+
+// Procedure 1 prints cell 2 and calls itself until cell 1 counts down to 0.
+const RECURSIVE: &str = "+ >++++++++++[>++++++<-]>+++++ <+++++ < (>>.<[-<:>]<) :";
+
+fn pbrain(source: &str) -> Vec<ProgramToken> {
+  optimize(&convert_tokens(&Dialect::pbrain().parse(source)))
+}
+
+fn run_in_wasi(program: &[ProgramToken]) -> String {
+  let code = compile_tokens_with_options(program, WasmOptions::new(WasmTarget::Wasi));
+  let mut io = MockIo::new();
+  run_wasi(&code, &mut io).unwrap();
+  io.output
+}
+
+#[test]
+pub fn procedures_are_only_parsed_in_pbrain() {
+  assert_eq!(
+    vec![Loop(vec![]), Offset(0, MemoryOp::Print)],
+    convert_tokens(&parse_program("([]:.)"))
+  );
+  assert_eq!(
+    vec![Procedure(vec![Call]), Call],
+    convert_tokens(&Dialect::pbrain().parse("(:):"))
+  );
+}
+
+#[test]
+pub fn brackets_must_match() {
+  let extensions = Dialect::pbrain().extensions;
+  let errors = [
+    ("+(", "Unclosed ( at line 1, column 2"),
+    (")", "Unmatched ) at line 1, column 1"),
+    (
+      "(\n]",
+      "] at line 2, column 1 closes the ( at line 1, column 1",
+    ),
+    (
+      "[)",
+      ") at line 1, column 2 closes the [ at line 1, column 1",
+    ),
+  ];
+
+  for &(source, message) in &errors {
+    assert_eq!(
+      message,
+      parse_program_checked_extended(source, extensions)
+        .unwrap_err()
+        .to_string()
+    );
+  }
+}
+
+#[test]
+pub fn moves_are_flushed_before_procedures() {
+  assert_eq!(
+    vec![
+      ChangeAddr(2),
+      Procedure(vec![Offset(1, MemoryOp::Print)]),
+      Call
+    ],
+    pbrain(">>(>.<):")
+  );
+}
+
+#[test]
+pub fn interpreter_runs_procedures() {
+  assert_eq!("ABC", run_tokens_in_interpreter(&pbrain(ABC)).output);
+  assert_eq!("!\"", run_tokens_in_interpreter(&pbrain(NESTED)).output);
+  assert_eq!(
+    "AAAAAA",
+    run_tokens_in_interpreter(&pbrain(RECURSIVE)).output
+  );
+}
+
+#[test]
+pub fn wasm_runs_procedures_like_the_interpreter() {
+  for source in &[ABC, NESTED, RECURSIVE] {
+    let program = pbrain(source);
+    let expected = run_tokens_in_interpreter(&program);
+    let actual = run_wasm_in_executor(&compile_tokens(&program, false));
+
+    assert_eq!(expected.output, actual.output);
+    assert_eq!(expected.state.pointer, actual.state.pointer);
+    // The procedure table comes after the tape.
+    assert!(expected.state.memory[..] == actual.state.memory[..expected.state.memory.len()]);
+
+    assert_eq!(expected.output, run_in_wasi(&program));
+  }
+}
+
+#[test]
+pub fn wasm_dispatches_through_a_branch_table() {
+  let mut wat = Vec::new();
+  WatBackend.compile_to_stream(&pbrain(NESTED), &mut wat);
+  let wat = String::from_utf8(wat).unwrap();
+
+  assert!(wat.contains("br_table 0 1 2 3 0"));
+  assert!(wat.contains("(func $procedure_2 (;6;) (type 3) (param $pointer i32) (result i32)"));
+}
+
+#[test]
+pub fn round_trips_keep_procedures() {
+  for source in &[ABC, NESTED, RECURSIVE] {
+    let program = pbrain(source);
+    let mut code = Vec::new();
+    BfBackend.compile_to_stream(&program, &mut code);
+
+    assert_eq!(
+      program,
+      pbrain(&String::from_utf8(code).unwrap()),
+      "{}",
+      source
+    );
+  }
+}
+
+#[test]
+pub fn procedures_are_formatted_like_loops() {
+  let mut options = FormatOptions::new(FormatStyle::Pretty);
+  options.extensions = Dialect::pbrain().extensions;
+
+  assert_eq!(
+    "+\n(\n  >.[-]\n)\n:\n",
+    format_source("+(>.[-]):", options).unwrap()
+  );
+  assert_eq!("(+):\n", format_source("(+) :", options).unwrap());
+}
+
+fn run_error(source: &str) -> RunError {
+  run_program(&pbrain(source), &mut State::new(), &mut MockIo::new()).unwrap_err()
+}
+
+#[test]
+pub fn interpreter_rejects_undefined_procedures() {
+  assert_eq!(RunError::UndefinedProcedure { number: 0 }, run_error(":"));
+  assert_eq!(
+    "Procedure 0 isn't defined.",
+    RunError::UndefinedProcedure { number: 0 }.to_string()
+  );
+}
+
+#[test]
+pub fn interpreter_stops_runaway_recursion() {
+  assert_eq!(RunError::RecursionTooDeep, run_error("(:):"));
+}
+
+#[test]
+pub fn wasm_traps_on_undefined_procedures_and_runaway_recursion() {
+  for source in &[":", "+(-:+):", "(:):"] {
+    let code = compile_tokens(&pbrain(source), false);
+    assert!(run_wasm(&code, &mut MockIo::new()).is_err(), "{}", source);
+  }
+}
+
+#[test]
+pub fn wasm_traps_when_running_off_the_tape() {
+  // The procedure table comes right after the tape, and mustn't be overwritten.
+  let walks = [">".repeat(65536) + "+", ">".repeat(65535) + ">+<"];
+
+  for walk in &walks {
+    for definitions in &["", "+(-)-"] {
+      let code = compile_tokens(&pbrain(&format!("{}{}", definitions, walk)), false);
+      assert!(run_wasm(&code, &mut MockIo::new()).is_err(), "{}", definitions);
+    }
+  }
+}
+
+#[test]
+pub fn wasi_traps_when_running_in_front_of_the_tape() {
+  // The output buffer and the procedure table come right before the tape.
+  for source in &["+(-)-<+", "+(-)-<+>", "+(<-):"] {
+    let code = compile_tokens_with_options(&pbrain(source), WasmOptions::new(WasmTarget::Wasi));
+    assert!(run_wasi(&code, &mut MockIo::new()).is_err(), "{}", source);
+  }
+}
+
+#[test]
+pub fn other_backends_return_an_error() {
+  let program = pbrain(ABC);
+  let backends: [&Backend; 2] = [&CBackend, &LlvmBackend];
+
+  for backend in &backends {
+    let error = backend.compile(&program, &mut Vec::new()).unwrap_err();
+    assert_eq!("The target doesn't support procedures.", error.to_string());
+  }
+
+  assert!(BfBackend.compile(&program, &mut Vec::new()).is_ok());
+}
+
+// Procedure 1 moves right and calls itself while the cell there is 1, so it's nested once for
+// every cell.
+fn nested_calls(depth: usize) -> Vec<ProgramToken> {
+  pbrain(&format!(
+    "+(>[:-]<){}{}:",
+    ">+".repeat(depth - 1),
+    "<".repeat(depth - 1)
+  ))
+}
+
+#[test]
+pub fn engines_nest_procedures_equally_deep() {
+  let program = nested_calls(MAX_CALL_DEPTH);
+  assert_eq!(Ok(()), run_program(&program, &mut State::new(), &mut MockIo::new()));
+  assert!(run_wasm(&compile_tokens(&program, false), &mut MockIo::new()).is_ok());
+
+  let program = nested_calls(MAX_CALL_DEPTH + 1);
+  assert_eq!(
+    Err(RunError::RecursionTooDeep),
+    run_program(&program, &mut State::new(), &mut MockIo::new())
+  );
+  assert!(run_wasm(&compile_tokens(&program, false), &mut MockIo::new()).is_err());
+}
@@ -1,12 +1,12 @@
 extern crate bfcrs;
 
 mod common;
-use common::node_bridge::run_bf_in_node;
+use common::wasm_util::run_bf_in_wasm;
 use common::test_util::run_and_expect_same;
 
 #[test]
 pub fn hello_world_wasm_output() {
-  let result = run_bf_in_node(include_str!("../bf/hello.bf"));
+  let result = run_bf_in_wasm(include_str!("../bf/hello.bf"));
   assert_eq!(include_str!("../bf/hello.bf.out"), result.output);
 }
 
@@ -17,7 +17,7 @@ pub fn hello_world_same() {
 
 #[test]
 pub fn sierpinski_wasm_output() {
-  let result = run_bf_in_node(include_str!("../bf/sierpinski.bf"));
+  let result = run_bf_in_wasm(include_str!("../bf/sierpinski.bf"));
   assert_eq!(include_str!("../bf/sierpinski.bf.out"), result.output);
 }
 
@@ -28,6 +28,6 @@ pub fn sierpinski_same() {
 
 #[test]
 pub fn mandelbrot_wasm_output() {
-  let result = run_bf_in_node(include_str!("../bf/mandelbrot.bf"));
+  let result = run_bf_in_wasm(include_str!("../bf/mandelbrot.bf"));
   assert_eq!(include_str!("../bf/mandelbrot.bf.out"), result.output);
 }
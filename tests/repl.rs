@@ -0,0 +1,140 @@
+extern crate bfcrs;
+
+use bfcrs::repl::Repl;
+
+mod common;
+use common::types::MockIo;
+
+// Feeds lines to the REPL, returning what it reported and what the programs printed.
+fn session(repl: &mut Repl, lines: &[&str]) -> (String, String) {
+  let mut io = MockIo::new();
+  let mut out = Vec::new();
+
+  for line in lines {
+    repl.handle_line(line, &mut io, &mut out).unwrap();
+  }
+
+  (String::from_utf8(out).unwrap(), io.output)
+}
+
+#[test]
+pub fn state_persists_between_lines() {
+  let mut repl = Repl::new();
+  let (out, _) = session(&mut repl, &["+++>", "++"]);
+
+  assert_eq!(
+    "pointer 1 | cells 0..6: 3 [0] 0 0 0 0\npointer 1 | cells 0..6: 3 [2] 0 0 0 0\n",
+    out
+  );
+  assert_eq!(1, repl.state.pointer);
+  assert_eq!(&[3, 2], &repl.state.memory[..2]);
+}
+
+#[test]
+pub fn unclosed_loops_continue_on_the_next_line() {
+  let mut repl = Repl::new();
+  let mut io = MockIo::new();
+  let mut out = Vec::new();
+
+  repl.handle_line("+++[>++", &mut io, &mut out).unwrap();
+  assert_eq!("..> ", repl.prompt());
+  assert!(out.is_empty());
+
+  repl.handle_line("<-]", &mut io, &mut out).unwrap();
+  assert_eq!("bf> ", repl.prompt());
+  assert_eq!(&[0, 6], &repl.state.memory[..2]);
+}
+
+#[test]
+pub fn output_ends_with_a_newline() {
+  let mut repl = Repl::new();
+  let (out, printed) = session(&mut repl, &["++++++++[>++++++++<-]>+."]);
+
+  assert_eq!("A", printed);
+  assert!(out.starts_with("\npointer 1"));
+}
+
+#[test]
+pub fn reset_clears_the_tape() {
+  let mut repl = Repl::new();
+  let (out, _) = session(&mut repl, &["+>+", ":reset"]);
+
+  assert!(out.ends_with("Tape cleared.\n"));
+  assert_eq!(0, repl.state.pointer);
+  assert!(repl.state.memory.iter().all(|x| *x == 0));
+}
+
+#[test]
+pub fn dump_shows_a_range() {
+  let mut repl = Repl::new();
+  let (out, _) = session(&mut repl, &["+>++>+++", ":dump 1..3", ":dump 3..1"]);
+  let out = out.lines().skip(1).collect::<Vec<_>>();
+
+  assert_eq!(
+    vec!["    1:   2   3", "error: Expected a range like 0..32"],
+    out
+  );
+}
+
+#[test]
+pub fn dump_defaults_to_two_rows() {
+  let mut repl = Repl::new();
+  let (out, _) = session(&mut repl, &[":dump"]);
+  let rows = out.lines().collect::<Vec<_>>();
+
+  assert_eq!(2, rows.len());
+  assert!(rows[1].starts_with("   16:"));
+}
+
+#[test]
+pub fn ir_shows_the_last_program() {
+  let mut repl = Repl::new();
+  let (out, _) = session(&mut repl, &["[-]+", ":ir"]);
+
+  assert_eq!(Some("[Offset(0, SetValue(1))]"), out.lines().last());
+}
+
+#[test]
+pub fn load_runs_a_file() {
+  let mut repl = Repl::new();
+  let (_, printed) = session(&mut repl, &[":load bf/hello.bf"]);
+
+  assert_eq!(include_str!("../bf/hello.bf.out"), printed);
+}
+
+#[test]
+pub fn errors_dont_end_the_session() {
+  let mut repl = Repl::new();
+  let (out, _) = session(
+    &mut repl,
+    &["+]", ":load does/not/exist.bf", ":frobnicate", "+"],
+  );
+  let out = out.lines().collect::<Vec<_>>();
+
+  assert_eq!("error: Unmatched ] at line 1, column 2", out[0]);
+  assert!(out[1].starts_with("error: Couldn't read does/not/exist.bf"));
+  assert_eq!("error: Unknown command :frobnicate. Try :help", out[2]);
+  assert_eq!("pointer 0 | cells 0..5: [1] 0 0 0 0", out[3]);
+}
+
+#[test]
+pub fn crashes_are_reported() {
+  let mut repl = Repl::new();
+  let (out, _) = session(&mut repl, &["<+", ":reset", "+"]);
+
+  let out = out.lines().collect::<Vec<_>>();
+
+  assert_eq!("error: Cell -1 is left of the start of the tape.", out[0]);
+  assert_eq!("pointer 0 | cells 0..5: [1] 0 0 0 0", out[out.len() - 1]);
+}
+
+#[test]
+pub fn quit_ends_the_session() {
+  let mut repl = Repl::new();
+  let mut out = Vec::new();
+
+  assert!(repl.handle_line("+", &mut MockIo::new(), &mut out).unwrap());
+  assert!(!repl
+    .handle_line(":quit", &mut MockIo::new(), &mut out)
+    .unwrap());
+}
@@ -0,0 +1,127 @@
+extern crate bfcrs;
+
+use bfcrs::interpreter::{run_program, RunError};
+use bfcrs::optimizer::optimize_parsed;
+use bfcrs::parser::parse_program;
+use bfcrs::tape::{BidirectionalTape, GrowableTape, SparseTape, Tape};
+use bfcrs::types::{ProgramToken, State};
+
+mod common;
+use common::types::MockIo;
+
+fn run_on(tape: &mut Tape, source: &str) -> String {
+  let program = optimize_parsed(&parse_program(source));
+  let mut io = MockIo::new();
+  run_program(&program, tape, &mut io).unwrap();
+  io.output
+}
+
+#[test]
+pub fn every_tape_runs_hello_world() {
+  for name in &["fixed", "growable", "bidirectional", "sparse"] {
+    let mut tape = Tape::from_name(name).unwrap();
+    assert_eq!(
+      include_str!("../bf/hello.bf.out"),
+      run_on(&mut *tape, include_str!("../bf/hello.bf")),
+      "Tape: {}",
+      name
+    );
+  }
+}
+
+#[test]
+pub fn unknown_tape() {
+  assert!(Tape::from_name("circular").is_none());
+}
+
+fn run_error(tape: &mut Tape, source: &str) -> RunError {
+  let program = optimize_parsed(&parse_program(source));
+  run_program(&program, tape, &mut MockIo::new()).unwrap_err()
+}
+
+#[test]
+pub fn fixed_tape_has_no_negative_cells() {
+  assert_eq!(
+    RunError::PointerUnderflow { cell: -1 },
+    run_error(&mut State::new(), "<+")
+  );
+  assert_eq!(
+    "Cell -1 is left of the start of the tape.",
+    RunError::PointerUnderflow { cell: -1 }.to_string()
+  );
+}
+
+#[test]
+pub fn fixed_tape_ends() {
+  let mut tape = State::new();
+  tape.pointer = 65535;
+
+  assert_eq!(
+    RunError::PointerOverflow { cell: 65536 },
+    run_error(&mut tape, ">[-]")
+  );
+}
+
+#[test]
+pub fn growable_tape_has_no_negative_cells() {
+  assert_eq!(
+    RunError::PointerUnderflow { cell: -2 },
+    run_error(&mut GrowableTape::new(), "<<.")
+  );
+}
+
+#[test]
+pub fn growable_tape_grows() {
+  let mut tape = GrowableTape::new();
+  let mut io = MockIo::new();
+
+  run_program(
+    &[
+      ProgramToken::ChangeAddr(100000),
+      ProgramToken::change_value(3),
+    ],
+    &mut tape,
+    &mut io,
+  )
+  .unwrap();
+
+  assert_eq!(100001, tape.memory.len());
+  assert_eq!(Ok(3), tape.get(100000));
+  // Reading past the end doesn't grow the tape.
+  assert_eq!(Ok(0), tape.get(200000));
+  assert_eq!(100001, tape.memory.len());
+}
+
+#[test]
+pub fn bidirectional_tape_goes_left() {
+  let mut tape = BidirectionalTape::new();
+  let output = run_on(&mut tape, "<<++++++++[>++++++++<-]>+.");
+
+  assert_eq!("A", output);
+  assert_eq!(-1, tape.pointer());
+  assert_eq!(Ok(65), tape.get(-1));
+  assert_eq!(Ok(0), tape.get(-2));
+  assert_eq!(Ok(0), tape.get(5));
+}
+
+#[test]
+pub fn sparse_tape_only_stores_nonzero_cells() {
+  let mut tape = SparseTape::new();
+  let mut io = MockIo::new();
+
+  run_program(
+    &[
+      ProgramToken::offs_change_value(-1000000, 1),
+      ProgramToken::offs_change_value(1000000, 2),
+      ProgramToken::change_value(1),
+      ProgramToken::set_value(0),
+    ],
+    &mut tape,
+    &mut io,
+  )
+  .unwrap();
+
+  assert_eq!(2, tape.cells.len());
+  assert_eq!(Ok(1), tape.get(-1000000));
+  assert_eq!(Ok(2), tape.get(1000000));
+}
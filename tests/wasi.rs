@@ -0,0 +1,75 @@
+extern crate bfcrs;
+
+use bfcrs::backend::wasm::decoder::{decode_module, ExternalKind};
+use bfcrs::backend::wasm::executor::run_wasi;
+use bfcrs::backend::wasm::WasiBackend;
+use bfcrs::backend::Backend;
+use bfcrs::optimizer::optimize_parsed;
+use bfcrs::parser::parse_program;
+
+mod common;
+use common::types::MockIo;
+
+fn compile_wasi(source: &str) -> Vec<u8> {
+  let program = optimize_parsed(&parse_program(source));
+  let mut code = Vec::new();
+  WasiBackend.compile_to_stream(&program, &mut code);
+  code
+}
+
+#[test]
+pub fn wasi_imports() {
+  let module = decode_module(&compile_wasi("+.")).unwrap();
+
+  let imports = module
+    .imports
+    .iter()
+    .map(|import| (&import.module[..], &import.field[..]))
+    .collect::<Vec<_>>();
+
+  assert_eq!(
+    vec![
+      ("wasi_snapshot_preview1", "fd_write"),
+      ("wasi_snapshot_preview1", "fd_read"),
+    ],
+    imports
+  );
+}
+
+#[test]
+pub fn wasi_exports() {
+  let module = decode_module(&compile_wasi("+.")).unwrap();
+
+  let start = module
+    .exports
+    .iter()
+    .find(|export| export.name == "_start")
+    .expect("_start should be exported.");
+  assert_eq!(ExternalKind::Function, start.kind);
+
+  // _start takes nothing and returns nothing.
+  let type_index = module.functions[start.index as usize - module.imports.len()];
+  let start_type = &module.types[type_index as usize];
+  assert!(start_type.params.is_empty());
+  assert!(start_type.results.is_empty());
+
+  assert!(module
+    .exports
+    .iter()
+    .any(|export| export.name == "memory" && export.kind == ExternalKind::Memory));
+}
+
+#[test]
+pub fn hello_world_wasi_output() {
+  let mut io = MockIo::new();
+  run_wasi(&compile_wasi(include_str!("../bf/hello.bf")), &mut io).unwrap();
+  assert_eq!(include_str!("../bf/hello.bf.out"), io.output);
+}
+
+#[test]
+pub fn sierpinski_wasi_output() {
+  // Large enough to fill the output buffer more than once.
+  let mut io = MockIo::new();
+  run_wasi(&compile_wasi(include_str!("../bf/sierpinski.bf")), &mut io).unwrap();
+  assert_eq!(include_str!("../bf/sierpinski.bf.out"), io.output);
+}
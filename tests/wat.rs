@@ -0,0 +1,160 @@
+extern crate bfcrs;
+
+use bfcrs::backend::wasm::code_stream::Instruction;
+use bfcrs::backend::wasm::decoder::decode_module;
+use bfcrs::backend::wasm::executor::run_wasm;
+use bfcrs::backend::wasm::wat::disassemble;
+use bfcrs::backend::wasm::{source_hash, WasmOptions, WasmTarget};
+use bfcrs::optimizer::{convert_tokens, optimize};
+use bfcrs::parser::parse_program;
+use bfcrs::{compile_program, compile_program_with_debug_info, program_token_offsets};
+
+mod common;
+use common::types::MockIo;
+
+// Optimized programs are compiled without token offsets, like build does.
+fn compile_with_debug_info(
+  source: &str,
+  options: WasmOptions,
+  optimize_program: bool,
+  embed_source: bool,
+) -> Vec<u8> {
+  let program = convert_tokens(&parse_program(source));
+
+  if optimize_program {
+    compile_program_with_debug_info(&optimize(&program), None, source, options, embed_source)
+  } else {
+    let offsets = Some(program_token_offsets(source));
+    compile_program_with_debug_info(&program, offsets, source, options, embed_source)
+  }
+}
+
+#[test]
+pub fn decode_smoke() {
+  let module = decode_module(&compile_program("+.")).unwrap();
+
+  assert_eq!(3, module.types.len());
+  assert_eq!(2, module.imports.len());
+  assert_eq!(1, module.bodies.len());
+  assert_eq!(Some(&Instruction::End), module.bodies[0].code.last());
+}
+
+#[test]
+pub fn disassemble_smoke() {
+  let mut output = Vec::new();
+  disassemble(&compile_program(">+[-]"), &mut output).unwrap();
+
+  let expected = r#"(module
+  (type (;0;) (func (param i32)))
+  (type (;1;) (func (result i32)))
+  (type (;2;) (func (result i32)))
+  (import "bfcrs" "print" (func $print (;0;) (type 0)))
+  (import "bfcrs" "read" (func $read (;1;) (type 1)))
+  (func $main (;2;) (type 2) (result i32)
+    (local $pointer i32)
+    local.get $pointer
+    i32.const 0
+    i32.store8 offset=1
+    local.get $pointer
+    i32.const 1
+    i32.add
+    local.set $pointer
+    local.get $pointer
+    return)
+  (memory (;0;) 1)
+  (export "main" (func $main))
+  (export "memory" (memory 0)))
+"#;
+
+  assert_eq!(expected, String::from_utf8(output).unwrap());
+}
+
+#[test]
+pub fn disassemble_rejects_garbage() {
+  let mut output = Vec::new();
+  assert!(disassemble(b"not wasm", &mut output).is_err());
+}
+
+#[test]
+pub fn name_section() {
+  let module = decode_module(&compile_program("+.")).unwrap();
+
+  assert_eq!("print", module.names.functions[&0]);
+  assert_eq!("main", module.names.functions[&2]);
+  assert_eq!("pointer", module.names.locals[&2][&0]);
+  assert!(module.source_info.is_none());
+}
+
+#[test]
+pub fn wasi_name_section() {
+  let module = decode_module(&compile_with_debug_info(
+    "+.",
+    WasmOptions::new(WasmTarget::Wasi),
+    true,
+    false,
+  )).unwrap();
+
+  assert_eq!("fd_write", module.names.functions[&0]);
+  assert_eq!("_start", module.names.functions[&4]);
+  assert_eq!("ch", module.names.locals[&2][&0]);
+}
+
+#[test]
+pub fn bfcrs_section() {
+  let source = "+ [-]\n.";
+  let module = decode_module(&compile_with_debug_info(
+    source,
+    WasmOptions::new(WasmTarget::Bfcrs),
+    false,
+    true,
+  )).unwrap();
+
+  let source_info = module.source_info.unwrap();
+  assert_eq!(source_hash(source), source_info.source_hash);
+  assert_eq!(source.len() as u32, source_info.source_length);
+  assert_eq!(Some(source.to_string()), source_info.source);
+  assert_eq!(2, source_info.entry_function);
+  assert!(source_info
+    .options
+    .contains(&("optimize".to_string(), "false".to_string())));
+
+  let source_offsets = source_info
+    .source_map
+    .iter()
+    .map(|(_, source_offset)| *source_offset)
+    .collect::<Vec<_>>();
+  assert_eq!(vec![0, 2, 3, 6], source_offsets);
+
+  // Every token emits at least one instruction, so the code offsets must be increasing.
+  let code_offsets = source_info
+    .source_map
+    .iter()
+    .map(|(code_offset, _)| *code_offset)
+    .collect::<Vec<_>>();
+  assert_eq!(0, code_offsets[0]);
+  assert!(code_offsets.windows(2).all(|pair| pair[0] < pair[1]));
+}
+
+#[test]
+pub fn optimized_programs_have_no_source_map() {
+  let module = decode_module(&compile_with_debug_info(
+    "++[-]",
+    WasmOptions::new(WasmTarget::Bfcrs),
+    true,
+    false,
+  )).unwrap();
+
+  let source_info = module.source_info.unwrap();
+  assert_eq!(None, source_info.source);
+  assert!(source_info.source_map.is_empty());
+}
+
+#[test]
+pub fn debug_info_doesnt_change_behaviour() {
+  let source = "++++++++[>++++++++<-]>+.";
+  let code = compile_with_debug_info(source, WasmOptions::new(WasmTarget::Bfcrs), false, true);
+
+  let mut io = MockIo::new();
+  run_wasm(&code, &mut io).unwrap();
+  assert_eq!("A", io.output);
+}